@@ -0,0 +1,212 @@
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use bytesize::ByteSize;
+use log::debug;
+
+/// How many files are copied concurrently at the start of an adaptive run
+const INITIAL_CONCURRENCY: usize = 2;
+/// The upper bound of the concurrency if `--threads` does not set one
+pub const DEFAULT_MAX_CONCURRENCY: usize = 16;
+const MEASUREMENT_WINDOW: Duration = Duration::from_millis(500);
+/// Throughput changes smaller than this are treated as noise
+const TOLERANCE: f64 = 0.05;
+
+/// Gates how many files are copied at the same time and tunes that number while the copy is running.
+/// Every [MEASUREMENT_WINDOW] the throughput is compared to the previous window: as long as it improves the
+/// concurrency keeps moving in the same direction by one, if it gets worse the direction is reversed
+pub struct AdaptiveConcurrency {
+    max_concurrency: usize,
+    state: Mutex<State>,
+    state_changed: Condvar,
+}
+
+struct State {
+    limit: usize,
+    active: usize,
+    finished: bool,
+}
+
+/// Allows one copy until it is dropped
+pub struct Permit<'a> {
+    concurrency: &'a AdaptiveConcurrency,
+}
+
+impl AdaptiveConcurrency {
+    pub fn new(max_concurrency: usize) -> AdaptiveConcurrency {
+        let max_concurrency = max_concurrency.max(1);
+        let state = State { limit: INITIAL_CONCURRENCY.min(max_concurrency), active: 0, finished: false };
+        AdaptiveConcurrency { max_concurrency, state: Mutex::new(state), state_changed: Condvar::new() }
+    }
+
+    pub fn max_concurrency(&self) -> usize {
+        self.max_concurrency
+    }
+
+    /// Blocks until fewer copies than the current limit are running
+    pub fn acquire(&self) -> Permit<'_> {
+        let mut state = self.lock();
+        while state.active >= state.limit {
+            state = self.state_changed.wait(state).unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        state.active += 1;
+        Permit { concurrency: self }
+    }
+
+    /// Adjusts the limit based on `bytes_copied` until [AdaptiveConcurrency::finish] is called
+    pub fn monitor(&self, bytes_copied: &AtomicU64) {
+        let mut direction = Direction::Up;
+        let mut previous_throughput = None;
+        let mut previous_bytes = bytes_copied.load(Ordering::Relaxed);
+        let mut window_start = Instant::now();
+        let mut state = self.lock();
+        loop {
+            let (next_state, _) = self.state_changed.wait_timeout_while(state, MEASUREMENT_WINDOW, |state| !state.finished)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            state = next_state;
+            if state.finished {
+                return;
+            }
+            let bytes = bytes_copied.load(Ordering::Relaxed);
+            let throughput = (bytes - previous_bytes) as f64 / window_start.elapsed().as_secs_f64();
+            previous_bytes = bytes;
+            window_start = Instant::now();
+            if throughput == 0.0 {
+                continue;
+            }
+            let (limit, next_direction) = next_limit(state.limit, direction, previous_throughput, throughput, self.max_concurrency);
+            if limit != state.limit {
+                debug!("Copying {} files concurrently at {}/s", limit, ByteSize(throughput as u64));
+            }
+            state.limit = limit;
+            direction = next_direction;
+            previous_throughput = Some(throughput);
+            self.state_changed.notify_all();
+        }
+    }
+
+    /// Stops [AdaptiveConcurrency::monitor]
+    pub fn finish(&self) {
+        self.lock().finished = true;
+        self.state_changed.notify_all();
+    }
+
+    /// Calls [AdaptiveConcurrency::finish] once the returned guard is dropped, also when a copy panicked
+    pub fn finish_on_drop(&self) -> FinishOnDrop<'_> {
+        FinishOnDrop { concurrency: self }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+pub struct FinishOnDrop<'a> {
+    concurrency: &'a AdaptiveConcurrency,
+}
+
+impl Drop for FinishOnDrop<'_> {
+    fn drop(&mut self) {
+        self.concurrency.finish();
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.concurrency.lock().active -= 1;
+        self.concurrency.state_changed.notify_all();
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Direction {
+    Up,
+    Down,
+}
+
+impl Direction {
+    fn reversed(self) -> Direction {
+        match self {
+            Direction::Up => { Direction::Down }
+            Direction::Down => { Direction::Up }
+        }
+    }
+}
+
+/// One hill climbing step, reversing at the bounds so the limit keeps probing for a better throughput
+fn next_limit(limit: usize, direction: Direction, previous_throughput: Option<f64>, throughput: f64, max: usize) -> (usize, Direction) {
+    let got_worse = previous_throughput.is_some_and(|previous| throughput < previous * (1.0 - TOLERANCE));
+    let direction = if got_worse { direction.reversed() } else { direction };
+    let direction = match direction {
+        Direction::Up if limit >= max => { Direction::Down }
+        Direction::Down if limit <= 1 => { Direction::Up }
+        direction => { direction }
+    };
+    let limit = match direction {
+        Direction::Up => { limit + 1 }
+        Direction::Down => { limit - 1 }
+    };
+    (limit.clamp(1, max), direction)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{mpsc, Mutex};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use crate::adaptive::{AdaptiveConcurrency, Direction, next_limit};
+
+    #[test]
+    fn test_next_limit() {
+        assert_eq!(next_limit(2, Direction::Up, None, 100.0, 8), (3, Direction::Up));
+        assert_eq!(next_limit(3, Direction::Up, Some(100.0), 150.0, 8), (4, Direction::Up));
+        assert_eq!(next_limit(4, Direction::Up, Some(150.0), 100.0, 8), (3, Direction::Down));
+        assert_eq!(next_limit(3, Direction::Down, Some(100.0), 98.0, 8), (2, Direction::Down));
+        assert_eq!(next_limit(8, Direction::Up, Some(100.0), 120.0, 8), (7, Direction::Down));
+        assert_eq!(next_limit(1, Direction::Down, Some(100.0), 120.0, 8), (2, Direction::Up));
+        assert_eq!(next_limit(1, Direction::Up, None, 100.0, 1), (1, Direction::Down));
+    }
+
+    #[test]
+    fn test_acquire_respects_the_limit() {
+        let concurrency = AdaptiveConcurrency::new(8);
+        let (acquired_sender, acquired) = mpsc::channel();
+        let (release_sender, release) = mpsc::channel();
+        let release = Mutex::new(release);
+        std::thread::scope(|scope| {
+            for _ in 0..6 {
+                scope.spawn(|| {
+                    let _permit = concurrency.acquire();
+                    acquired_sender.send(()).unwrap();
+                    release.lock().unwrap().recv().unwrap();
+                });
+            }
+            acquired.recv().unwrap();
+            acquired.recv().unwrap();
+            // Every released permit lets exactly one of the waiting threads in
+            for _ in 0..4 {
+                assert_eq!(concurrency.lock().active, 2);
+                release_sender.send(()).unwrap();
+                acquired.recv().unwrap();
+            }
+            assert_eq!(concurrency.lock().active, 2);
+            for _ in 0..2 {
+                release_sender.send(()).unwrap();
+            }
+        });
+        assert_eq!(concurrency.lock().active, 0);
+    }
+
+    #[test]
+    fn test_finish_stops_the_monitor() {
+        let concurrency = AdaptiveConcurrency::new(4);
+        let bytes_copied = AtomicU64::new(0);
+        std::thread::scope(|scope| {
+            let monitor = scope.spawn(|| concurrency.monitor(&bytes_copied));
+            bytes_copied.store(1024, Ordering::Relaxed);
+            concurrency.finish();
+            monitor.join().unwrap();
+        });
+    }
+}
@@ -0,0 +1,370 @@
+use std::fs::{File, Metadata};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::JoinHandle;
+
+use chrono::{DateTime, Local};
+use log::warn;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Files up to this size are read by the copying threads themselves, larger ones are streamed by the archive writer
+/// so they do not have to fit into memory
+const MAX_BUFFERED_SIZE: u64 = 8 * 1024 * 1024;
+
+/// The archive formats `--archive` can write, chosen by the extension of its path
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// The format belonging to the extension of `path`, `None` if it is neither `.tar` nor `.zip`
+    pub fn of(path: &Path) -> Option<ArchiveFormat> {
+        let extension = path.extension()?.to_string_lossy().to_lowercase();
+        match extension.as_str() {
+            "tar" => { Some(ArchiveFormat::Tar) }
+            "zip" => { Some(ArchiveFormat::Zip) }
+            _ => { None }
+        }
+    }
+}
+
+enum Contents {
+    Directory,
+    Buffered(Vec<u8>),
+    Streamed(File),
+}
+
+struct ArchiveEntry {
+    name: String,
+    metadata: Metadata,
+    contents: Contents,
+    written: Sender<std::io::Result<u64>>,
+}
+
+/// An archive that is written by a single background thread, as neither tar nor zip writers can be shared.
+/// The copying threads read the files in parallel and hand them over through a channel. Until [Archive::finish]
+/// the archive only exists as a sibling `<name>.part`, so `path` never contains a truncated archive
+pub struct Archive {
+    path: PathBuf,
+    part_path: PathBuf,
+    entries: Sender<ArchiveEntry>,
+    writer: JoinHandle<std::io::Result<()>>,
+}
+
+impl Archive {
+    /// Creates or truncates the archive at `path` in the [ArchiveFormat] of its extension
+    pub fn create(path: &Path) -> std::io::Result<Archive> {
+        let format = ArchiveFormat::of(path).ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput,
+            format!("{} is neither a .tar nor a .zip file", path.to_string_lossy())))?;
+        let mut part_path = path.as_os_str().to_os_string();
+        part_path.push(".part");
+        let part_path = PathBuf::from(part_path);
+        let file = BufWriter::new(File::create(&part_path)?);
+        let (entries, received) = channel();
+        let writer = match format {
+            ArchiveFormat::Tar => { std::thread::spawn(move || write_tar(file, received)) }
+            ArchiveFormat::Zip => { std::thread::spawn(move || write_zip(file, received)) }
+        };
+        Ok(Archive { path: path.to_path_buf(), part_path, entries, writer })
+    }
+
+    /// The file the archive is written to until it is finished
+    pub fn part_path(&self) -> &Path {
+        &self.part_path
+    }
+
+    /// Adds the file or directory at `source_path` as `relative_path`, which uses `/` as separator within the
+    /// archive. Blocks until it was written and returns the number of bytes added
+    pub fn add(&self, source_path: &Path, relative_path: &Path) -> std::io::Result<u64> {
+        let name = archive_name(relative_path)?;
+        let metadata = std::fs::metadata(source_path)?;
+        let contents = if metadata.is_dir() {
+            Contents::Directory
+        } else if metadata.len() <= MAX_BUFFERED_SIZE {
+            let mut buffer = Vec::with_capacity(metadata.len() as usize);
+            File::open(source_path)?.read_to_end(&mut buffer)?;
+            Contents::Buffered(buffer)
+        } else {
+            Contents::Streamed(File::open(source_path)?)
+        };
+        let (written, result) = channel();
+        self.entries.send(ArchiveEntry { name, metadata, contents, written })
+            .map_err(|_| std::io::Error::other("the archive writer stopped"))?;
+        result.recv().map_err(|_| std::io::Error::other("the archive writer stopped"))?
+    }
+
+    /// Waits for all added entries to be written, completes the archive and renames it into place. If the
+    /// archive could not be written completely the part file is deleted instead
+    pub fn finish(self) -> std::io::Result<()> {
+        drop(self.entries);
+        let written = self.writer.join().map_err(|_| std::io::Error::other("the archive writer panicked"))
+            .and_then(|written| written);
+        if let Err(err) = written {
+            if let Err(remove_err) = std::fs::remove_file(&self.part_path) {
+                warn!("Could not delete the incomplete archive {} due to {}", self.part_path.to_string_lossy(), remove_err);
+            }
+            return Err(err);
+        }
+        std::fs::rename(&self.part_path, &self.path)
+    }
+}
+
+/// Joins the components of `relative_path` with `/`, rejecting everything that would point outside the archive
+fn archive_name(relative_path: &Path) -> std::io::Result<String> {
+    let mut names = Vec::new();
+    for component in relative_path.components() {
+        match component {
+            Component::Normal(name) => { names.push(name.to_string_lossy()) }
+            Component::CurDir => {}
+            _ => {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                                               format!("{} is no relative path within the archive", relative_path.to_string_lossy())));
+            }
+        }
+    }
+    if names.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "the path within the archive is empty"));
+    }
+    Ok(names.join("/"))
+}
+
+/// Writes the entries to a tar stream. A streamed file that shrank below the size its header announced fails its
+/// entry, which is cut off the stream again so the following entries are written where it started. Should an
+/// entry fail in any other way, or cutting it off fail, tar has no index to leave it out of and the whole
+/// archive fails
+fn write_tar(file: BufWriter<File>, entries: Receiver<ArchiveEntry>) -> std::io::Result<()> {
+    let mut builder = tar::Builder::new(file);
+    let mut broken: Option<std::io::Error> = None;
+    for entry in entries {
+        if let Some(err) = &broken {
+            let _ = entry.written.send(Err(std::io::Error::new(err.kind(), format!("the archive is broken: {}", err))));
+            continue;
+        }
+        let mut header = tar::Header::new_gnu();
+        header.set_metadata(&entry.metadata);
+        let result = match entry.contents {
+            Contents::Directory => {
+                builder.append_data(&mut header, format!("{}/", entry.name), std::io::empty()).map(|_| 0)
+            }
+            Contents::Buffered(buffer) => {
+                header.set_size(buffer.len() as u64);
+                builder.append_data(&mut header, &entry.name, buffer.as_slice()).map(|_| buffer.len() as u64)
+            }
+            Contents::Streamed(file) => {
+                // The header announces the size up front, so a file growing meanwhile must not add more bytes
+                let size = entry.metadata.len();
+                header.set_size(size);
+                match builder.get_mut().stream_position() {
+                    Ok(start) => {
+                        let result = builder.append_data(&mut header, &entry.name, ExactSize::new(file, size)).map(|_| size);
+                        match result {
+                            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                                match truncate_to(builder.get_mut(), start) {
+                                    Ok(_) => {
+                                        let _ = entry.written.send(Err(err));
+                                        continue;
+                                    }
+                                    Err(truncate_err) => { Err(truncate_err) }
+                                }
+                            }
+                            result => { result }
+                        }
+                    }
+                    Err(err) => { Err(err) }
+                }
+            }
+        };
+        if let Err(err) = &result {
+            broken = Some(std::io::Error::new(err.kind(), format!("{} could not be added: {}", entry.name, err)));
+        }
+        let _ = entry.written.send(result);
+    }
+    if let Some(err) = broken {
+        return Err(err);
+    }
+    builder.into_inner()?.flush()
+}
+
+/// Cuts `file` off at `position`, dropping everything written after it
+fn truncate_to(file: &mut BufWriter<File>, position: u64) -> std::io::Result<()> {
+    file.seek(SeekFrom::Start(position))?;
+    file.get_ref().set_len(position)
+}
+
+/// Reads exactly the size the file had when it was added, a file that ends early fails with
+/// [std::io::ErrorKind::UnexpectedEof] instead of adding a truncated entry
+struct ExactSize {
+    file: std::io::Take<File>,
+    remaining: u64,
+}
+
+impl ExactSize {
+    fn new(file: File, size: u64) -> ExactSize {
+        ExactSize { file: file.take(size), remaining: size }
+    }
+}
+
+impl Read for ExactSize {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.file.read(buffer)?;
+        if read == 0 && self.remaining > 0 && !buffer.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof,
+                                           format!("the file shrank by {} bytes while it was archived", self.remaining)));
+        }
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+/// Writes the entries to a zip archive. A streamed file that shrank while it was read fails its entry like in
+/// [write_tar], which is removed from the archive again
+fn write_zip(file: BufWriter<File>, entries: Receiver<ArchiveEntry>) -> std::io::Result<()> {
+    let mut writer = ZipWriter::new(file);
+    for entry in entries {
+        let options = zip_options(&entry.metadata);
+        let result = match entry.contents {
+            Contents::Directory => { writer.add_directory(&entry.name, options).map(|_| 0).map_err(Into::into) }
+            Contents::Buffered(buffer) => {
+                writer.start_file(&entry.name, options).map_err(Into::into)
+                    .and_then(|_| writer.write_all(&buffer))
+                    .map(|_| buffer.len() as u64)
+            }
+            Contents::Streamed(file) => {
+                let size = entry.metadata.len();
+                match writer.start_file(&entry.name, options) {
+                    Ok(_) => {
+                        let copied = std::io::copy(&mut ExactSize::new(file, size), &mut writer);
+                        if copied.is_err() {
+                            if let Err(err) = writer.abort_file() {
+                                let _ = entry.written.send(copied);
+                                return Err(err.into());
+                            }
+                        }
+                        copied
+                    }
+                    Err(err) => { Err(err.into()) }
+                }
+            }
+        };
+        let _ = entry.written.send(result);
+    }
+    writer.finish()?.flush()
+}
+
+fn zip_options(metadata: &Metadata) -> SimpleFileOptions {
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .large_file(metadata.len() > u32::MAX as u64);
+    // Zip timestamps are local times between 1980 and 2107, everything else keeps the default
+    let options = match metadata.modified().map(|modified| DateTime::<Local>::from(modified).naive_local()) {
+        Ok(modified) => {
+            match zip::DateTime::try_from(modified) {
+                Ok(modified) => { options.last_modified_time(modified) }
+                Err(_) => { options }
+            }
+        }
+        Err(_) => { options }
+    };
+    unix_permissions(options, metadata)
+}
+
+#[cfg(unix)]
+fn unix_permissions(options: SimpleFileOptions, metadata: &Metadata) -> SimpleFileOptions {
+    use std::os::unix::fs::PermissionsExt;
+    options.unix_permissions(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn unix_permissions(options: SimpleFileOptions, _metadata: &Metadata) -> SimpleFileOptions {
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{BufWriter, ErrorKind, Read};
+    use std::path::Path;
+    use std::sync::mpsc::channel;
+
+    use crate::archive::{Archive, archive_name, ArchiveEntry, ArchiveFormat, Contents, write_tar, write_zip};
+
+    #[test]
+    fn test_archive_format() {
+        assert_eq!(ArchiveFormat::of(Path::new("backup.tar")), Some(ArchiveFormat::Tar));
+        assert_eq!(ArchiveFormat::of(Path::new("backup.ZIP")), Some(ArchiveFormat::Zip));
+        assert_eq!(ArchiveFormat::of(Path::new("backup.tar.gz")), None);
+        assert_eq!(ArchiveFormat::of(Path::new("backup")), None);
+    }
+
+    #[test]
+    fn test_archive_name() {
+        assert_eq!(archive_name(&Path::new("a").join("b").join("c.txt")).unwrap(), "a/b/c.txt");
+        assert!(archive_name(Path::new("")).is_err());
+        assert!(archive_name(&Path::new("..").join("escape.txt")).is_err());
+    }
+
+    #[test]
+    fn test_streamed_files_that_shrink_fail_only_their_entry() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        for format in [ArchiveFormat::Tar, ArchiveFormat::Zip] {
+            let path = temp_dir.path().join("shrinking.bin");
+            std::fs::write(&path, vec![1u8; 1000]).unwrap();
+            let metadata = std::fs::metadata(&path).unwrap();
+            let file = File::open(&path).unwrap();
+            std::fs::write(&path, vec![1u8; 10]).unwrap();
+            let (entries, received) = channel();
+            let (written, shrunk) = channel();
+            entries.send(ArchiveEntry { name: "shrinking.bin".to_string(), metadata: metadata.clone(), contents: Contents::Streamed(file), written }).unwrap();
+            let (written, later) = channel();
+            entries.send(ArchiveEntry { name: "later.bin".to_string(), metadata, contents: Contents::Buffered(vec![2u8; 1000]), written }).unwrap();
+            drop(entries);
+            let archive_path = temp_dir.path().join(format!("archive.{:?}", format));
+            let archive = BufWriter::new(File::create(&archive_path).unwrap());
+            match format {
+                ArchiveFormat::Tar => { write_tar(archive, received).unwrap() }
+                ArchiveFormat::Zip => { write_zip(archive, received).unwrap() }
+            }
+            assert_eq!(shrunk.recv().unwrap().unwrap_err().kind(), ErrorKind::UnexpectedEof);
+            assert_eq!(later.recv().unwrap().unwrap(), 1000);
+            let names: Vec<String> = match format {
+                ArchiveFormat::Tar => {
+                    let mut archive = tar::Archive::new(File::open(&archive_path).unwrap());
+                    archive.entries().unwrap().map(|entry| {
+                        let mut entry = entry.unwrap();
+                        let mut content = Vec::new();
+                        entry.read_to_end(&mut content).unwrap();
+                        assert_eq!(content, vec![2u8; 1000]);
+                        entry.path().unwrap().to_string_lossy().to_string()
+                    }).collect()
+                }
+                ArchiveFormat::Zip => {
+                    let mut archive = zip::ZipArchive::new(File::open(&archive_path).unwrap()).unwrap();
+                    let mut content = Vec::new();
+                    archive.by_name("later.bin").unwrap().read_to_end(&mut content).unwrap();
+                    assert_eq!(content, vec![2u8; 1000]);
+                    archive.file_names().map(|name| name.unwrap().to_string()).collect()
+                }
+            };
+            assert_eq!(names, vec!["later.bin"], "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_duplicate_names_in_zip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        std::fs::write(temp_dir.path().join("a.txt"), "a").unwrap();
+        let archive = Archive::create(&temp_dir.path().join("archive.zip")).unwrap();
+        archive.add(&temp_dir.path().join("a.txt"), Path::new("a.txt")).unwrap();
+        // Adding the same name twice is rejected by the zip writer, the archive stays usable
+        assert!(archive.add(&temp_dir.path().join("a.txt"), Path::new("a.txt")).is_err());
+        archive.finish().unwrap();
+        let mut zip = zip::ZipArchive::new(std::fs::File::open(temp_dir.path().join("archive.zip")).unwrap()).unwrap();
+        let mut content = String::new();
+        zip.by_name("a.txt").unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "a");
+    }
+}
@@ -1,10 +1,34 @@
-use std::collections::HashSet;
-use std::path::{MAIN_SEPARATOR, Path, PathBuf};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::io::ErrorKind;
+use std::ops::RangeInclusive;
+use std::path::{Component, MAIN_SEPARATOR, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
+use anyhow::{anyhow, bail, Context};
+use bytesize::ByteSize;
+use chrono::{DateTime, Utc};
 use clap::{Args, Parser, ValueEnum};
+use glob::Pattern;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use log::{LevelFilter, warn};
 use regex::Regex;
 
+use crate::archive::ArchiveFormat;
 use crate::args::TargetMode::{Ignore, Target};
+use crate::long_paths::long_path;
+use crate::progress::ProgressWriter;
+use crate::StopSignal;
+use crate::throttle::RateLimiter;
+
+mod builder;
+mod config;
+mod template;
+
+pub use builder::ArgumentsBuilder;
+pub use template::TargetTemplate;
 
 #[derive(ValueEnum, Clone, PartialOrd, PartialEq, Debug)]
 enum TargetMode {
@@ -12,203 +36,1644 @@ enum TargetMode {
     Target,
 }
 
-#[derive(Args, Clone)]
+/// What happens to a source file once it has been transferred to the target
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug, Default)]
+pub enum Mode {
+    /// Keep the source file untouched
+    #[default]
+    Copy,
+    /// Delete the source file after it was copied successfully
+    Move,
+    /// Create hardlinks instead of copies, falling back to a copy across devices. Hardlinks share their
+    /// content with the source, so editing one also edits the other
+    Hardlink,
+    /// Create symbolic links pointing at the absolute source path, e.g. to build a filtered view of an archive.
+    /// On Windows this requires the symlink privilege or the developer mode
+    Symlink,
+}
+
+/// How the configured folders are compared against a path
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug, Default)]
+pub enum FolderMatch {
+    /// A folder matches any single directory name along the path
+    #[default]
+    Segment,
+    /// A folder, which may span several directories like `src/gen`, matches the start of the path below the source root
+    Prefix,
+}
+
+/// Why the discovery did not pick an entry for copying
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum SkipReason {
+    ExcludeRegex,
+    ExcludeFrom,
+    Folder,
+    Name,
+    IncludeRegex,
+    Extension,
+    Hidden,
+    Empty,
+    Size,
+    Time,
+    /// Rejected by the predicate passed to [crate::gather_files_with]
+    Predicate,
+    /// The entry could not be read due to missing permissions
+    PermissionDenied,
+    /// A symbolic link left out by `--exclude-symlinks`
+    Symlink,
+    /// No symbolic link, left out by `--only-symlinks`
+    NotSymlink,
+}
+
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let label = match self {
+            SkipReason::ExcludeRegex => { "exclude-regex" }
+            SkipReason::ExcludeFrom => { "exclude-from" }
+            SkipReason::Folder => { "folder" }
+            SkipReason::Name => { "name" }
+            SkipReason::IncludeRegex => { "include-regex" }
+            SkipReason::Extension => { "ext" }
+            SkipReason::Hidden => { "hidden" }
+            SkipReason::Empty => { "empty" }
+            SkipReason::Size => { "size" }
+            SkipReason::Time => { "time" }
+            SkipReason::Predicate => { "filter" }
+            SkipReason::PermissionDenied => { "permission denied" }
+            SkipReason::Symlink => { "symlink" }
+            SkipReason::NotSymlink => { "not a symlink" }
+        };
+        write!(f, "{}", label)
+    }
+}
+
+/// Above how many files or bytes a copy has to be confirmed
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ConfirmThreshold {
+    files: Option<usize>,
+    bytes: Option<u64>,
+}
+
+impl Default for ConfirmThreshold {
+    fn default() -> Self {
+        ConfirmThreshold { files: Some(100_000), bytes: Some(ByteSize::gb(10).as_u64()) }
+    }
+}
+
+impl ConfirmThreshold {
+    /// A plain number is a file count, a number with a unit like `10G` a total size
+    fn parse(s: &str) -> Result<ConfirmThreshold, String> {
+        if let Ok(files) = s.parse::<usize>() {
+            return Ok(ConfirmThreshold { files: Some(files), bytes: None });
+        }
+        let bytes = s.parse::<ByteSize>()
+            .map_err(|_| format!("'{}' is neither a file count nor a size like 10G", s))?;
+        Ok(ConfirmThreshold { files: None, bytes: Some(bytes.as_u64()) })
+    }
+
+    pub fn is_exceeded(&self, files: usize, bytes: u64) -> bool {
+        self.files.is_some_and(|limit| files > limit) || self.bytes.is_some_and(|limit| bytes > limit)
+    }
+}
+
+/// Makes a fraction of the copies fail without touching the disk for `--simulate-error-rate`, meant for testing
+/// how failures are handled and never for production runs. Whether a file fails only depends on its path and the
+/// seed, so runs with the same seed fail the same files regardless of the order the workers copy them in
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ErrorSimulation {
+    rate: f64,
+    seed: u64,
+}
+
+impl ErrorSimulation {
+    pub fn new(rate: f64, seed: u64) -> ErrorSimulation {
+        ErrorSimulation { rate, seed }
+    }
+
+    pub fn fails(&self, path: &Path) -> bool {
+        let hash = xxhash_rust::xxh3::xxh3_64_with_seed(path.as_os_str().as_encoded_bytes(), self.seed);
+        (hash as f64 / u64::MAX as f64) < self.rate
+    }
+}
+
+/// What happens when two flattened or templated files, or files from different source roots, share the same target
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug, Default)]
+pub enum FlattenCollision {
+    /// Only the first of the files is copied
+    Skip,
+    /// Later files get a numeric suffix like `photo_1.jpg`
+    #[default]
+    Suffix,
+}
+
+/// The hash function of `--checksum-manifest`, each matching the format of its coreutils-style check tool
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug, Default)]
+pub enum ChecksumAlgorithm {
+    /// Verifiable with `sha256sum -c`
+    #[default]
+    Sha256,
+    /// Verifiable with `sha512sum -c`
+    Sha512,
+    /// The much faster but non-cryptographic XXH3-128, verifiable with `xxh128sum -c`
+    Xxh128,
+}
+
+/// The order in which gathered files are copied
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum Order {
+    /// Alphabetically by path
+    #[value(alias = "name")]
+    Path,
+    /// The smallest files first
+    #[value(alias = "size")]
+    SizeAsc,
+    /// The largest files first
+    SizeDesc,
+    /// The least recently modified files first
+    Mtime,
+}
+
+/// How log records are written to stderr
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug, Default)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per record with its timestamp, level, target and message, copy events also carry a `file`
+    Json,
+}
+
+/// What happens to the rest of a run once a file could not be copied
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug, Default)]
+pub enum OnError {
+    /// Copy the remaining files and report all failures at the end
+    #[default]
+    Continue,
+    /// Start no further copies, the running ones still finish
+    Abort,
+}
+
+/// Decides whether an already existing target file may be replaced
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug, Default)]
+pub enum OverwritePolicy {
+    /// Always replace the target
+    #[default]
+    Always,
+    /// Never replace an existing target
+    Never,
+    /// Only replace the target if the source was modified more recently
+    IfNewer,
+    /// Keep the existing target and copy to the first free `name (1).ext`, `name (2).ext`, ... instead
+    Rename,
+}
+
+impl OverwritePolicy {
+    pub fn allows_writing(&self, source_path: &Path, target_path: &Path) -> bool {
+        let target = match std::fs::metadata(target_path) {
+            Ok(target) => { target }
+            Err(_) => { return true; }
+        };
+        match self {
+            OverwritePolicy::Always | OverwritePolicy::Rename => { true }
+            OverwritePolicy::Never => { false }
+            OverwritePolicy::IfNewer => {
+                let source_modified = std::fs::metadata(source_path).and_then(|source| source.modified());
+                match (source_modified, target.modified()) {
+                    (Ok(source_modified), Ok(target_modified)) => { source_modified > target_modified }
+                    _ => { false }
+                }
+            }
+        }
+    }
+}
+
+/// How copied files are compressed, each compressed target gets the extension of its format appended
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug, Default)]
+pub enum Compression {
+    /// Copy the files as they are
+    #[default]
+    None,
+    /// Compress to `.gz` with levels 0 to 9, defaulting to 6
+    Gzip,
+    /// Compress to `.zst` with levels 1 to 22, defaulting to 3
+    Zstd,
+}
+
+impl Compression {
+    /// The extension appended to compressed targets, without the leading dot
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            Compression::None => { None }
+            Compression::Gzip => { Some("gz") }
+            Compression::Zstd => { Some("zst") }
+        }
+    }
+
+    /// The range of valid `--compression-level`s and the level used without one
+    fn levels(&self) -> (RangeInclusive<i32>, i32) {
+        match self {
+            Compression::None => { (0..=0, 0) }
+            Compression::Gzip => { (0..=9, 6) }
+            Compression::Zstd => { (1..=22, 3) }
+        }
+    }
+}
+
+/// When colored output should be used
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug, Default)]
+pub enum ColorChoice {
+    /// Use colors if stdout is a terminal supporting them and `NO_COLOR` is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Where the progress bar is drawn
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug, Default)]
+pub enum ProgressTarget {
+    /// Keeps stdout free for the output of e.g. `--list-only` or `--summary-json`
+    #[default]
+    Stderr,
+    Stdout,
+    /// Do not draw a progress bar at all
+    #[value(name = "none")]
+    Hidden,
+}
+
+#[derive(Args, Clone, Debug)]
 struct OptionalHandling {
     target: TargetMode,
     values: Vec<String>,
 }
 
 fn parse_special_options(s: &str) -> Result<OptionalHandling, &'static str> {
-    let splitter = Regex::new("\\s+").unwrap();
-    let result = splitter.split(s).collect::<Vec<&str>>();
+    let result = split_quoted(s)?;
     if result.len() < 2 {
         return Err("Not enough arguments supplied");
     }
-    let mode = match result[0] {
+    let mode = match result[0].as_str() {
         "Ignore" => { Ignore }
         "Target" => { Target }
         _ => { return Err("Invalid target mode specified"); }
     };
-    let extensions = result.into_iter().skip(1)
-        .map(|ext| ext.to_string())
-        .collect();
+    let extensions = result.into_iter().skip(1).collect();
     Ok(OptionalHandling {
         target: mode,
         values: extensions,
     })
 }
 
-#[derive(Parser, Clone)]
-#[clap(author = "Shahondin1624", about = "A simple cli-application for fast scraping of data from a system")]
+/// Parses a `--route` like `jpg=images` into the extension and its subfolder, which has to be a relative path
+/// staying below the target
+fn parse_route(s: &str) -> Result<(String, String), String> {
+    let Some((extension, subfolder)) = s.split_once('=') else {
+        return Err(format!("expected <EXT>=<SUBDIR>, got '{}'", s));
+    };
+    let (extension, subfolder) = (extension.trim(), subfolder.trim());
+    if extension.is_empty() || extension == "." || subfolder.is_empty() {
+        return Err(format!("expected <EXT>=<SUBDIR>, got '{}'", s));
+    }
+    if !Path::new(subfolder).components().all(|component| matches!(component, Component::Normal(_))) {
+        return Err(format!("the subfolder '{}' has to be a relative path below the target", subfolder));
+    }
+    Ok((extension.to_string(), subfolder.to_string()))
+}
+
+/// Splits `s` on whitespace, except within double quotes, so `Ignore "My Documents" temp` has three values.
+/// The quotes themselves are dropped
+fn split_quoted(s: &str) -> Result<Vec<String>, &'static str> {
+    let mut values = Vec::new();
+    let mut current: Option<String> = None;
+    let mut in_quotes = false;
+    for c in s.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                current.get_or_insert_with(String::new);
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                values.extend(current.take());
+            }
+            c => { current.get_or_insert_with(String::new).push(c); }
+        }
+    }
+    if in_quotes {
+        return Err("Unterminated quote");
+    }
+    values.extend(current);
+    Ok(values.into_iter().filter(|value| !value.is_empty()).collect())
+}
+
+#[derive(Parser, Clone, Default)]
+#[clap(author = "Shahondin1624", about = "A simple cli-application for fast scraping of data from a system",
+after_help = "Pressing Ctrl-C once lets the running copies finish without starting new ones, pressing it again \
+aborts them and deletes their partially written targets.")]
 pub struct CliArgs {
     ///The root folder from which all data should be scraped recursively
-    source_root_file_path: String,
+    #[arg(required_unless_present = "config")]
+    source_root_file_path: Option<String>,
     ///The target root folder to which all data should be copied to
-    target_root_file_path: String,
-    // ///File extensions that should be either ignored or copied specifically
-    #[arg(long, value_parser = parse_special_options)]
-    file_extensions: Option<OptionalHandling>,
-    // ///Folders that should be either ignored or copied specifically
+    #[arg(required_unless_present_any = ["config", "archive"])]
+    target_root_file_path: Option<String>,
+    ///Another source root whose content is merged into the same target, can be passed multiple times. Each file
+    ///keeps its path relative to the root it was found in, the roots must not be nested in one another
+    #[arg(long = "source", value_name = "SOURCE")]
+    additional_sources: Vec<String>,
+    ///What happens when files of different source roots have the same relative path. Which one keeps the
+    ///original name depends on the copy order
+    #[arg(long, value_enum, default_value = "suffix")]
+    source_collisions: FlattenCollision,
+    ///A TOML file providing the paths and filters, explicitly passed options take precedence
+    #[arg(long)]
+    config: Option<PathBuf>,
+    ///File extensions that should be either ignored or copied specifically, can be passed multiple times.
+    ///They match the end of the file name, so both `.tar.gz` and `.gz` match `archive.tar.gz`.
+    ///The deprecated form `"<Ignore|Target> <extensions...>"` is still accepted for now
+    #[arg(long)]
+    file_extensions: Vec<String>,
+    ///Whether the `--file-extensions` are ignored or the only ones copied, defaults to target
+    #[arg(long, value_enum)]
+    file_extensions_mode: Option<TargetMode>,
+    ///File extensions that are never copied, even if they are targeted by `--file-extensions`, can be passed
+    ///multiple times. As they also match the end of the file name, `--file-extensions jpg` with
+    ///`--exclude-extensions thumb.jpg` copies all JPEGs except the thumbnails
+    #[arg(long)]
+    exclude_extensions: Vec<String>,
+    ///Whether file extensions should be matched case-sensitively, by default `.JPG` matches `.jpg`
+    #[arg(long)]
+    case_sensitive_extensions: bool,
+    ///Folders that should be either ignored or copied specifically, as `"<Ignore|Target> <folders...>"`.
+    ///Wrap folder names containing spaces in double quotes, like `Ignore "My Documents" temp`
     #[arg(long, value_parser = parse_special_options)]
     folders: Option<OptionalHandling>,
+    ///Whether `--folders` match any directory along the path or only the leading directories below the
+    ///source root, so `prefix` with `build` means the top-level `build` only and `src/gen` is possible.
+    ///Both filter files by the directories containing them
+    #[arg(long, value_enum, default_value = "segment")]
+    folders_match: FolderMatch,
+    ///Match `--folders` regardless of case, so `Temp` also matches `temp` and `TEMP` like on Windows and macOS
+    #[arg(long)]
+    case_insensitive_folders: bool,
+    ///Paths matching this regex are not copied, can be passed multiple times
+    #[arg(long)]
+    exclude_regex: Vec<String>,
+    ///Paths matching the gitignore-style patterns in this file are not copied, including negations with `!` and
+    ///directory-only patterns ending in `/`. Patterns are relative to the source root
+    #[arg(long)]
+    exclude_from: Option<PathBuf>,
+    ///Only files whose path matches at least one of these regexes are copied, can be passed multiple times.
+    ///Excludes win over includes
+    #[arg(long)]
+    include_regex: Vec<String>,
+    ///Files with exactly this name, like `Makefile`, are copied even if the extension filter rejects them,
+    ///can be passed multiple times
+    #[arg(long)]
+    include_name: Vec<String>,
+    ///Files with exactly this name, like `.DS_Store`, are never copied, can be passed multiple times.
+    ///Excluded names win over included ones
+    #[arg(long)]
+    exclude_name: Vec<String>,
+    ///Copy the newline-separated paths listed in this file, or stdin for `-`, instead of walking the source root.
+    ///The listed paths have to be located below the source root
+    #[arg(long)]
+    from_list: Option<PathBuf>,
+    ///Retry the files that failed in an earlier run, read from the `--error-log` it wrote or stdin for `-`. Works
+    ///like `--from-list`, but every line ends at its first tab as the error follows there
+    #[arg(long, conflicts_with = "from_list")]
+    from_error_log: Option<PathBuf>,
+    ///Read directories in parallel while discovering files, which pays off on multi-core machines with high
+    ///latency storage like network shares. Every entry is stat-ed twice though, so on local disks the
+    ///sequential walk may well be faster
+    #[arg(long)]
+    parallel_walk: bool,
+    ///Skip everything matched by `.gitignore` and `.ignore` files as well as git's global and repository excludes,
+    ///also outside of git repositories. The other filters still apply on top. With `--follow-links` the ignore
+    ///files inside linked directories are honored too. Hidden files are only skipped with `--exclude-hidden`
+    #[arg(long, conflicts_with_all = ["parallel_walk", "follow_links_depth_limit"])]
+    respect_gitignore: bool,
+    ///Unix only: do not descend into directories on another filesystem than their source root, like `/proc` or
+    ///network mounts when scraping `/`
+    #[arg(long, alias = "exclude-devices")]
+    one_filesystem: bool,
     ///Whether links should be followed or ignored
-    #[arg(short, long, default_value = "false")]
+    #[arg(short, long, default_value = "false", overrides_with = "no_follow_links")]
     follow_links: bool,
-    ///Whether the logging should be verbose or not
+    ///Do not follow links even if the `--config` file enables `follow_links`
+    #[arg(long, overrides_with = "follow_links")]
+    no_follow_links: bool,
+    ///Recreate symbolic links as links at the target instead of copying their content. The link targets are
+    ///kept as they are, so relative links point into the copied tree while absolute ones keep pointing at their
+    ///original location. Moved links are removed from the source afterwards
+    #[arg(long, conflicts_with_all = ["follow_links", "archive", "compress"])]
+    copy_links_as_links: bool,
+    ///How many symlinks may be followed in a row to reach an entry, unlimited by default
+    #[arg(long, requires = "follow_links")]
+    follow_links_depth_limit: Option<usize>,
+    ///How many levels below the source root should be descended into, 0 only yields the root itself.
+    ///When following links, the depth is counted along the followed path, not the link target's location
+    #[arg(long)]
+    max_depth: Option<usize>,
+    ///How many levels below the source root have to be reached before entries are yielded, 1 skips the root itself
+    #[arg(long)]
+    min_depth: Option<usize>,
+    ///Files smaller than this are not copied, accepts suffixes like `500K` or `10M`
+    #[arg(long)]
+    min_size: Option<ByteSize>,
+    ///Files larger than this are not copied, accepts suffixes like `500K` or `10M`
+    #[arg(long)]
+    max_size: Option<ByteSize>,
+    ///Files with a size of 0 bytes are not copied
+    #[arg(long)]
+    exclude_empty_files: bool,
+    ///Stop the discovery once this many files passed the filters, e.g. to try out filters on a huge tree. These are
+    ///the first files in walk order, which follows the directory order of the filesystem and may differ between
+    ///machines, and with `--parallel-walk` between runs. The parallel walk also still reads the whole tree
+    #[arg(long, conflicts_with_all = ["from_list", "from_error_log"])]
+    max_files: Option<usize>,
+    ///Symbolic links are not copied, only regular files. With `--follow-links` the directories they point to are
+    ///still walked, only the link entries themselves are left out
+    #[arg(long)]
+    exclude_symlinks: bool,
+    ///Only symbolic links are copied, e.g. to audit which links a tree contains with `--list-only`
+    #[arg(long, conflicts_with = "exclude_symlinks")]
+    only_symlinks: bool,
+    ///Stop starting new copies once the next file would push the copied total above this size, accepts suffixes
+    ///like `500M` or `16G`. Combine it with `--order` to decide which files get the budget first
+    #[arg(long)]
+    max_total_size: Option<ByteSize>,
+    ///Copy the files in this order, which requires gathering all of them before the first copy starts.
+    ///As files are copied in parallel the order is only a hint for scheduling, `--threads 1` makes it strict
+    #[arg(long, value_enum)]
+    order: Option<Order>,
+    ///Skip dotfiles and hidden directories including everything inside them, on Windows also entries with the
+    ///hidden attribute
+    #[arg(long)]
+    exclude_hidden: bool,
+    ///Copy every file directly into the target root instead of mirroring the source's directories
+    #[arg(long)]
+    flatten: bool,
+    ///What happens when files flattened or laid out by `--target-template` share a target. Which one keeps the
+    ///original name depends on the copy order
+    #[arg(long, value_enum, default_value = "suffix")]
+    flatten_collisions: FlattenCollision,
+    ///Lay out the target with this template instead of mirroring the source, e.g. `{year}/{ext}/{name}`.
+    ///Available placeholders are `{name}`, `{stem}`, `{ext}`, `{dir}` as well as `{year}`, `{month}` and `{day}`
+    ///of the last modification. Files a placeholder other than `{dir}` expands to nothing for, like `{ext}` of
+    ///`README`, fail. Files sharing a target are handled by `--flatten-collisions`
+    #[arg(long, value_parser = TargetTemplate::parse, conflicts_with = "flatten")]
+    target_template: Option<TargetTemplate>,
+    ///Put files with this extension into a subfolder of the target, like `jpg=images`, can be passed multiple
+    ///times. The longest matching extension wins, files without a route keep their usual target
+    #[arg(long, value_name = "EXT=SUBDIR", value_parser = parse_route, conflicts_with = "target_template")]
+    route: Vec<(String, String)>,
+    ///Recreate the source's directories at the target, even those without any copied file inside
+    #[arg(long)]
+    preserve_empty_dirs: bool,
+    ///Only copy files modified at or after this point in time, either an RFC3339 timestamp like
+    ///`2024-01-31T12:00:00Z` or a duration before now like `30m`, `12h`, `7d` or `2w`
+    #[arg(long, value_parser = parse_point_in_time)]
+    newer_than: Option<SystemTime>,
+    ///Only copy files modified before this point in time, e.g. `90d` for files untouched for 90 days.
+    ///Accepts the same formats as `--newer-than`
+    #[arg(long, value_parser = parse_point_in_time)]
+    older_than: Option<SystemTime>,
+    ///Only log what would be copied where, without touching the disk
+    #[arg(long)]
+    dry_run: bool,
+    ///Run this shell command once the copy finished, also if it failed or was interrupted. Its outcome is passed
+    ///in `FILESCRAPER_COPIED`, `FILESCRAPER_FAILED`, `FILESCRAPER_SKIPPED`, `FILESCRAPER_BYTES`,
+    ///`FILESCRAPER_DURATION_MS` and `FILESCRAPER_INTERRUPTED`. A failing command makes filescraper fail too
+    #[arg(long)]
+    post_command: Option<String>,
+    ///Print the source path of every matched file, one per line, and exit without copying
+    #[arg(long, conflicts_with = "dry_run")]
+    list_only: bool,
+    ///Print the matched files as a tree below the source root and exit without copying
+    #[arg(long, conflicts_with_all = ["dry_run", "list_only"])]
+    preview_tree: bool,
+    ///Print how many files match and their total size, e.g. `3 files, 1.2 MB`, and exit without copying
+    #[arg(long, conflicts_with_all = ["dry_run", "list_only", "preview_tree"])]
+    count_only: bool,
+    ///Compare every matched file to its target and print how many are new, changed or the same by size and
+    ///modification time, then exit without copying. Targets copied without `--preserve-timestamps` count as changed
+    #[arg(long, conflicts_with_all = ["dry_run", "list_only", "preview_tree", "count_only"])]
+    diff: bool,
+    ///Also print the status of every file compared by `--diff`
+    #[arg(long, requires = "diff")]
+    diff_files: bool,
+    ///Whether files should be copied or moved to the target
+    #[arg(long, value_enum, default_value = "copy")]
+    mode: Mode,
+    ///After copying, delete everything at the target whose source no longer exists, as long as the filters
+    ///would copy it, so the target mirrors the source. Entries the walk would not visit, like hidden ones with
+    ///`--exclude-hidden` or those beyond the depth limits, are kept. Asks for confirmation unless `--yes` is set
+    #[arg(long, conflicts_with_all = ["flatten", "target_template", "route"])]
+    delete_extraneous: bool,
+    ///Skip files that already exist at the target with the same size and modification time
+    #[arg(long)]
+    skip_existing: bool,
+    ///What to do when a file already exists at the target
+    #[arg(long, value_enum, default_value = "always")]
+    overwrite: OverwritePolicy,
+    ///Whether copied files should keep the access and modification times of their source
+    #[arg(long)]
+    preserve_timestamps: bool,
+    ///Explicitly copy the permission bits of copied and moved files, on Unix also their owner and group when running
+    ///as root. On Windows only the readonly attribute is preserved
+    #[arg(long)]
+    preserve_permissions: bool,
+    ///Copy the extended attributes of copied and moved files, like macOS tags or SELinux contexts. Only supported
+    ///on Unix, filesystems without extended attributes are skipped silently
+    #[arg(long)]
+    preserve_xattrs: bool,
+    ///Set the permission bits of every copied or moved file to this octal mode like 644, after any preserved
+    ///permissions were applied. Only supported on Unix
+    #[arg(long, value_name = "OCTAL", value_parser = parse_mode)]
+    chmod: Option<u32>,
+    ///Set the permission bits of every directory created at the target to this octal mode like 755. The mode is
+    ///applied when the directory is created, so it has to keep it writable and searchable by its owner. Only
+    ///supported on Unix
+    #[arg(long, value_name = "OCTAL", value_parser = parse_mode)]
+    chmod_dirs: Option<u32>,
+    ///Compare checksums of source and target after each copy, this doubles the read I/O.
+    ///Moved files are not verified as their source is gone afterwards, neither are hardlinks and symlinks as
+    ///they point at the source's content. Copies made when a hardlink can not cross devices are verified
+    #[arg(long)]
+    verify: bool,
+    ///Delete targets whose checksum does not match their source
+    #[arg(long, requires = "verify")]
+    verify_delete_on_mismatch: bool,
+    ///How often a copy failing with a transient error like a timeout is retried, waiting 100ms, 200ms, 400ms, ...
+    ///in between. Useful for flaky network mounts
+    #[arg(long, default_value_t = 0)]
+    retries: u32,
+    ///Whether the remaining files are still copied after a copy failed, or whether the run stops with an error
+    #[arg(long, value_enum, default_value = "continue")]
+    on_error: OnError,
+    ///Keep running after the copy and also copy files that are created or modified in the source afterwards,
+    ///once they did not change for half a second. Stops on Ctrl-C. Every batch of changes is copied like a
+    ///run of its own, so this can not be combined with the logs, manifests and size budget covering a whole run
+    #[arg(long, conflicts_with_all = ["from_list", "from_error_log", "dry_run", "error_log", "manifest",
+        "checksum_manifest", "max_total_size"])]
+    watch: bool,
+    ///Copy into this .tar or .zip archive instead of a target root, keeping the structure below the source root.
+    ///Entries keep the modification time and on Unix the permissions of their source
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["watch", "delete_extraneous", "resume", "skip_existing",
+        "verify", "dedup_link", "checksum_manifest"])]
+    archive: Option<PathBuf>,
+    ///Compress every copied file, appending `.gz` or `.zst` to its target. Compressed targets never match their
+    ///source, so this can not be combined with `--archive`, `--skip-existing`, `--verify` or `--delete-extraneous`
+    #[arg(long, value_enum, default_value = "none")]
+    compress: Compression,
+    ///The level of `--compress`, higher levels compress better but slower
+    #[arg(long, requires = "compress")]
+    compression_level: Option<i32>,
+    ///Count files and directories that can not be read due to missing permissions as skipped instead of failed
+    ///and only mention them in the debug log, e.g. when scraping system directories as a regular user
+    #[arg(long)]
+    skip_permission_errors: bool,
+    ///Copy byte-identical files only once, later duplicates are skipped
+    #[arg(long)]
+    dedup: bool,
+    ///Hardlink duplicates to the first copy instead of skipping them
+    #[arg(long, requires = "dedup")]
+    dedup_link: bool,
+    ///How many files should be copied in parallel, 0 uses one worker per CPU.
+    ///On spinning disks 1 or 2 is often fastest as parallel access makes the drive head thrash
+    #[arg(long)]
+    threads: Option<usize>,
+    ///Experimental: start with 2 concurrent copies and keep adjusting their number to the one with the best
+    ///throughput, up to `--threads` or 16
+    #[arg(long)]
+    adaptive_threads: bool,
+    ///Keep at most this many files open for copying at the same time, each copy holds its source and target.
+    ///Bounds the file descriptors regardless of `--threads`, e.g. on systems with a low `ulimit -n`
+    #[arg(long)]
+    max_open_files: Option<usize>,
+    ///Limit the combined throughput of all workers to this many bytes per second, accepts suffixes like `500K`
+    ///or `10M`. The rate is approximate, short bursts above it are possible
+    #[arg(long)]
+    max_rate: Option<ByteSize>,
+    ///Copy files of 16MiB or more through an explicit buffer of this size instead of the platform's copy, e.g.
+    ///`8MiB`. Can improve the throughput on some disks, but on Linux the platform's copy is often faster, so
+    ///measure before relying on it
+    #[arg(long)]
+    copy_buffer_size: Option<ByteSize>,
+    ///Let the progress bar count bytes instead of files, which is more accurate when file sizes vary a lot
+    #[arg(long)]
+    progress_by_bytes: bool,
+    ///Where the progress bar is drawn, `--quiet` hides it regardless. `stdout` can not be combined with
+    ///`--summary-json` or `--list-only`, whose output on stdout a progress bar there would garble
+    #[arg(long, value_enum, default_value = "stderr")]
+    progress_to: ProgressTarget,
+    ///Unix only: write the progress as newline-delimited JSON to this open file descriptor, at most every 100ms and
+    ///once more when done, like `{"current":12,"total":40,"bytes":123456,"done":false}`. `current` counts the
+    ///processed entries, `total` is `null` until all of them are discovered and `bytes` counts the copied bytes.
+    ///The progress bar is still drawn unless `--quiet` is set
+    #[arg(long, value_name = "FD")]
+    progress_fd: Option<i32>,
+    ///Write every source path that failed to copy to this file, each followed by a tab and the error. Pass it
+    ///to `--from-error-log` to retry them
+    #[arg(long)]
+    error_log: Option<PathBuf>,
+    ///Write a JSON record of every copied file to this path
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+    ///Write the checksum of every file copied by this run to this path, as `<hash>  <path>` lines with paths
+    ///relative to the target root, so the target can be verified later with e.g. `sha256sum -c`
+    #[arg(long)]
+    checksum_manifest: Option<PathBuf>,
+    ///The hash function of the `--checksum-manifest`
+    #[arg(long, value_enum, default_value = "sha256", requires = "checksum_manifest")]
+    checksum_algo: ChecksumAlgorithm,
+    ///Skip every file whose checksum matches the one an earlier run's `--checksum-manifest` lists for its path.
+    ///Unlike `--skip-existing`, which only compares the size and modification time with the target, this also
+    ///notices changes that keep both, but every listed source file has to be read once more to hash it.
+    ///As the sources are compared with their checksums this can not be combined with `--compress`
+    #[arg(long, value_name = "PATH")]
+    since_manifest: Option<PathBuf>,
+    ///Record every completed copy in this state file and skip the files it already lists, so an interrupted run
+    ///can be continued without checking every target. It is created if it does not exist
+    #[arg(long)]
+    resume: Option<PathBuf>,
+    ///Print the final summary as a single JSON object on stdout instead of the human readable line,
+    ///even with `--quiet`
+    #[arg(long)]
+    summary_json: bool,
+    ///Testing only, never use it for real copies: fail this fraction between 0.0 and 1.0 of the copies with a
+    ///simulated error instead of copying them, to try out how failures are reported
+    #[arg(long, hide = true)]
+    simulate_error_rate: Option<f64>,
+    ///Testing only: the seed choosing which files `--simulate-error-rate` fails, random if not set
+    #[arg(long, hide = true, requires = "simulate_error_rate")]
+    simulate_seed: Option<u64>,
+    ///Print how many files and bytes were copied per extension after the summary, for the largest ones
+    #[arg(long)]
+    stats: bool,
+    ///Start large copies without asking for confirmation. Without it, runs on a terminal gather all files
+    ///before copying, so they can be counted first
+    #[arg(long, short)]
+    yes: bool,
+    ///Ask for confirmation above this many files, or above this total size when given with a unit like `10G`.
+    ///Defaults to 100000 files or 10 GB, whichever is reached first
+    #[arg(long, value_parser = ConfirmThreshold::parse)]
+    confirm_threshold: Option<ConfirmThreshold>,
+    ///Whether the output should be colored. With `auto` the progress bar is colored if the terminal it is drawn
+    ///to supports it, the other output if stdout does
+    #[arg(long, value_enum, default_value = "auto")]
+    color: ColorChoice,
+    ///Whether log records are written as text or as one JSON object per line, the verbosity applies to both
+    #[arg(long, value_enum, default_value = "text")]
+    log_format: LogFormat,
+    ///Whether the logging should be verbose or not. Its `--quiet` also hides the progress bar and the final
+    ///summary, only errors are logged then. `--quiet` and `--verbose` are mutually exclusive
     #[clap(flatten)]
     verbose: clap_verbosity_flag::Verbosity,
 }
 
 impl CliArgs {
-    pub fn convert(&self) -> Arguments {
-        let file_extensions = match &self.file_extensions {
-            None => { FileExtensionFilterMode::Ignored(HashSet::new()) }
-            Some(inner) => {
-                let extensions: HashSet<String> = inner.clone().values.iter()
-                    .map(|s| if s.starts_with('.') { s.clone() } else { format!(".{}", s) })
-                    .collect();
-                match inner.target {
-                    Ignore => { FileExtensionFilterMode::Ignored(extensions) }
-                    Target => { FileExtensionFilterMode::Targeted(extensions) }
+    /// The level to log at, `--quiet` still logs errors
+    pub fn log_level_filter(&self) -> LevelFilter {
+        if self.verbose.is_silent() { LevelFilter::Error } else { self.verbose.log_level_filter() }
+    }
+
+    pub fn log_format(&self) -> LogFormat {
+        self.log_format
+    }
+
+    pub fn convert(&self) -> anyhow::Result<Arguments> {
+        if let Some(config) = &self.config {
+            return self.merged_with(CliArgs::from_config(config)?).convert();
+        }
+        let source_root_file_path = self.source_root_file_path.clone()
+            .ok_or_else(|| anyhow!("No source root folder was specified"))?;
+        let target_root_file_path = match (&self.archive, &self.target_root_file_path) {
+            (Some(_), Some(_)) => { bail!("--archive replaces the target root folder, pass only one of them"); }
+            (Some(archive), None) => {
+                if ArchiveFormat::of(archive).is_none() {
+                    bail!("The archive {} has to be a .tar or .zip file", archive.to_string_lossy());
+                }
+                if self.mode != Mode::Copy {
+                    bail!("--archive only supports --mode copy");
                 }
+                archive.to_string_lossy().to_string()
+            }
+            (None, target_root_file_path) => {
+                target_root_file_path.clone().ok_or_else(|| anyhow!("No target root folder was specified"))?
             }
         };
+        let source_roots: Vec<String> = std::iter::once(source_root_file_path.clone())
+            .chain(self.additional_sources.iter().cloned())
+            .collect();
+        for source_root in &source_roots {
+            validate_roots(Path::new(source_root), Path::new(&target_root_file_path))?;
+        }
+        if source_roots.len() > 1 {
+            validate_source_roots(&source_roots)?;
+        }
+        let single_file_target = Path::new(&source_root_file_path).is_file()
+            .then(|| single_file_target(Path::new(&source_root_file_path), &target_root_file_path));
+        if self.delete_extraneous {
+            if self.mode == Mode::Move {
+                bail!("--delete-extraneous can not be combined with --mode move, as every moved file would be extraneous");
+            }
+            if source_roots.len() > 1 || single_file_target.is_some() {
+                bail!("--delete-extraneous requires a single source root that is a directory");
+            }
+            if self.overwrite == OverwritePolicy::Rename {
+                bail!("--delete-extraneous can not be combined with --overwrite rename, as every renamed copy would be extraneous");
+            }
+        }
+        if let (Some(min_depth), Some(max_depth)) = (self.min_depth, self.max_depth) {
+            if min_depth > max_depth {
+                bail!("The minimum depth ({}) must not be greater than the maximum depth ({})", min_depth, max_depth);
+            }
+        }
+        if let (Some(newer_than), Some(older_than)) = (self.newer_than, self.older_than) {
+            if newer_than >= older_than {
+                bail!("No file can be both newer than {} and older than {}",
+                    DateTime::<Utc>::from(newer_than).to_rfc3339(), DateTime::<Utc>::from(older_than).to_rfc3339());
+            }
+        }
+        if let (Some(min_size), Some(max_size)) = (self.min_size, self.max_size) {
+            if min_size > max_size {
+                bail!("The minimum size ({}) must not be greater than the maximum size ({})", min_size, max_size);
+            }
+        }
+        let (compression_levels, default_compression_level) = self.compress.levels();
+        let compression_level = self.compression_level.unwrap_or(default_compression_level);
+        if !compression_levels.contains(&compression_level) {
+            bail!("The compression level has to be between {} and {}", compression_levels.start(), compression_levels.end());
+        }
+        if self.compress != Compression::None && self.mode != Mode::Copy {
+            bail!("--compress only supports --mode copy");
+        }
+        if self.compress != Compression::None && self.since_manifest.is_some() {
+            bail!("--since-manifest can not be combined with --compress, as the checksum manifest of a compressed run lists the checksums of the compressed targets");
+        }
+        if self.compress != Compression::None {
+            let conflicting = [(self.archive.is_some(), "--archive"), (self.skip_existing, "--skip-existing"),
+                (self.verify, "--verify"), (self.delete_extraneous, "--delete-extraneous")];
+            if let Some((_, option)) = conflicting.iter().find(|(passed, _)| *passed) {
+                bail!("--compress can not be combined with {}, as compressed targets never match their source", option);
+            }
+        }
+        if self.progress_to == ProgressTarget::Stdout && (self.summary_json || self.list_only) {
+            bail!("--progress-to stdout can not be combined with --summary-json or --list-only, as the progress bar would garble their output");
+        }
+        if self.one_filesystem && !cfg!(unix) {
+            bail!("--one-filesystem is only supported on Unix");
+        }
+        if (self.chmod.is_some() || self.chmod_dirs.is_some()) && !cfg!(unix) {
+            bail!("--chmod and --chmod-dirs are only supported on Unix");
+        }
+        if self.chmod_dirs.is_some_and(|mode| mode & OWNER_WRITE_AND_SEARCH != OWNER_WRITE_AND_SEARCH) {
+            bail!("--chmod-dirs has to keep the directories writable and searchable by their owner (u+wx), as the files are copied into them afterwards");
+        }
+        let simulate_errors = match self.simulate_error_rate {
+            Some(rate) if !(0.0..=1.0).contains(&rate) => { bail!("The simulated error rate must be between 0.0 and 1.0"); }
+            Some(rate) => {
+                let seed = self.simulate_seed.unwrap_or_else(|| {
+                    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_nanos() as u64)
+                });
+                warn!("Simulating failures for {:.0}% of the copies with seed {}", rate * 100.0, seed);
+                Some(ErrorSimulation::new(rate, seed))
+            }
+            None => { None }
+        };
+        if self.max_open_files == Some(0) {
+            bail!("The maximum number of open files must be greater than 0");
+        }
+        if self.max_rate.is_some_and(|max_rate| max_rate.as_u64() == 0) {
+            bail!("The maximum rate must be greater than 0");
+        }
+        let copy_buffer_size = match self.copy_buffer_size {
+            Some(size) if size.as_u64() == 0 => { bail!("The copy buffer size must be greater than 0"); }
+            Some(size) => { Some(usize::try_from(size.as_u64()).context("The copy buffer size is too large")?) }
+            None => { None }
+        };
+        let file_extensions = self.file_extension_filter()?;
         let folders = match &self.folders {
-            None => { FolderFilterMode::Ignored(HashSet::new()) }
+            None => { FolderFilterMode::Ignored(Vec::new()) }
             Some(inner) => {
+                let patterns = inner.values.iter()
+                    .map(|value| if self.case_insensitive_folders { value.to_lowercase() } else { value.clone() })
+                    .map(|value| FolderPattern::new(&value))
+                    .collect::<anyhow::Result<Vec<FolderPattern>>>()?;
                 match inner.target {
-                    Ignore => { FolderFilterMode::Ignored(as_hash_set(inner.values.clone())) }
-                    Target => { FolderFilterMode::Targeted(as_hash_set(inner.values.clone())) }
+                    Ignore => { FolderFilterMode::Ignored(patterns) }
+                    Target => { FolderFilterMode::Targeted(patterns) }
                 }
             }
         };
-        Arguments {
-            source_root_file_path: self.source_root_file_path.clone(),
-            target_root_file_path: self.target_root_file_path.clone(),
+        let exclude_regex = compile_regexes(&self.exclude_regex)?;
+        let exclude_from = self.exclude_from.as_deref()
+            .map(|path| load_ignore_patterns(Path::new(&source_root_file_path), path))
+            .transpose()?;
+        let include_regex = compile_regexes(&self.include_regex)?;
+        let source_root_tags = SourceRootTags::for_roots(&source_roots);
+        Ok(Arguments {
+            source_root_file_path,
+            source_roots,
+            source_root_tags,
+            source_collisions: self.source_collisions,
+            target_root_file_path,
+            single_file_target,
             file_extensions,
+            case_sensitive_extensions: self.case_sensitive_extensions,
             folders,
+            folders_match: self.folders_match,
+            case_insensitive_folders: self.case_insensitive_folders,
+            exclude_regex,
+            exclude_from,
+            include_regex,
+            include_names: self.include_name.iter().cloned().collect(),
+            exclude_names: self.exclude_name.iter().cloned().collect(),
+            from_list: self.from_list.clone().or_else(|| self.from_error_log.clone()),
+            from_error_log: self.from_error_log.is_some(),
+            parallel_walk: self.parallel_walk,
+            respect_gitignore: self.respect_gitignore,
+            one_filesystem: self.one_filesystem,
             follow_links: self.follow_links,
+            follow_links_depth_limit: self.follow_links_depth_limit,
+            max_depth: self.max_depth,
+            min_depth: self.min_depth,
+            min_size: self.min_size.map(|size| size.as_u64()),
+            exclude_empty_files: self.exclude_empty_files,
+            max_files: self.max_files,
+            exclude_symlinks: self.exclude_symlinks,
+            copy_links_as_links: self.copy_links_as_links,
+            only_symlinks: self.only_symlinks,
+            max_size: self.max_size.map(|size| size.as_u64()),
+            max_total_size: self.max_total_size.map(|size| size.as_u64()),
+            order: self.order,
+            exclude_hidden: self.exclude_hidden,
+            flatten: self.flatten,
+            flatten_collisions: self.flatten_collisions,
+            target_template: self.target_template.clone(),
+            routes: self.routes(),
+            preserve_empty_dirs: self.preserve_empty_dirs,
+            newer_than: self.newer_than,
+            older_than: self.older_than,
+            dry_run: self.dry_run,
+            post_command: self.post_command.clone(),
+            list_only: self.list_only,
+            preview_tree: self.preview_tree,
+            count_only: self.count_only,
+            diff: self.diff,
+            diff_files: self.diff_files,
+            mode: self.mode,
+            delete_extraneous: self.delete_extraneous,
+            skip_existing: self.skip_existing,
+            overwrite: self.overwrite,
+            preserve_timestamps: self.preserve_timestamps,
+            preserve_permissions: self.preserve_permissions,
+            preserve_xattrs: self.preserve_xattrs,
+            chmod: self.chmod,
+            chmod_dirs: self.chmod_dirs,
+            verify: self.verify,
+            verify_delete_on_mismatch: self.verify_delete_on_mismatch,
+            retries: self.retries,
+            on_error: self.on_error,
+            watch: self.watch,
+            archive: self.archive.clone(),
+            compress: self.compress,
+            compression_level,
+            skip_permission_errors: self.skip_permission_errors,
+            dedup: self.dedup,
+            dedup_link: self.dedup_link,
+            threads: self.threads,
+            adaptive_threads: self.adaptive_threads,
+            max_open_files: self.max_open_files,
+            rate_limiter: self.max_rate.map(|max_rate| RateLimiter::new(max_rate.as_u64())),
+            copy_buffer_size,
+            progress_writer: self.progress_fd.map(open_progress_fd).transpose()?,
+            progress_by_bytes: self.progress_by_bytes,
+            progress_to: if self.verbose.is_silent() { ProgressTarget::Hidden } else { self.progress_to },
+            error_log: self.error_log.clone(),
+            manifest: self.manifest.clone(),
+            checksum_manifest: self.checksum_manifest.clone(),
+            since_manifest: self.since_manifest.clone(),
+            checksum_algo: self.checksum_algo,
+            resume: self.resume.clone(),
+            summary_json: self.summary_json,
+            simulate_errors,
+            stats: self.stats,
+            yes: self.yes,
+            confirm_threshold: self.confirm_threshold.unwrap_or_default(),
+            color: self.color,
+            quiet: self.verbose.is_silent(),
             verbose: self.verbose.clone(),
+            stop_signal: StopSignal::default(),
+        })
+    }
+}
+
+impl CliArgs {
+    /// Collects all `--file-extensions`, each value may also hold several whitespace separated extensions
+    /// or use the deprecated form with a leading mode
+    fn file_extension_filter(&self) -> anyhow::Result<FileExtensionRules> {
+        let mut mode = self.file_extensions_mode.clone();
+        let mut values: Vec<String> = Vec::new();
+        for value in &self.file_extensions {
+            match parse_special_options(value) {
+                Ok(legacy) => {
+                    warn!("--file-extensions \"{}\" is deprecated, pass every extension separately and use --file-extensions-mode instead", value);
+                    if mode.as_ref().is_some_and(|mode| *mode != legacy.target) {
+                        bail!("--file-extensions \"{}\" conflicts with the mode of the other file extension options", value);
+                    }
+                    mode = Some(legacy.target);
+                    values.extend(legacy.values);
+                }
+                Err(_) => { values.extend(value.split_whitespace().map(|ext| ext.to_string())) }
+            }
         }
+        let normalize = |values: Vec<String>| -> HashSet<String> {
+            values.into_iter()
+                .map(|s| if s.starts_with('.') { s } else { format!(".{}", s) })
+                .map(|s| if self.case_sensitive_extensions { s } else { s.to_lowercase() })
+                .collect()
+        };
+        let mut rules = FileExtensionRules {
+            allowed: None,
+            blocked: normalize(self.exclude_extensions.iter().flat_map(|value| value.split_whitespace()).map(String::from).collect()),
+        };
+        if !values.is_empty() {
+            match mode.unwrap_or(Target) {
+                Ignore => { rules.blocked.extend(normalize(values)) }
+                Target => { rules.allowed = Some(normalize(values)) }
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Collects all `--route`s, later ones win for the same extension
+    fn routes(&self) -> HashMap<String, PathBuf> {
+        self.route.iter()
+            .map(|(extension, subfolder)| {
+                let extension = if extension.starts_with('.') { extension.clone() } else { format!(".{}", extension) };
+                let extension = if self.case_sensitive_extensions { extension } else { extension.to_lowercase() };
+                (extension, PathBuf::from(subfolder))
+            })
+            .collect()
     }
 }
 
 pub struct Arguments {
     pub source_root_file_path: String,
+    /// The source root followed by all roots of `--source`
+    pub source_roots: Vec<String>,
+    source_root_tags: SourceRootTags,
+    pub source_collisions: FlattenCollision,
     pub target_root_file_path: String,
-    pub file_extensions: FileExtensionFilterMode,
+    /// Where the source root is copied to if it is a single file instead of a directory
+    pub single_file_target: Option<PathBuf>,
+    pub file_extensions: FileExtensionRules,
+    pub case_sensitive_extensions: bool,
     pub folders: FolderFilterMode,
+    pub case_insensitive_folders: bool,
+    pub folders_match: FolderMatch,
+    pub exclude_regex: Vec<Regex>,
+    pub exclude_from: Option<Gitignore>,
+    pub include_regex: Vec<Regex>,
+    pub include_names: HashSet<String>,
+    pub exclude_names: HashSet<String>,
+    /// The list of `--from-list`, or of `--from-error-log` if [Arguments::from_error_log] is set
+    pub from_list: Option<PathBuf>,
+    /// Whether [Arguments::from_list] is an `--error-log`, whose lines continue with a tab and the error
+    pub from_error_log: bool,
+    pub parallel_walk: bool,
+    pub respect_gitignore: bool,
+    pub one_filesystem: bool,
     pub follow_links: bool,
+    pub follow_links_depth_limit: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub min_depth: Option<usize>,
+    pub min_size: Option<u64>,
+    pub exclude_empty_files: bool,
+    pub max_files: Option<usize>,
+    pub exclude_symlinks: bool,
+    pub copy_links_as_links: bool,
+    pub only_symlinks: bool,
+    pub max_size: Option<u64>,
+    pub max_total_size: Option<u64>,
+    pub order: Option<Order>,
+    pub exclude_hidden: bool,
+    pub flatten: bool,
+    pub flatten_collisions: FlattenCollision,
+    pub target_template: Option<TargetTemplate>,
+    /// The subfolders of `--route` by extension, normalized like the `--file-extensions`
+    pub routes: HashMap<String, PathBuf>,
+    pub preserve_empty_dirs: bool,
+    pub newer_than: Option<SystemTime>,
+    pub older_than: Option<SystemTime>,
+    pub dry_run: bool,
+    pub post_command: Option<String>,
+    pub list_only: bool,
+    pub preview_tree: bool,
+    pub count_only: bool,
+    pub diff: bool,
+    pub diff_files: bool,
+    pub mode: Mode,
+    pub delete_extraneous: bool,
+    pub skip_existing: bool,
+    pub overwrite: OverwritePolicy,
+    pub preserve_timestamps: bool,
+    pub preserve_permissions: bool,
+    pub preserve_xattrs: bool,
+    pub chmod: Option<u32>,
+    pub chmod_dirs: Option<u32>,
+    pub verify: bool,
+    pub verify_delete_on_mismatch: bool,
+    pub retries: u32,
+    pub on_error: OnError,
+    pub watch: bool,
+    /// Set by `--archive`, which also uses it as [Arguments::target_root_file_path]
+    pub archive: Option<PathBuf>,
+    pub compress: Compression,
+    /// The `--compression-level`, already the default level of [Arguments::compress] if none was passed
+    pub compression_level: i32,
+    pub skip_permission_errors: bool,
+    pub dedup: bool,
+    pub dedup_link: bool,
+    pub threads: Option<usize>,
+    pub adaptive_threads: bool,
+    pub max_open_files: Option<usize>,
+    pub rate_limiter: Option<RateLimiter>,
+    /// The buffer size of `--copy-buffer-size` in bytes
+    pub copy_buffer_size: Option<usize>,
+    pub progress_writer: Option<ProgressWriter>,
+    pub progress_by_bytes: bool,
+    /// Where the progress bar is drawn, already [ProgressTarget::Hidden] with `--quiet`
+    pub progress_to: ProgressTarget,
+    pub error_log: Option<PathBuf>,
+    pub manifest: Option<PathBuf>,
+    pub checksum_manifest: Option<PathBuf>,
+    pub since_manifest: Option<PathBuf>,
+    pub checksum_algo: ChecksumAlgorithm,
+    pub resume: Option<PathBuf>,
+    pub summary_json: bool,
+    pub simulate_errors: Option<ErrorSimulation>,
+    pub stats: bool,
+    pub yes: bool,
+    pub confirm_threshold: ConfirmThreshold,
+    pub color: ColorChoice,
+    pub quiet: bool,
     pub verbose: clap_verbosity_flag::Verbosity,
+    pub stop_signal: StopSignal,
+}
+
+/// The source roots the walks found entries in, for the paths located below more than one root, like
+/// `a/link/file.txt` for the roots `a` and `a/link` where `link` is a symlink. Only those are remembered, as all
+/// other paths belong to the one source root they start with
+#[derive(Clone, Default, Debug)]
+struct SourceRootTags {
+    /// Whether a source root is located below another one by its path, so there is anything to tag
+    overlapping: bool,
+    tags: Arc<Mutex<HashMap<PathBuf, usize>>>,
+}
+
+impl SourceRootTags {
+    fn for_roots(source_roots: &[String]) -> SourceRootTags {
+        let overlapping = source_roots.iter().enumerate().any(|(index, root)| {
+            source_roots.iter().enumerate().any(|(other_index, other)| index != other_index && Path::new(root).starts_with(other))
+        });
+        SourceRootTags { overlapping, tags: Arc::default() }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<PathBuf, usize>> {
+        self.tags.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
 }
 
 impl Arguments {
+    /// Excludes take precedence over includes, an empty include list does not constrain anything.
+    /// Includes only apply to files so that the directories containing them are kept. Folders are matched
+    /// on the path below the source root, for files on the directory containing them. Of the file filters,
+    /// excluded names come first, then the include regexes and finally included names, which bypass the
+    /// extension filter
+    ///
+    /// ```
+    /// use std::path::Path;
+    /// use clap::Parser;
+    /// use filescraper::args::CliArgs;
+    ///
+    /// let args = CliArgs::parse_from(["filescraper", "source", "target", "--file-extensions", "rs",
+    ///     "--folders", "Ignore build"]).convert().unwrap();
+    /// assert!(args.should_copy(&Path::new("source").join("src").join("main.rs")));
+    /// assert!(!args.should_copy(&Path::new("source").join("README.md")));
+    /// assert!(!args.should_copy(&Path::new("source").join("build").join("out.rs")));
+    /// ```
     pub fn should_copy(&self, path: &Path) -> bool {
-        return if path.is_dir() {
-            self.folders.should_copy(path)
+        self.skip_reason(path).is_none()
+    }
+
+    /// Like [Arguments::should_copy], but with the file type already known, e.g. from a walked entry, instead
+    /// of reading it from the filesystem
+    pub fn should_copy_of_type(&self, path: &Path, is_dir: bool) -> bool {
+        self.skip_reason_of_type(path, is_dir).is_none()
+    }
+
+    /// The first path filter rejecting `path` in the order described at [Arguments::should_copy], `None` if it
+    /// is copied. Whether `path` is a directory is read from the filesystem, which treats missing or
+    /// inaccessible paths as files
+    pub fn skip_reason(&self, path: &Path) -> Option<SkipReason> {
+        self.skip_reason_of_type(path, path.is_dir())
+    }
+
+    /// Like [Arguments::skip_reason], but with the file type already known
+    pub fn skip_reason_of_type(&self, path: &Path, is_dir: bool) -> Option<SkipReason> {
+        let path_string = path.to_string_lossy();
+        if self.exclude_regex.iter().any(|regex| regex.is_match(&path_string)) {
+            return Some(SkipReason::ExcludeRegex);
+        }
+        let relative_path = path.strip_prefix(self.source_root_of(path)).unwrap_or(path);
+        if let Some(exclude_from) = &self.exclude_from {
+            if !relative_path.as_os_str().is_empty() && exclude_from.matched_path_or_any_parents(relative_path, is_dir).is_ignore() {
+                return Some(SkipReason::ExcludeFrom);
+            }
+        }
+        // The configured folders were already lowercased when converting the arguments
+        let folder_path = if self.case_insensitive_folders {
+            Cow::Owned(PathBuf::from(relative_path.to_string_lossy().to_lowercase()))
         } else {
-            path.file_name()
-                .and_then(|file_name| file_name.to_str())
-                .and_then(|file_name| Some(self.file_extensions.should_copy(file_name)))
-                .or_else(|| Some(false))
-                .unwrap()
+            Cow::Borrowed(relative_path)
+        };
+        if is_dir {
+            return (!self.folders.should_copy(&folder_path, self.folders_match)).then_some(SkipReason::Folder);
+        }
+        let folder = folder_path.parent().unwrap_or(Path::new(""));
+        if self.single_file_target.is_none() && !self.folders.should_copy(folder, self.folders_match) {
+            return Some(SkipReason::Folder);
+        }
+        let Some(file_name) = path.file_name().and_then(|file_name| file_name.to_str()) else {
+            return Some(SkipReason::Extension);
+        };
+        if self.exclude_names.contains(file_name) {
+            return Some(SkipReason::Name);
+        }
+        if !self.include_regex.is_empty() && !self.include_regex.iter().any(|regex| regex.is_match(&path_string)) {
+            return Some(SkipReason::IncludeRegex);
+        }
+        if self.include_names.contains(file_name) || self.file_extensions.should_copy(file_name, self.case_sensitive_extensions) {
+            return None;
+        }
+        Some(SkipReason::Extension)
+    }
+
+    /// The source root `path` is located in, the first one if it is in none of them. Paths below several roots
+    /// belong to the root whose walk found them via [Arguments::tag_source_root]
+    pub fn source_root_of(&self, path: &Path) -> &str {
+        if self.source_root_tags.overlapping {
+            if let Some(index) = self.source_root_tags.lock().get(path) {
+                return &self.source_roots[*index];
+            }
+        }
+        self.source_roots.iter()
+            .find(|source_root| path.starts_with(source_root))
+            .unwrap_or(&self.source_root_file_path)
+    }
+
+    /// Remembers that the walk of the source root at `index` of [Arguments::source_roots] found `path`, if that
+    /// is below several roots. Should the same path be found below several roots, the first walk keeps it, just
+    /// like the first occurrence of a file found through several paths is kept
+    pub(crate) fn tag_source_root(&self, path: &Path, index: usize) {
+        if !self.source_root_tags.overlapping || self.source_roots.iter().filter(|root| path.starts_with(root)).count() < 2 {
+            return;
+        }
+        self.source_root_tags.lock().entry(path.to_path_buf()).or_insert(index);
+    }
+
+    /// Whether the size of files has to be read to filter them, which includes `--exclude-empty-files`
+    pub fn has_size_limits(&self) -> bool {
+        self.min_size.is_some() || self.max_size.is_some() || self.exclude_empty_files
+    }
+
+    pub fn is_within_size_limits(&self, size: u64) -> bool {
+        self.min_size.is_none_or(|min_size| size >= min_size)
+            && self.max_size.is_none_or(|max_size| size <= max_size)
+    }
+
+    pub fn has_time_limits(&self) -> bool {
+        self.newer_than.is_some() || self.older_than.is_some()
+    }
+
+    pub fn is_within_time_limits(&self, modified: SystemTime) -> bool {
+        self.newer_than.is_none_or(|newer_than| modified >= newer_than)
+            && self.older_than.is_none_or(|older_than| modified < older_than)
+    }
+
+    /// Mirrors `source_path` below the target root, puts it right into the target root when flattening or
+    /// places it according to the target template. The `--route` of its extension adds a subfolder to the
+    /// target root unless a template is used. On Windows the result carries the `\\?\` long path prefix
+    pub fn transform_source_to_target_path(&self, source_path: &Path) -> std::io::Result<PathBuf> {
+        let target_path = self.target_path(source_path)?;
+        let target_path = match self.compress.extension() {
+            Some(extension) if !source_path.is_dir() => {
+                let mut compressed = target_path.into_os_string();
+                compressed.push(".");
+                compressed.push(extension);
+                PathBuf::from(compressed)
+            }
+            _ => { target_path }
         };
+        Ok(long_path(&target_path).into_owned())
     }
 
-    pub fn transform_source_to_target_path(&self, source_path: &Path) -> PathBuf {
-        transform_source_to_target_path(&self.source_root_file_path, &self.target_root_file_path, source_path)
+    fn target_path(&self, source_path: &Path) -> std::io::Result<PathBuf> {
+        let source_root = self.source_root_of(source_path);
+        let target_root = self.routed_target_root(source_path);
+        let target_path = transform_source_to_target_path(source_root, &target_root, source_path)?;
+        if let Some(template) = &self.target_template {
+            let relative_path = match source_path.strip_prefix(source_root) {
+                Ok(relative_path) if !relative_path.as_os_str().is_empty() => { relative_path }
+                _ => { Path::new(source_path.file_name().unwrap_or_default()) }
+            };
+            let expanded = template.expand(relative_path, || std::fs::metadata(source_path)?.modified())?;
+            return Ok(Path::new(&self.target_root_file_path).join(expanded));
+        }
+        if let Some(single_file_target) = &self.single_file_target {
+            return Ok(single_file_target.clone());
+        }
+        match (self.flatten, source_path.file_name()) {
+            (true, Some(file_name)) => { Ok(target_root.join(file_name)) }
+            _ => { Ok(target_path) }
+        }
+    }
+
+    /// The target root joined with the `--route` subfolder of the longest matching extension of `source_path`
+    fn routed_target_root(&self, source_path: &Path) -> PathBuf {
+        let target_root = Path::new(&self.target_root_file_path);
+        if self.routes.is_empty() || source_path.is_dir() {
+            return target_root.to_path_buf();
+        }
+        let subfolder = source_path.file_name()
+            .and_then(|file_name| file_name.to_str())
+            .and_then(|file_name| {
+                file_extensions(file_name, self.case_sensitive_extensions).into_iter()
+                    .find_map(|extension| self.routes.get(&extension))
+            });
+        match subfolder {
+            Some(subfolder) => { target_root.join(subfolder) }
+            None => { target_root.to_path_buf() }
+        }
     }
 }
 
-fn transform_source_to_target_path(source_root_file_path: &str, target_root_file_path: &str, source_path: &Path) -> PathBuf {
+/// A single file is copied into the target if that is an existing directory, ends with a separator or has no
+/// extension, otherwise the target is taken as the path of the copy, like `cp` does
+fn single_file_target(source_file: &Path, target_root_file_path: &str) -> PathBuf {
+    let target_root = Path::new(target_root_file_path);
+    let is_directory = target_root.is_dir()
+        || target_root_file_path.ends_with(['/', MAIN_SEPARATOR])
+        || target_root.extension().is_none();
+    match (is_directory, source_file.file_name()) {
+        (true, Some(file_name)) => { target_root.join(file_name) }
+        _ => { target_root.to_path_buf() }
+    }
+}
+
+/// Rejects a target equal to or inside the source, which would copy its own output over and over again
+fn validate_roots(source_root: &Path, target_root: &Path) -> anyhow::Result<()> {
+    let source = canonicalize_existing_prefix(source_root)?;
+    let target = canonicalize_existing_prefix(target_root)?;
+    if target.starts_with(&source) {
+        bail!("The target root {} must not be the source root {} or be located inside it",
+            target_root.to_string_lossy(), source_root.to_string_lossy());
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn open_progress_fd(fd: i32) -> anyhow::Result<ProgressWriter> {
+    ProgressWriter::from_fd(fd).with_context(|| format!("Could not open the progress file descriptor {}", fd))
+}
+
+#[cfg(not(unix))]
+fn open_progress_fd(_fd: i32) -> anyhow::Result<ProgressWriter> {
+    bail!("--progress-fd is only supported on Unix")
+}
+
+/// Rejects multiple source roots that are files or nested in one another, as every file has to belong to
+/// exactly one root to determine its relative path
+fn validate_source_roots(source_roots: &[String]) -> anyhow::Result<()> {
+    let canonical_roots = source_roots.iter()
+        .map(|source_root| {
+            if Path::new(source_root).is_file() {
+                bail!("The source root {} must be a directory when copying from multiple roots", source_root);
+            }
+            Ok(canonicalize_existing_prefix(Path::new(source_root))?)
+        })
+        .collect::<anyhow::Result<Vec<PathBuf>>>()?;
+    for (index, root) in canonical_roots.iter().enumerate() {
+        for (other_index, other) in canonical_roots.iter().enumerate() {
+            if index != other_index && root.starts_with(other) {
+                bail!("The source root {} must not be located inside the source root {}", source_roots[index], source_roots[other_index]);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Canonicalizes the longest existing ancestor of `path` and appends the not yet existing rest, as the
+/// target usually does not exist before the first copy. The `.` and `..` of the rest are resolved lexically
+fn canonicalize_existing_prefix(path: &Path) -> std::io::Result<PathBuf> {
+    let absolute = std::path::absolute(path)?;
+    let mut existing = absolute.as_path();
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            let missing = absolute.strip_prefix(existing).unwrap_or(Path::new(""));
+            return Ok(join_lexically(canonical, missing));
+        }
+        match existing.parent() {
+            Some(parent) => { existing = parent; }
+            None => { return Ok(join_lexically(PathBuf::new(), &absolute)); }
+        }
+    }
+}
+
+/// Appends `relative` to `base`, where a `..` removes the last component instead of being kept
+fn join_lexically(base: PathBuf, relative: &Path) -> PathBuf {
+    relative.components().fold(base, |mut path, component| {
+        match component {
+            Component::ParentDir => { path.pop(); }
+            Component::CurDir => {}
+            component => { path.push(component); }
+        }
+        path
+    })
+}
+
+/// Parses an RFC3339 timestamp or a duration like `7d`, which is counted back from now
+fn parse_point_in_time(s: &str) -> Result<SystemTime, String> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(s) {
+        return Ok(timestamp.into());
+    }
+    let duration = parse_duration(s)?;
+    SystemTime::now().checked_sub(duration).ok_or_else(|| format!("{} reaches too far into the past", s))
+}
+
+/// Parses a whole number followed by one of the units `s`, `m`, `h`, `d` or `w`
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("'{}' is neither an RFC3339 timestamp nor a duration like 7d", s);
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (amount, unit) = s.split_at(digits);
+    let amount: u64 = amount.parse().map_err(|_| invalid())?;
+    let seconds_per_unit = match unit {
+        "s" => { 1 }
+        "m" => { 60 }
+        "h" => { 60 * 60 }
+        "d" => { 24 * 60 * 60 }
+        "w" => { 7 * 24 * 60 * 60 }
+        _ => { return Err(invalid()); }
+    };
+    amount.checked_mul(seconds_per_unit).map(Duration::from_secs).ok_or_else(invalid)
+}
+
+/// Parses an octal permission mode like `644`, `0755` or `0o755`
+/// The owner's write and search bits a directory needs while files are copied into it
+const OWNER_WRITE_AND_SEARCH: u32 = 0o300;
+
+fn parse_mode(s: &str) -> Result<u32, String> {
+    let digits = s.strip_prefix("0o").unwrap_or(s);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o7777 => { Ok(mode) }
+        _ => { Err(format!("'{}' is not an octal permission mode like 644", s)) }
+    }
+}
+
+/// Loads the gitignore-style patterns in `path`, anchored at the source root
+fn load_ignore_patterns(source_root: &Path, path: &Path) -> anyhow::Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(source_root);
+    if let Some(err) = builder.add(path) {
+        bail!("Could not load the exclude patterns from {}: {}", path.to_string_lossy(), err);
+    }
+    builder.build().map_err(|err| anyhow!("Invalid exclude patterns in {}: {}", path.to_string_lossy(), err))
+}
+
+fn compile_regexes(patterns: &[String]) -> anyhow::Result<Vec<Regex>> {
+    patterns.iter()
+        .map(|pattern| Regex::new(pattern).map_err(|err| anyhow!("Invalid regex '{}': {}", pattern, err)))
+        .collect()
+}
+
+fn transform_source_to_target_path(source_root_file_path: &str, target_root: impl AsRef<Path>, source_path: &Path) -> std::io::Result<PathBuf> {
     let source_root = Path::new(source_root_file_path);
     match source_path.strip_prefix(source_root) {
         Ok(stripped) => {
-            let target_root = Path::new(target_root_file_path);
-            target_root.join(stripped)
+            Ok(target_root.as_ref().join(stripped))
         }
         Err(err) => {
-            panic!("{}", err)
+            Err(std::io::Error::new(ErrorKind::InvalidInput, format!("{} is not below {}: {}", source_path.to_string_lossy(), source_root_file_path, err)))
         }
     }
 }
 
-fn as_hash_set(vec: Vec<String>) -> HashSet<String> {
-    vec.into_iter().collect()
-}
-
 
-#[derive(PartialEq, Debug)]
-enum FileExtensionFilterMode {
-    Ignored(HashSet<String>),
-    Targeted(HashSet<String>),
+/// The configured `--file-extensions` and `--exclude-extensions`, each with its leading dot and lowercased unless
+/// matched case-sensitively. Without any `allowed` extensions every extension that is not `blocked` is copied
+#[derive(PartialEq, Debug, Default)]
+pub struct FileExtensionRules {
+    pub allowed: Option<HashSet<String>>,
+    pub blocked: HashSet<String>,
 }
 
-trait FileExtensionFilter {
-    fn should_copy(&self, file_name: &str) -> bool;
+pub trait FileExtensionFilter {
+    /// Whether the [file_extensions] of `file_name` pass the filter
+    ///
+    /// ```
+    /// use std::collections::HashSet;
+    /// use filescraper::args::{FileExtensionFilter, FileExtensionRules};
+    ///
+    /// let rules = FileExtensionRules {
+    ///     allowed: Some(HashSet::from([".gz".to_string()])),
+    ///     blocked: HashSet::from([".log.gz".to_string()]),
+    /// };
+    /// assert!(rules.should_copy("archive.tar.gz", false));
+    /// assert!(rules.should_copy("ARCHIVE.GZ", false));
+    /// assert!(!rules.should_copy("ARCHIVE.GZ", true));
+    /// assert!(!rules.should_copy("notes.txt", false));
+    /// assert!(!rules.should_copy("server.log.gz", false));
+    /// ```
+    fn should_copy(&self, file_name: &str, case_sensitive: bool) -> bool;
 }
 
-impl FileExtensionFilter for FileExtensionFilterMode {
-    fn should_copy(&self, file_name: &str) -> bool {
-        let file_extension = file_extension(file_name);
-        match self {
-            FileExtensionFilterMode::Ignored(ignored) => {
-                !ignored.contains(&file_extension)
-            }
-            FileExtensionFilterMode::Targeted(targeted) => {
-                targeted.contains(&file_extension)
-            }
+impl FileExtensionFilter for FileExtensionRules {
+    /// Blocked extensions win, so a file has to match an allowed extension, if there are any, and no blocked one
+    fn should_copy(&self, file_name: &str, case_sensitive: bool) -> bool {
+        let file_extensions = file_extensions(file_name, case_sensitive);
+        if file_extensions.iter().any(|extension| self.blocked.contains(extension)) {
+            return false;
         }
+        self.allowed.as_ref()
+            .is_none_or(|allowed| file_extensions.iter().any(|extension| allowed.contains(extension)))
     }
 }
 
-fn file_extension(file_name: &str) -> String {
-    Path::new(file_name)
-        .extension()
-        .map(|extension| format!(".{}", extension.to_string_lossy()))
-        .unwrap_or_else(|| "".to_string())
+/// Every dotted suffix of `file_name`, so a configured extension matches as the end of the name:
+/// `archive.tar.gz` yields `.tar.gz` and `.gz`. A leading dot as in `.bashrc` does not start an extension
+///
+/// ```
+/// use filescraper::args::file_extensions;
+///
+/// assert_eq!(file_extensions("archive.tar.gz", false), vec![".tar.gz", ".gz"]);
+/// assert_eq!(file_extensions("Photo.JPG", false), vec![".jpg"]);
+/// assert_eq!(file_extensions("Photo.JPG", true), vec![".JPG"]);
+/// assert!(file_extensions(".bashrc", false).is_empty());
+/// ```
+pub fn file_extensions(file_name: &str, case_sensitive: bool) -> Vec<String> {
+    let file_name = if case_sensitive { file_name.to_string() } else { file_name.to_lowercase() };
+    file_name.char_indices()
+        .filter(|(index, c)| *index > 0 && *c == '.')
+        .map(|(index, _)| file_name[index..].to_string())
+        .collect()
 }
 
 
-trait FolderFilter {
-    fn should_copy(&self, path: &Path) -> bool;
+pub trait FolderFilter {
+    /// Whether a path passes the filter, where `folders_match` decides whether the folders are matched by
+    /// [path_contains_folder] or by [path_starts_with_folder] on the path below the source root
+    ///
+    /// ```
+    /// use std::path::Path;
+    /// use filescraper::args::{FolderFilter, FolderFilterMode, FolderMatch, FolderPattern};
+    ///
+    /// let ignored = FolderFilterMode::Ignored(vec![FolderPattern::new("build").unwrap()]);
+    /// let nested = Path::new("src").join("build");
+    /// assert!(!ignored.should_copy(&nested, FolderMatch::Segment));
+    /// assert!(ignored.should_copy(&nested, FolderMatch::Prefix));
+    /// assert!(ignored.should_copy(Path::new("src"), FolderMatch::Segment));
+    /// ```
+    fn should_copy(&self, path: &Path, folders_match: FolderMatch) -> bool;
 }
 
 
+/// The configured `--folders`
 #[derive(PartialEq, Debug)]
-enum FolderFilterMode {
-    Ignored(HashSet<String>),
-    Targeted(HashSet<String>),
+pub enum FolderFilterMode {
+    Ignored(Vec<FolderPattern>),
+    Targeted(Vec<FolderPattern>),
+}
+
+/// A configured folder, either matched literally or as a glob if it contains glob metacharacters
+#[derive(PartialEq, Debug)]
+pub enum FolderPattern {
+    Literal(String),
+    Glob(Pattern),
+}
+
+impl FolderPattern {
+    /// Parses `value` as a glob if it contains any of `*`, `?` or `[`, and as a literal folder otherwise
+    ///
+    /// ```
+    /// use filescraper::args::FolderPattern;
+    ///
+    /// assert!(FolderPattern::new("cache-*").unwrap().matches("cache-2024"));
+    /// assert!(FolderPattern::new("build").unwrap().matches("build"));
+    /// assert!(!FolderPattern::new("build").unwrap().matches("Build"));
+    /// assert!(FolderPattern::new("[").is_err());
+    /// ```
+    pub fn new(value: &str) -> anyhow::Result<FolderPattern> {
+        if value.contains(['*', '?', '[']) {
+            let pattern = Pattern::new(value)
+                .map_err(|err| anyhow!("Invalid folder pattern '{}': {}", value, err))?;
+            Ok(FolderPattern::Glob(pattern))
+        } else {
+            Ok(FolderPattern::Literal(value.to_string()))
+        }
+    }
+
+    /// Whether a single directory name, or for [FolderMatch::Prefix] several joined by `/`, matches the pattern
+    pub fn matches(&self, segment: &str) -> bool {
+        match self {
+            FolderPattern::Literal(literal) => { literal == segment }
+            FolderPattern::Glob(pattern) => { pattern.matches(segment) }
+        }
+    }
+
+    /// How many directories the pattern spans, `src/gen` spans two
+    fn depth(&self) -> usize {
+        let value = match self {
+            FolderPattern::Literal(literal) => { literal.as_str() }
+            FolderPattern::Glob(pattern) => { pattern.as_str() }
+        };
+        value.split(['/', MAIN_SEPARATOR]).filter(|s| !s.is_empty()).count()
+    }
 }
 
 impl FolderFilter for FolderFilterMode {
-    fn should_copy(&self, path: &Path) -> bool {
+    fn should_copy(&self, path: &Path, folders_match: FolderMatch) -> bool {
+        let matches = |folder: &FolderPattern| {
+            match folders_match {
+                FolderMatch::Segment => { path_contains_folder(path, folder) }
+                FolderMatch::Prefix => { path_starts_with_folder(path, folder) }
+            }
+        };
         match self {
             FolderFilterMode::Ignored(ignored) => {
-                !ignored.into_iter().any(|ign| path_contains_folder(path, ign))
+                !ignored.iter().any(matches)
             }
             FolderFilterMode::Targeted(targeted) => {
-                targeted.into_iter().any(|tar| path_contains_folder(path, tar))
+                targeted.iter().any(matches)
             }
         }
     }
 }
 
-fn path_contains_folder(path: &Path, folder: &str) -> bool {
+/// Whether any directory name along `path` matches `folder`
+///
+/// ```
+/// use std::path::Path;
+/// use filescraper::args::{FolderPattern, path_contains_folder};
+///
+/// let path = Path::new("src").join("build").join("out.o");
+/// assert!(path_contains_folder(&path, &FolderPattern::new("build").unwrap()));
+/// assert!(path_contains_folder(&path, &FolderPattern::new("b*").unwrap()));
+/// assert!(!path_contains_folder(&path, &FolderPattern::new("gen").unwrap()));
+/// ```
+pub fn path_contains_folder(path: &Path, folder: &FolderPattern) -> bool {
     match path.to_str() {
         None => false,
         Some(path_str) => {
-            let folders: HashSet<&str> = path_str.split(MAIN_SEPARATOR).filter(|s| !s.is_empty()).collect();
-            folders.contains(folder)
+            path_str.split(MAIN_SEPARATOR)
+                .filter(|s| !s.is_empty())
+                .any(|segment| folder.matches(segment))
         }
     }
 }
 
+/// Whether the leading directories of `path` match `folder`, which may span several directories
+///
+/// ```
+/// use std::path::Path;
+/// use filescraper::args::{FolderPattern, path_starts_with_folder};
+///
+/// let path = Path::new("src").join("gen").join("bindings.rs");
+/// assert!(path_starts_with_folder(&path, &FolderPattern::new("src/gen").unwrap()));
+/// assert!(path_starts_with_folder(&path, &FolderPattern::new("src").unwrap()));
+/// assert!(!path_starts_with_folder(&path, &FolderPattern::new("gen").unwrap()));
+/// ```
+pub fn path_starts_with_folder(path: &Path, folder: &FolderPattern) -> bool {
+    let segments: Vec<String> = path.components()
+        .filter_map(|component| match component {
+            Component::Normal(segment) => { Some(segment.to_string_lossy().to_string()) }
+            _ => { None }
+        })
+        .collect();
+    let depth = folder.depth();
+    depth > 0 && segments.len() >= depth && folder.matches(&segments[..depth].join("/"))
+}
+
 #[cfg(test)]
 mod tests {
-    use std::path::{Path, PathBuf};
+    use std::fs::File;
+    use std::path::{MAIN_SEPARATOR, Path, PathBuf};
+    use std::time::{Duration, SystemTime};
 
-    use crate::args::{CliArgs, FileExtensionFilterMode, FolderFilterMode, OptionalHandling, parse_special_options, transform_source_to_target_path};
+    use clap::Parser;
+
+    use bytesize::ByteSize;
+    use crate::args::{CliArgs, ConfirmThreshold, file_extensions, FolderFilterMode, OptionalHandling, OverwritePolicy, parse_duration, parse_mode, ProgressTarget, parse_point_in_time, parse_route, parse_special_options, SkipReason, transform_source_to_target_path, validate_roots};
     use crate::args::TargetMode::{Ignore, Target};
 
     #[test]
@@ -223,6 +1688,20 @@ mod tests {
         assert!(names.contains(&".mp3".to_string()));
     }
 
+    #[test]
+    fn test_parse_special_options_with_quotes() {
+        let result = parse_special_options("Ignore \"My Documents\" temp").unwrap();
+        assert_eq!(result.target, Ignore);
+        assert_eq!(result.values, vec!["My Documents".to_string(), "temp".to_string()]);
+        let result = parse_special_options("Target  build \"Program Files/App Data\"   \"x\"").unwrap();
+        assert_eq!(result.target, Target);
+        assert_eq!(result.values, vec!["build".to_string(), "Program Files/App Data".to_string(), "x".to_string()]);
+        let result = parse_special_options("\"Ignore\" \"\" a").unwrap();
+        assert_eq!(result.values, vec!["a".to_string()]);
+        assert!(parse_special_options("Ignore \"My Documents").is_err());
+        assert!(parse_special_options("Ignore \"\"").is_err());
+    }
+
     #[test]
     fn test_parse_special_options_not_enough_options_supplied() {
         let input = "Target";
@@ -240,46 +1719,46 @@ mod tests {
     #[test]
     fn test_convert_cli_args_to_args() {
         let cli_args = CliArgs {
-            source_root_file_path: "source".to_string(),
-            target_root_file_path: "target".to_string(),
-            file_extensions: Some(OptionalHandling {
-                target: Ignore,
-                values: vec![".jpg".to_string(), ".pdf".to_string()],
-            }),
+            source_root_file_path: Some("source".to_string()),
+            target_root_file_path: Some("target".to_string()),
+            file_extensions: vec![".jpg".to_string(), ".pdf".to_string()],
+            file_extensions_mode: Some(Ignore),
             folders: None,
             follow_links: false,
             verbose: Default::default(),
+            ..Default::default()
         };
-        let result = cli_args.convert();
-        assert_eq!(result.follow_links, false);
+        let result = cli_args.convert().unwrap();
+        assert!(!result.follow_links);
         let target = match result.folders {
             FolderFilterMode::Ignored(_) => { Ignore }
             FolderFilterMode::Targeted(_) => { Target }
         };
         assert_eq!(target, Ignore);
-        let file_extensions = match result.file_extensions {
-            FileExtensionFilterMode::Ignored(ext) => { ext }
-            FileExtensionFilterMode::Targeted(_) => { panic!("Wrong mode"); }
-        };
-        assert!(file_extensions.contains(".jpg"));
-        assert!(file_extensions.contains(".pdf"));
+        assert_eq!(result.file_extensions.allowed, None);
+        assert!(result.file_extensions.blocked.contains(".jpg"));
+        assert!(result.file_extensions.blocked.contains(".pdf"));
     }
 
     #[test]
     fn test_should_copy_folder() {
         let cli_args = CliArgs {
-            source_root_file_path: "source".to_string(),
-            target_root_file_path: "target".to_string(),
-            file_extensions: None,
+            source_root_file_path: Some("source".to_string()),
+            target_root_file_path: Some("target".to_string()),
             folders: Some(OptionalHandling {
                 target: Ignore,
                 values: vec!["bin".to_string(), "target".to_string()],
             }),
             follow_links: false,
             verbose: Default::default(),
+            ..Default::default()
         };
-        let current_dir = std::env::current_dir().unwrap();
-        let result = cli_args.convert();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let current_dir = temp_dir.path().to_path_buf();
+        for folder in ["test/bin/test", "test/file"] {
+            std::fs::create_dir_all(current_dir.join(folder)).unwrap();
+        }
+        let result = cli_args.convert().unwrap();
         let path = Path::new("test/bin/");
         let binding = current_dir.clone().joined(path);
         let path = binding.as_path();
@@ -300,19 +1779,175 @@ mod tests {
     }
 
     #[test]
-    fn test_should_copy_file() {
+    fn test_folders_match_segment_and_prefix() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let source = temp_dir.path().join("source");
+        for folder in ["build", "src/build", "src/gen", "docs"] {
+            std::fs::create_dir_all(source.join(folder)).unwrap();
+        }
+        let source_root = source.to_string_lossy().to_string();
+        let parse = |args: &[&str]| {
+            let mut full_args = vec!["filescraper", &source_root, "target"];
+            full_args.extend_from_slice(args);
+            CliArgs::parse_from(full_args).convert().unwrap()
+        };
+
+        let segment = parse(&["--folders", "Ignore build"]);
+        assert!(!segment.should_copy(&source.join("build")));
+        assert!(!segment.should_copy(&source.join("src").join("build")));
+        assert!(!segment.should_copy(&source.join("src").join("build").join("out.o")));
+        assert!(segment.should_copy(&source.join("README.md")));
+        assert!(segment.should_copy(&source.join("src").join("gen")));
+        assert!(segment.should_copy(&source.join("src").join("main.rs")));
+
+        let prefix = parse(&["--folders", "Ignore build", "--folders-match", "prefix"]);
+        assert!(!prefix.should_copy(&source.join("build")));
+        assert!(!prefix.should_copy(&source.join("build").join("out.o")));
+        assert!(prefix.should_copy(&source.join("src").join("build")));
+        assert!(prefix.should_copy(&source.join("src").join("build").join("out.o")));
+
+        let nested = parse(&["--folders", "Target src/gen", "--folders-match", "prefix"]);
+        assert!(nested.should_copy(&source.join("src").join("gen")));
+        assert!(nested.should_copy(&source.join("src").join("gen").join("bindings.rs")));
+        assert!(!nested.should_copy(&source.join("src").join("build").join("out.o")));
+        assert!(!nested.should_copy(&source.join("README.md")));
+    }
+
+    #[test]
+    fn test_case_insensitive_folders() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let source = temp_dir.path().join("Source");
+        for folder in ["Temp", "temp", "TEMP", "docs"] {
+            std::fs::create_dir_all(source.join("files").join(folder)).unwrap();
+        }
+        let source_root = source.to_string_lossy().to_string();
+        let parse = |args: &[&str]| {
+            let mut full_args = vec!["filescraper", &source_root, "target"];
+            full_args.extend_from_slice(args);
+            CliArgs::parse_from(full_args).convert().unwrap()
+        };
+
+        let insensitive = parse(&["--folders", "Ignore Temp", "--case-insensitive-folders"]);
+        for folder in ["Temp", "temp", "TEMP"] {
+            assert!(!insensitive.should_copy(&source.join("files").join(folder)));
+            assert!(!insensitive.should_copy(&source.join("files").join(folder).join("notes.txt")));
+        }
+        assert!(insensitive.should_copy(&source.join("files").join("docs")));
+        assert!(insensitive.should_copy(&source.join("files").join("docs").join("notes.txt")));
+
+        let glob = parse(&["--folders", "Ignore TE*", "--case-insensitive-folders"]);
+        assert!(!glob.should_copy(&source.join("files").join("temp")));
+        assert!(!glob.should_copy(&source.join("files").join("temp").join("notes.txt")));
+        assert!(glob.should_copy(&source.join("files").join("docs").join("notes.txt")));
+        let prefix = parse(&["--folders", "Target Files/Temp", "--case-insensitive-folders", "--folders-match", "prefix"]);
+        assert!(prefix.should_copy(&source.join("files").join("TEMP").join("notes.txt")));
+        assert!(!prefix.should_copy(&source.join("files").join("docs").join("notes.txt")));
+
+        let sensitive = parse(&["--folders", "Ignore Temp"]);
+        assert!(!sensitive.should_copy(&source.join("files").join("Temp")));
+        assert!(!sensitive.should_copy(&source.join("files").join("Temp").join("notes.txt")));
+        assert!(sensitive.should_copy(&source.join("files").join("temp").join("notes.txt")));
+        assert!(sensitive.should_copy(&source.join("files").join("TEMP").join("notes.txt")));
+    }
+
+    #[test]
+    fn test_folders_ignore_source_root_segments() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let source = temp_dir.path().join("build").join("source");
+        std::fs::create_dir_all(source.join("docs")).unwrap();
+        let cli_args = CliArgs::parse_from(["filescraper", &source.to_string_lossy(), "target", "--folders", "Ignore build"]);
+        let result = cli_args.convert().unwrap();
+        assert!(result.should_copy(&source.join("docs")));
+        assert!(result.should_copy(&source.join("docs").join("manual.pdf")));
+    }
+
+    #[test]
+    fn test_should_copy_folder_glob() {
+        let cli_args = CliArgs {
+            source_root_file_path: Some("source".to_string()),
+            target_root_file_path: Some("target".to_string()),
+            folders: Some(OptionalHandling {
+                target: Ignore,
+                values: vec!["cache*".to_string(), "*.tmp".to_string(), "bin".to_string()],
+            }),
+            ..Default::default()
+        };
+        let temp_dir = tempfile::tempdir().unwrap();
+        for folder in ["cache_v2", "build.tmp", "bin", "binaries", "src"] {
+            std::fs::create_dir_all(temp_dir.path().join("client").join(folder)).unwrap();
+        }
+        let result = cli_args.convert().unwrap();
+        let client = temp_dir.path().join("client");
+        assert!(!result.should_copy(&client.join("cache_v2")));
+        assert!(!result.should_copy(&client.join("build.tmp")));
+        assert!(!result.should_copy(&client.join("bin")));
+        assert!(result.should_copy(&client.join("binaries")));
+        assert!(result.should_copy(&client.join("src")));
+    }
+
+    #[test]
+    fn test_invalid_folder_glob() {
         let cli_args = CliArgs {
-            source_root_file_path: "source".to_string(),
-            target_root_file_path: "target".to_string(),
-            file_extensions: Some(OptionalHandling {
+            source_root_file_path: Some("source".to_string()),
+            target_root_file_path: Some("target".to_string()),
+            folders: Some(OptionalHandling {
                 target: Ignore,
-                values: vec![".jpg".to_string(), ".pdf".to_string()],
+                values: vec!["cache[".to_string()],
             }),
+            ..Default::default()
+        };
+        assert!(cli_args.convert().is_err());
+    }
+
+    #[test]
+    fn test_target_inside_source_is_rejected() {
+        for (source, target) in [("/data", "/data/backup"), ("/data", "/data"), ("/data", "/data/./backup/.."),
+                                 ("/data", "/backup/../data/copy"), ("/data/../data", "/data/copy")] {
+            assert!(validate_roots(Path::new(source), Path::new(target)).is_err(), "{} and {}", source, target);
+        }
+        for (source, target) in [("/data", "/backup"), ("/data", "/database"), ("/data/backup", "/data"),
+                                 ("/data", "/data/../backup")] {
+            assert!(validate_roots(Path::new(source), Path::new(target)).is_ok(), "{} and {}", source, target);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_target_inside_source_through_symlink_is_rejected() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let source = temp_dir.path().join("source");
+        std::fs::create_dir(&source).unwrap();
+        std::os::unix::fs::symlink(&source, temp_dir.path().join("link")).unwrap();
+        assert!(validate_roots(&source, &temp_dir.path().join("link").join("backup")).is_err());
+        let through_missing = temp_dir.path().join("missing").join("..").join("source").join("backup");
+        assert!(validate_roots(&source, &through_missing).is_err());
+    }
+
+    #[test]
+    fn test_min_depth_greater_than_max_depth() {
+        let cli_args = CliArgs {
+            source_root_file_path: Some("source".to_string()),
+            target_root_file_path: Some("target".to_string()),
+            min_depth: Some(3),
+            max_depth: Some(2),
+            ..Default::default()
+        };
+        assert!(cli_args.convert().is_err());
+    }
+
+    #[test]
+    fn test_should_copy_file() {
+        let cli_args = CliArgs {
+            source_root_file_path: Some("source".to_string()),
+            target_root_file_path: Some("target".to_string()),
+            file_extensions: vec![".jpg".to_string(), ".pdf".to_string()],
+            file_extensions_mode: Some(Ignore),
             folders: None,
             follow_links: false,
             verbose: Default::default(),
+            ..Default::default()
         };
-        let result = cli_args.convert();
+        let result = cli_args.convert().unwrap();
         let path = Path::new("test.jpg");
         let should_copy = result.should_copy(path);
         assert!(!should_copy);
@@ -326,16 +1961,522 @@ mod tests {
         assert!(should_copy);
     }
 
+    #[test]
+    fn test_should_copy_file_case_insensitive_extensions() {
+        let cli_args = CliArgs {
+            source_root_file_path: Some("source".to_string()),
+            target_root_file_path: Some("target".to_string()),
+            file_extensions: vec![".jpg".to_string()],
+            file_extensions_mode: Some(Target),
+            ..Default::default()
+        };
+        let result = cli_args.convert().unwrap();
+        assert!(result.should_copy(Path::new("photo.JPG")));
+        assert!(result.should_copy(Path::new("photo.Jpg")));
+        assert!(result.should_copy(Path::new("photo.jpg")));
+        assert!(!result.should_copy(Path::new("photo.png")));
+    }
+
+    #[test]
+    fn test_should_copy_file_case_sensitive_extensions() {
+        let cli_args = CliArgs {
+            source_root_file_path: Some("source".to_string()),
+            target_root_file_path: Some("target".to_string()),
+            file_extensions: vec!["JPG".to_string()],
+            file_extensions_mode: Some(Target),
+            case_sensitive_extensions: true,
+            ..Default::default()
+        };
+        let result = cli_args.convert().unwrap();
+        assert!(result.should_copy(Path::new("photo.JPG")));
+        assert!(!result.should_copy(Path::new("photo.Jpg")));
+        assert!(!result.should_copy(Path::new("photo.jpg")));
+    }
+
+    #[test]
+    fn test_file_extensions_accumulate() {
+        let cli_args = CliArgs::parse_from(["filescraper", "source", "target",
+            "--file-extensions", "jpg", "--file-extensions", ".PNG", "--file-extensions", "gif tiff"]);
+        let result = cli_args.convert().unwrap();
+        assert!(result.should_copy(Path::new("photo.jpg")));
+        assert!(result.should_copy(Path::new("photo.png")));
+        assert!(result.should_copy(Path::new("photo.tiff")));
+        assert!(!result.should_copy(Path::new("notes.txt")));
+
+        let cli_args = CliArgs::parse_from(["filescraper", "source", "target",
+            "--file-extensions", "jpg", "--file-extensions-mode", "ignore"]);
+        let result = cli_args.convert().unwrap();
+        assert!(!result.should_copy(Path::new("photo.jpg")));
+        assert!(result.should_copy(Path::new("notes.txt")));
+    }
+
+    #[test]
+    fn test_file_extensions_deprecated_form() {
+        let cli_args = CliArgs::parse_from(["filescraper", "source", "target", "--file-extensions", "Ignore jpg pdf"]);
+        let result = cli_args.convert().unwrap();
+        assert!(!result.should_copy(Path::new("photo.jpg")));
+        assert!(!result.should_copy(Path::new("report.pdf")));
+        assert!(result.should_copy(Path::new("notes.txt")));
+
+        let cli_args = CliArgs::parse_from(["filescraper", "source", "target",
+            "--file-extensions", "Ignore jpg", "--file-extensions-mode", "target"]);
+        assert!(cli_args.convert().is_err());
+    }
+
+    #[test]
+    fn test_targeted_and_excluded_extensions() {
+        let cli_args = CliArgs::parse_from(["filescraper", "source", "target",
+            "--file-extensions", "jpg", "--file-extensions", "png", "--exclude-extensions", ".thumb.jpg"]);
+        let result = cli_args.convert().unwrap();
+        assert!(result.should_copy(Path::new("photo.jpg")));
+        assert!(result.should_copy(Path::new("diagram.PNG")));
+        assert!(!result.should_copy(Path::new("photo.thumb.jpg")));
+        assert!(!result.should_copy(Path::new("notes.txt")));
+
+        let cli_args = CliArgs::parse_from(["filescraper", "source", "target",
+            "--file-extensions", "tmp", "--file-extensions-mode", "ignore", "--exclude-extensions", "bak log"]);
+        let result = cli_args.convert().unwrap();
+        assert_eq!(result.file_extensions.allowed, None);
+        assert!(!result.should_copy(Path::new("scratch.tmp")));
+        assert!(!result.should_copy(Path::new("old.bak")));
+        assert!(!result.should_copy(Path::new("server.log")));
+        assert!(result.should_copy(Path::new("notes.txt")));
+
+        let cli_args = CliArgs::parse_from(["filescraper", "source", "target", "--exclude-extensions", "bak"]);
+        let result = cli_args.convert().unwrap();
+        assert!(result.should_copy(Path::new("notes.txt")));
+        assert!(!result.should_copy(Path::new("notes.txt.bak")));
+    }
+
+    #[test]
+    fn test_file_extensions() {
+        assert_eq!(file_extensions("archive.tar.gz", false), vec![".tar.gz", ".gz"]);
+        assert_eq!(file_extensions("Photo.JPG", false), vec![".jpg"]);
+        assert_eq!(file_extensions("Photo.JPG", true), vec![".JPG"]);
+        assert!(file_extensions(".bashrc", false).is_empty());
+        assert!(file_extensions("README", false).is_empty());
+    }
+
+    #[test]
+    fn test_should_copy_compound_extensions() {
+        let cli_args = CliArgs::parse_from(["filescraper", "source", "target",
+            "--file-extensions", ".tar.gz", "--file-extensions", "tar.bz2"]);
+        let result = cli_args.convert().unwrap();
+        assert!(result.should_copy(Path::new("archive.tar.gz")));
+        assert!(result.should_copy(Path::new("archive.TAR.BZ2")));
+        assert!(!result.should_copy(Path::new("archive.gz")));
+        assert!(!result.should_copy(Path::new("archive.bz2")));
+
+        let cli_args = CliArgs::parse_from(["filescraper", "source", "target", "--file-extensions", "gz"]);
+        let result = cli_args.convert().unwrap();
+        assert!(result.should_copy(Path::new("archive.gz")));
+        assert!(result.should_copy(Path::new("archive.tar.gz")));
+        assert!(!result.should_copy(Path::new("archive.tar.bz2")));
+
+        let cli_args = CliArgs::parse_from(["filescraper", "source", "target",
+            "--file-extensions", "tar.gz", "--file-extensions-mode", "ignore"]);
+        let result = cli_args.convert().unwrap();
+        assert!(!result.should_copy(Path::new("archive.tar.gz")));
+        assert!(result.should_copy(Path::new("archive.gz")));
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("644"), Ok(0o644));
+        assert_eq!(parse_mode("0755"), Ok(0o755));
+        assert_eq!(parse_mode("0o4750"), Ok(0o4750));
+        assert!(parse_mode("888").is_err());
+        assert!(parse_mode("17777").is_err());
+        assert!(parse_mode("rwx").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("45s"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration("12h"), Ok(Duration::from_secs(12 * 60 * 60)));
+        assert_eq!(parse_duration("7d"), Ok(Duration::from_secs(7 * 24 * 60 * 60)));
+        assert_eq!(parse_duration("2w"), Ok(Duration::from_secs(14 * 24 * 60 * 60)));
+        assert!(parse_duration("7").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("7y").is_err());
+        assert!(parse_duration("-7d").is_err());
+        assert!(parse_duration("99999999999999999999w").is_err());
+    }
+
+    #[test]
+    fn test_parse_point_in_time() {
+        let timestamp = parse_point_in_time("2024-01-31T12:00:00Z").unwrap();
+        assert_eq!(timestamp, SystemTime::UNIX_EPOCH + Duration::from_secs(1_706_702_400));
+        let with_offset = parse_point_in_time("2024-01-31T13:00:00+01:00").unwrap();
+        assert_eq!(with_offset, timestamp);
+        let before = SystemTime::now();
+        let relative = parse_point_in_time("1h").unwrap();
+        assert!(relative <= before - Duration::from_secs(3_600) + Duration::from_secs(1));
+        assert!(relative >= before - Duration::from_secs(3_601));
+        assert!(parse_point_in_time("2024-01-31").is_err());
+    }
+
+    #[test]
+    fn test_newer_than_boundary() {
+        let cli_args = CliArgs::parse_from(["filescraper", "source", "target", "--newer-than", "2024-01-31T12:00:00Z"]);
+        let result = cli_args.convert().unwrap();
+        let threshold = SystemTime::UNIX_EPOCH + Duration::from_secs(1_706_702_400);
+        assert!(result.has_time_limits());
+        assert!(result.is_within_time_limits(threshold));
+        assert!(result.is_within_time_limits(threshold + Duration::from_nanos(1)));
+        assert!(!result.is_within_time_limits(threshold - Duration::from_nanos(1)));
+    }
+
+    #[test]
+    fn test_time_window() {
+        let cli_args = CliArgs::parse_from(["filescraper", "source", "target",
+            "--newer-than", "2024-01-01T00:00:00Z", "--older-than", "2024-02-01T00:00:00Z"]);
+        let result = cli_args.convert().unwrap();
+        let january = SystemTime::UNIX_EPOCH + Duration::from_secs(1_704_067_200);
+        let february = SystemTime::UNIX_EPOCH + Duration::from_secs(1_706_745_600);
+        assert!(result.is_within_time_limits(january));
+        assert!(result.is_within_time_limits(february - Duration::from_nanos(1)));
+        assert!(!result.is_within_time_limits(february));
+        assert!(!result.is_within_time_limits(january - Duration::from_nanos(1)));
+
+        let cli_args = CliArgs::parse_from(["filescraper", "source", "target", "--older-than", "90d"]);
+        let result = cli_args.convert().unwrap();
+        assert!(!result.is_within_time_limits(SystemTime::now()));
+        assert!(result.is_within_time_limits(SystemTime::now() - Duration::from_secs(91 * 24 * 60 * 60)));
+    }
+
+    #[test]
+    fn test_empty_time_window() {
+        for (newer_than, older_than) in [("7d", "30d"), ("2024-01-01T00:00:00Z", "2024-01-01T00:00:00Z")] {
+            let cli_args = CliArgs::parse_from(["filescraper", "source", "target",
+                "--newer-than", newer_than, "--older-than", older_than]);
+            assert!(cli_args.convert().is_err(), "{} to {}", newer_than, older_than);
+        }
+        let cli_args = CliArgs::parse_from(["filescraper", "source", "target", "--newer-than", "30d", "--older-than", "7d"]);
+        assert!(cli_args.convert().is_ok());
+    }
+
+    #[test]
+    fn test_single_file_source() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let source = temp_dir.path().join("report.txt");
+        std::fs::write(&source, "report").unwrap();
+        std::fs::create_dir(temp_dir.path().join("existing.d")).unwrap();
+        let source_root = source.to_string_lossy().to_string();
+        let target_for = |target: &Path, flags: &[&str]| {
+            let target = target.to_string_lossy().to_string();
+            let mut args = vec!["filescraper", &source_root, &target];
+            args.extend_from_slice(flags);
+            let result = CliArgs::parse_from(args).convert().unwrap();
+            assert!(result.should_copy(&source));
+            result.transform_source_to_target_path(&source).unwrap()
+        };
+        assert_eq!(target_for(&temp_dir.path().join("existing.d"), &[]), temp_dir.path().join("existing.d").join("report.txt"));
+        assert_eq!(target_for(&temp_dir.path().join("backup"), &[]), temp_dir.path().join("backup").join("report.txt"));
+        assert_eq!(target_for(&temp_dir.path().join("copy.txt"), &[]), temp_dir.path().join("copy.txt"));
+        assert_eq!(target_for(&temp_dir.path().join("backup"), &["--folders", "Target docs"]), temp_dir.path().join("backup").join("report.txt"));
+        assert_eq!(target_for(&temp_dir.path().join("backup"), &["--target-template", "{ext}/{name}"]), temp_dir.path().join("backup").join("txt").join("report.txt"));
+        let with_separator = format!("{}{}", temp_dir.path().join("dir.d").to_string_lossy(), MAIN_SEPARATOR);
+        assert_eq!(target_for(Path::new(&with_separator), &[]), temp_dir.path().join("dir.d").join("report.txt"));
+    }
+
+    #[test]
+    fn test_exclude_from() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let source = temp_dir.path().join("source");
+        for folder in ["target/debug", "build", "src/build", "docs"] {
+            std::fs::create_dir_all(source.join(folder)).unwrap();
+        }
+        let ignore_file = temp_dir.path().join("shared.ignore");
+        std::fs::write(&ignore_file, "# build output\ntarget/\n/build\n*.log\n!keep.log\ndocs/*.tmp\nnotes\n").unwrap();
+        let cli_args = CliArgs::parse_from(["filescraper", &source.to_string_lossy(), "target",
+            "--exclude-from", &ignore_file.to_string_lossy()]);
+        let result = cli_args.convert().unwrap();
+        assert!(!result.should_copy(&source.join("target")));
+        assert!(!result.should_copy(&source.join("target").join("debug").join("app")));
+        assert!(!result.should_copy(&source.join("build").join("out.o")));
+        assert!(result.should_copy(&source.join("src").join("build").join("gen.rs")));
+        assert!(!result.should_copy(&source.join("src").join("debug.log")));
+        assert!(result.should_copy(&source.join("src").join("keep.log")));
+        assert!(!result.should_copy(&source.join("docs").join("draft.tmp")));
+        assert!(result.should_copy(&source.join("src").join("draft.tmp")));
+        assert!(!result.should_copy(&source.join("docs").join("notes")));
+        assert!(result.should_copy(&source.join("README.md")));
+        assert_eq!(result.skip_reason(&source.join("debug.log")), Some(SkipReason::ExcludeFrom));
+
+        let cli_args = CliArgs::parse_from(["filescraper", &source.to_string_lossy(), "target",
+            "--exclude-from", &temp_dir.path().join("missing.ignore").to_string_lossy()]);
+        assert!(cli_args.convert().is_err());
+    }
+
+    #[test]
+    fn test_skip_reason_of_type_ignores_the_filesystem() {
+        let cli_args = CliArgs::parse_from(["filescraper", "source", "target", "--folders", "Ignore build", "--file-extensions", "rs"]);
+        let result = cli_args.convert().unwrap();
+        assert!(!Path::new("source/missing/build").exists());
+        assert_eq!(result.skip_reason_of_type(Path::new("source/missing/build"), true), Some(SkipReason::Folder));
+        assert_eq!(result.skip_reason_of_type(Path::new("source/missing/src"), true), None);
+        assert_eq!(result.skip_reason_of_type(Path::new("source/missing/src"), false), Some(SkipReason::Extension));
+        assert!(result.should_copy_of_type(Path::new("source/missing/main.rs"), false));
+        assert!(!result.should_copy_of_type(Path::new("source/build/main.rs"), false));
+    }
+
+    #[test]
+    fn test_skip_reason() {
+        let cli_args = CliArgs::parse_from(["filescraper", "source", "target", "--file-extensions", "rs",
+            "--folders", "Ignore build", "--exclude-regex", "\\.bak", "--exclude-name", "lib.rs"]);
+        let result = cli_args.convert().unwrap();
+        assert_eq!(result.skip_reason(Path::new("source/main.rs")), None);
+        assert_eq!(result.skip_reason(Path::new("source/main.rs.bak")), Some(SkipReason::ExcludeRegex));
+        assert_eq!(result.skip_reason(Path::new("source/build/out.rs")), Some(SkipReason::Folder));
+        assert_eq!(result.skip_reason(Path::new("source/lib.rs")), Some(SkipReason::Name));
+        assert_eq!(result.skip_reason(Path::new("source/README.md")), Some(SkipReason::Extension));
+    }
+
+    #[test]
+    fn test_include_and_exclude_names() {
+        let cli_args = CliArgs::parse_from(["filescraper", "source", "target", "--file-extensions", "rs",
+            "--include-name", "Dockerfile", "--include-name", "Makefile", "--include-name", ".gitignore",
+            "--exclude-name", "build.rs", "--exclude-name", "Makefile"]);
+        let result = cli_args.convert().unwrap();
+        assert!(result.should_copy(Path::new("source/main.rs")));
+        assert!(result.should_copy(Path::new("source/docker/Dockerfile")));
+        assert!(result.should_copy(Path::new("source/.gitignore")));
+        assert!(!result.should_copy(Path::new("source/build.rs")));
+        assert!(!result.should_copy(Path::new("source/Makefile")));
+        assert!(!result.should_copy(Path::new("source/Dockerfile.dev")));
+        assert!(!result.should_copy(Path::new("source/dockerfile")));
+        assert!(!result.should_copy(Path::new("source/README.md")));
+    }
+
+    #[test]
+    fn test_confirm_threshold() {
+        let default = ConfirmThreshold::default();
+        assert!(!default.is_exceeded(100_000, 1024));
+        assert!(default.is_exceeded(100_001, 1024));
+        assert!(default.is_exceeded(1, ByteSize::gb(11).as_u64()));
+        let files = ConfirmThreshold::parse("10").unwrap();
+        assert!(files.is_exceeded(11, 0));
+        assert!(!files.is_exceeded(10, u64::MAX));
+        let bytes = ConfirmThreshold::parse("1M").unwrap();
+        assert!(bytes.is_exceeded(1, 1_000_001));
+        assert!(!bytes.is_exceeded(usize::MAX, 1_000_000));
+        assert!(ConfirmThreshold::parse("many").is_err());
+    }
+
+    #[test]
+    fn test_max_rate() {
+        let cli_args = CliArgs::parse_from(["filescraper", "source", "target", "--max-rate", "10M"]);
+        let result = cli_args.convert().unwrap();
+        assert_eq!(result.rate_limiter.unwrap().bytes_per_second(), 10_000_000);
+        let cli_args = CliArgs::parse_from(["filescraper", "source", "target", "--max-rate", "0"]);
+        assert!(cli_args.convert().is_err());
+    }
+
+    #[test]
+    fn test_simulate_error_rate() {
+        let parse = |flags: &[&str]| {
+            let mut cli = vec!["filescraper", "source", "target"];
+            cli.extend_from_slice(flags);
+            CliArgs::parse_from(cli).convert()
+        };
+        assert!(parse(&["--simulate-error-rate", "1.5"]).is_err());
+        assert!(parse(&["--simulate-error-rate=-0.1"]).is_err());
+        assert!(parse(&[]).unwrap().simulate_errors.is_none());
+        let never = parse(&["--simulate-error-rate", "0"]).unwrap().simulate_errors.unwrap();
+        let always = parse(&["--simulate-error-rate", "1"]).unwrap().simulate_errors.unwrap();
+        let half = parse(&["--simulate-error-rate", "0.5", "--simulate-seed", "7"]).unwrap().simulate_errors.unwrap();
+        let paths: Vec<PathBuf> = (0..1000).map(|index| PathBuf::from(format!("source/{}.txt", index))).collect();
+        assert!(paths.iter().all(|path| !never.fails(path)));
+        assert!(paths.iter().all(|path| always.fails(path)));
+        let failing = paths.iter().filter(|path| half.fails(path)).count();
+        assert!((400..600).contains(&failing), "{} of 1000 failed", failing);
+        let same_seed = parse(&["--simulate-error-rate", "0.5", "--simulate-seed", "7"]).unwrap().simulate_errors.unwrap();
+        assert!(paths.iter().all(|path| half.fails(path) == same_seed.fails(path)));
+    }
+
+    #[test]
+    fn test_progress_to() {
+        let progress_to = |flags: &[&str]| {
+            let mut cli = vec!["filescraper", "source", "target"];
+            cli.extend_from_slice(flags);
+            CliArgs::parse_from(cli).convert().unwrap().progress_to
+        };
+        assert_eq!(progress_to(&[]), ProgressTarget::Stderr);
+        assert_eq!(progress_to(&["--progress-to", "stdout"]), ProgressTarget::Stdout);
+        assert_eq!(progress_to(&["--progress-to", "none"]), ProgressTarget::Hidden);
+        assert_eq!(progress_to(&["--progress-to", "stdout", "--quiet"]), ProgressTarget::Hidden);
+        for output in ["--summary-json", "--list-only"] {
+            assert_eq!(progress_to(&[output]), ProgressTarget::Stderr);
+            assert_eq!(progress_to(&[output, "--progress-to", "stderr"]), ProgressTarget::Stderr);
+            assert_eq!(progress_to(&[output, "--progress-to", "none"]), ProgressTarget::Hidden);
+            assert!(CliArgs::parse_from(["filescraper", "--progress-to", "stdout", output, "source", "target"]).convert().is_err());
+        }
+    }
+
+    #[test]
+    fn test_max_open_files() {
+        assert!(CliArgs::parse_from(["filescraper", "source", "target", "--max-open-files", "0"]).convert().is_err());
+        let result = CliArgs::parse_from(["filescraper", "source", "target", "--max-open-files", "8"]).convert().unwrap();
+        assert_eq!(result.max_open_files, Some(8));
+    }
+
+    #[test]
+    fn test_copy_buffer_size() {
+        let cli_args = CliArgs::parse_from(["filescraper", "source", "target", "--copy-buffer-size", "8MiB"]);
+        assert_eq!(cli_args.convert().unwrap().copy_buffer_size, Some(8 * 1024 * 1024));
+        let cli_args = CliArgs::parse_from(["filescraper", "source", "target"]);
+        assert_eq!(cli_args.convert().unwrap().copy_buffer_size, None);
+        let cli_args = CliArgs::parse_from(["filescraper", "source", "target", "--copy-buffer-size", "0"]);
+        assert!(cli_args.convert().is_err());
+    }
+
+    #[test]
+    fn test_multiple_source_roots() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let first = temp_dir.path().join("first");
+        let second = temp_dir.path().join("second");
+        std::fs::create_dir_all(first.join("nested")).unwrap();
+        std::fs::create_dir_all(second.join("nested")).unwrap();
+        let first_root = first.to_string_lossy().to_string();
+        let second_root = second.to_string_lossy().to_string();
+        let cli_args = CliArgs::parse_from(["filescraper", &first_root, "target", "--source", &second_root]);
+        let result = cli_args.convert().unwrap();
+        assert_eq!(result.source_roots, vec![first_root.clone(), second_root.clone()]);
+        assert_eq!(result.source_root_of(&second.join("nested").join("a.txt")), second_root);
+        assert_eq!(result.transform_source_to_target_path(&second.join("nested").join("a.txt")).unwrap(),
+                   Path::new("target").join("nested").join("a.txt"));
+        assert_eq!(result.transform_source_to_target_path(&first.join("b.txt")).unwrap(), Path::new("target").join("b.txt"));
+
+        let nested_root = first.join("nested").to_string_lossy().to_string();
+        let cli_args = CliArgs::parse_from(["filescraper", &first_root, "target", "--source", &nested_root]);
+        assert!(cli_args.convert().is_err());
+        let inside_second = second.join("out").to_string_lossy().to_string();
+        let cli_args = CliArgs::parse_from(["filescraper", &first_root, &inside_second, "--source", &second_root]);
+        assert!(cli_args.convert().is_err());
+    }
+
+    #[test]
+    fn test_should_copy_exclude_regex() {
+        let cli_args = CliArgs {
+            source_root_file_path: Some("source".to_string()),
+            target_root_file_path: Some("target".to_string()),
+            exclude_regex: vec!["\\.bak\\.[^.]+$".to_string(), "__pycache__".to_string()],
+            ..Default::default()
+        };
+        let result = cli_args.convert().unwrap();
+        assert!(!result.should_copy(Path::new("notes.bak.txt")));
+        assert!(!result.should_copy(Path::new("src/__pycache__/module.pyc")));
+        assert!(result.should_copy(Path::new("notes.txt")));
+        assert!(result.should_copy(Path::new("src/module.py")));
+    }
+
+    #[test]
+    fn test_should_copy_include_regex() {
+        let cli_args = CliArgs {
+            source_root_file_path: Some("source".to_string()),
+            target_root_file_path: Some("target".to_string()),
+            include_regex: vec!["^photos/".to_string(), "\\.pdf$".to_string()],
+            exclude_regex: vec!["draft".to_string()],
+            ..Default::default()
+        };
+        let result = cli_args.convert().unwrap();
+        assert!(result.should_copy(Path::new("photos/holiday.jpg")));
+        assert!(result.should_copy(Path::new("docs/report.pdf")));
+        assert!(!result.should_copy(Path::new("docs/report.txt")));
+        assert!(!result.should_copy(Path::new("docs/draft.pdf")));
+    }
+
+    #[test]
+    fn test_should_copy_empty_include_regex() {
+        let cli_args = CliArgs {
+            source_root_file_path: Some("source".to_string()),
+            target_root_file_path: Some("target".to_string()),
+            ..Default::default()
+        };
+        let result = cli_args.convert().unwrap();
+        assert!(result.include_regex.is_empty());
+        assert!(result.should_copy(Path::new("docs/report.txt")));
+    }
+
+    #[test]
+    fn test_invalid_exclude_regex() {
+        let cli_args = CliArgs {
+            source_root_file_path: Some("source".to_string()),
+            target_root_file_path: Some("target".to_string()),
+            exclude_regex: vec!["(unclosed".to_string()],
+            ..Default::default()
+        };
+        let err = cli_args.convert().err().unwrap();
+        assert!(err.to_string().contains("(unclosed"));
+    }
+
+    #[test]
+    fn test_overwrite_policy_if_newer() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let older = temp_dir.path().join("older.txt");
+        let newer = temp_dir.path().join("newer.txt");
+        let missing = temp_dir.path().join("missing.txt");
+        std::fs::write(&older, "older").unwrap();
+        std::fs::write(&newer, "newer").unwrap();
+        let now = SystemTime::now();
+        File::options().write(true).open(&older).unwrap().set_modified(now - Duration::from_secs(60)).unwrap();
+        File::options().write(true).open(&newer).unwrap().set_modified(now).unwrap();
+        assert!(OverwritePolicy::IfNewer.allows_writing(&newer, &older));
+        assert!(!OverwritePolicy::IfNewer.allows_writing(&older, &newer));
+        assert!(OverwritePolicy::IfNewer.allows_writing(&older, &missing));
+        assert!(!OverwritePolicy::Never.allows_writing(&newer, &older));
+        assert!(OverwritePolicy::Never.allows_writing(&newer, &missing));
+        assert!(OverwritePolicy::Always.allows_writing(&older, &newer));
+        assert!(OverwritePolicy::Rename.allows_writing(&older, &newer));
+    }
+
     #[test]
     fn test_transform_source_to_target_path() {
         let source_root_path = "test/bin";
         let target_root_path = "tar/bin2";
         let path = Path::new("test/bin/path");
-        let result = transform_source_to_target_path(source_root_path, target_root_path, path);
+        let result = transform_source_to_target_path(source_root_path, target_root_path, path).unwrap();
         let path = result.to_str().unwrap();
         assert_eq!(path, "tar/bin2/path");
     }
 
+    #[test]
+    fn test_route() {
+        let result = CliArgs::parse_from(["filescraper", "source", "target", "--route", "jpg=images", "--route", ".PDF=docs/pdf",
+            "--route", "tar.gz=archives", "--route", "gz=compressed"]).convert().unwrap();
+        let target_of = |path: &str| result.transform_source_to_target_path(Path::new(path)).unwrap();
+        assert_eq!(target_of("source/holiday/beach.jpg"), Path::new("target/images/holiday/beach.jpg"));
+        assert_eq!(target_of("source/Scan.PDF"), Path::new("target/docs/pdf/Scan.PDF"));
+        assert_eq!(target_of("source/backup.tar.gz"), Path::new("target/archives/backup.tar.gz"));
+        assert_eq!(target_of("source/log.gz"), Path::new("target/compressed/log.gz"));
+        assert_eq!(target_of("source/notes/todo.txt"), Path::new("target/notes/todo.txt"));
+        assert_eq!(target_of("source/jpg"), Path::new("target/jpg"));
+
+        let result = CliArgs::parse_from(["filescraper", "source", "target", "--route", "jpg=images", "--flatten"]).convert().unwrap();
+        assert_eq!(result.transform_source_to_target_path(Path::new("source/holiday/beach.jpg")).unwrap(), Path::new("target/images/beach.jpg"));
+        assert_eq!(result.transform_source_to_target_path(Path::new("source/notes/todo.txt")).unwrap(), Path::new("target/todo.txt"));
+    }
+
+    #[test]
+    fn test_parse_route() {
+        assert_eq!(parse_route("jpg=images"), Ok(("jpg".to_string(), "images".to_string())));
+        assert_eq!(parse_route(" .pdf = docs/pdf "), Ok((".pdf".to_string(), "docs/pdf".to_string())));
+        assert!(parse_route("jpg").is_err());
+        assert!(parse_route("=images").is_err());
+        assert!(parse_route("jpg=").is_err());
+        assert!(parse_route("jpg=../outside").is_err());
+        assert!(parse_route("jpg=/absolute").is_err());
+    }
+
+    #[test]
+    fn test_transform_source_to_target_path_outside_source_root() {
+        let path = Path::new("elsewhere/path");
+        let result = transform_source_to_target_path("test/bin", "tar/bin2", path);
+        assert!(result.is_err());
+    }
+
     trait PathBufExt {
         fn joined(self, suffix: &Path) -> PathBuf;
     }
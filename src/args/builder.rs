@@ -0,0 +1,251 @@
+use std::ffi::OsString;
+use std::path::Path;
+
+use bytesize::ByteSize;
+use clap::{Parser, ValueEnum};
+
+use crate::args::{Arguments, CliArgs, Mode, OptionalHandling, OverwritePolicy, TargetMode};
+
+/// Builds [Arguments] for embedding the copy into other programs, without assembling [CliArgs] by hand.
+/// Every setter corresponds to a command line option and [ArgumentsBuilder::build] validates them just like the
+/// command line, so conflicting options are rejected and all other options keep their command line defaults.
+/// Setting a single-valued option again replaces its value
+///
+/// ```
+/// use filescraper::args::ArgumentsBuilder;
+///
+/// let source = tempfile::tempdir().unwrap();
+/// let target = tempfile::tempdir().unwrap();
+/// std::fs::write(source.path().join("photo.jpg"), "jpg").unwrap();
+/// std::fs::write(source.path().join("notes.txt"), "txt").unwrap();
+/// let args = ArgumentsBuilder::new(source.path(), target.path().join("backup"))
+///     .target_extensions(["jpg"])
+///     .ignore_folders(["cache", "My Documents"])
+///     .quiet(true)
+///     .build()
+///     .unwrap();
+/// let files = filescraper::gather_files_for_copying(&args);
+/// let report = filescraper::copy(args, files).unwrap();
+/// assert_eq!(report.copied, 1);
+/// assert!(target.path().join("backup").join("photo.jpg").is_file());
+/// ```
+///
+/// Invalid combinations are reported by [ArgumentsBuilder::build]:
+///
+/// ```
+/// use filescraper::args::ArgumentsBuilder;
+///
+/// let result = ArgumentsBuilder::new("source", "target").min_depth(3).max_depth(1).build();
+/// assert!(result.is_err());
+/// ```
+#[derive(Clone, Debug)]
+pub struct ArgumentsBuilder {
+    options: Vec<OsString>,
+    roots: Vec<OsString>,
+    /// Set on the parsed arguments directly, as the quoting of `--folders` can not express every folder name
+    folders: Option<OptionalHandling>,
+}
+
+impl ArgumentsBuilder {
+    /// Starts arguments that copy everything below `source` to `target`
+    pub fn new(source: impl AsRef<Path>, target: impl AsRef<Path>) -> ArgumentsBuilder {
+        ArgumentsBuilder {
+            options: Vec::new(),
+            roots: vec![source.as_ref().into(), target.as_ref().into()],
+            folders: None,
+        }
+    }
+
+    /// Adds another source root merged into the same target, like `--source`
+    pub fn additional_source(self, source: impl AsRef<Path>) -> ArgumentsBuilder {
+        self.push("--source", source.as_ref())
+    }
+
+    /// Only copies files with one of these extensions, like `--file-extensions`
+    pub fn target_extensions<S: AsRef<str>>(self, extensions: impl IntoIterator<Item=S>) -> ArgumentsBuilder {
+        extensions.into_iter().fold(self, |builder, extension| builder.push("--file-extensions", extension.as_ref()))
+    }
+
+    /// Never copies files with one of these extensions, like `--exclude-extensions`
+    pub fn ignore_extensions<S: AsRef<str>>(self, extensions: impl IntoIterator<Item=S>) -> ArgumentsBuilder {
+        extensions.into_iter().fold(self, |builder, extension| builder.push("--exclude-extensions", extension.as_ref()))
+    }
+
+    /// Skips these folders, like `--folders "Ignore ..."`. Folder names are taken as they are, spaces and quotes
+    /// included. As there is only one `--folders` this replaces earlier [ArgumentsBuilder::target_folders]
+    pub fn ignore_folders<S: AsRef<str>>(self, folders: impl IntoIterator<Item=S>) -> ArgumentsBuilder {
+        self.folders(TargetMode::Ignore, folders)
+    }
+
+    /// Only copies these folders, like `--folders "Target ..."`, replacing earlier [ArgumentsBuilder::ignore_folders]
+    pub fn target_folders<S: AsRef<str>>(self, folders: impl IntoIterator<Item=S>) -> ArgumentsBuilder {
+        self.folders(TargetMode::Target, folders)
+    }
+
+    fn folders<S: AsRef<str>>(mut self, target: TargetMode, folders: impl IntoIterator<Item=S>) -> ArgumentsBuilder {
+        let values = folders.into_iter().map(|folder| folder.as_ref().to_string()).collect();
+        self.folders = Some(OptionalHandling { target, values });
+        self
+    }
+
+    /// Whether symbolic links are followed, like `--follow-links`
+    pub fn follow_links(self, follow_links: bool) -> ArgumentsBuilder {
+        self.flag("--follow-links", follow_links)
+    }
+
+    /// Leaves out symbolic links, like `--exclude-symlinks`
+    pub fn exclude_symlinks(self, exclude_symlinks: bool) -> ArgumentsBuilder {
+        self.flag("--exclude-symlinks", exclude_symlinks)
+    }
+
+    /// Only copies symbolic links, like `--only-symlinks`
+    pub fn only_symlinks(self, only_symlinks: bool) -> ArgumentsBuilder {
+        self.flag("--only-symlinks", only_symlinks)
+    }
+
+    /// Like `--max-depth`
+    pub fn max_depth(self, max_depth: usize) -> ArgumentsBuilder {
+        self.option("--max-depth", max_depth.to_string())
+    }
+
+    /// Like `--min-depth`
+    pub fn min_depth(self, min_depth: usize) -> ArgumentsBuilder {
+        self.option("--min-depth", min_depth.to_string())
+    }
+
+    /// Stops the discovery after this many files, like `--max-files`
+    pub fn max_files(self, max_files: usize) -> ArgumentsBuilder {
+        self.option("--max-files", max_files.to_string())
+    }
+
+    /// Like `--min-size`
+    pub fn min_size(self, min_size: ByteSize) -> ArgumentsBuilder {
+        self.option("--min-size", min_size.as_u64().to_string())
+    }
+
+    /// Like `--max-size`
+    pub fn max_size(self, max_size: ByteSize) -> ArgumentsBuilder {
+        self.option("--max-size", max_size.as_u64().to_string())
+    }
+
+    /// How the files get to the target, like `--mode`
+    pub fn mode(self, mode: Mode) -> ArgumentsBuilder {
+        self.value_enum("--mode", mode)
+    }
+
+    /// Like `--overwrite`
+    pub fn overwrite(self, overwrite: OverwritePolicy) -> ArgumentsBuilder {
+        self.value_enum("--overwrite", overwrite)
+    }
+
+    /// Only logs what would be copied, like `--dry-run`
+    pub fn dry_run(self, dry_run: bool) -> ArgumentsBuilder {
+        self.flag("--dry-run", dry_run)
+    }
+
+    /// Like `--threads`
+    pub fn threads(self, threads: usize) -> ArgumentsBuilder {
+        self.option("--threads", threads.to_string())
+    }
+
+    /// Hides the progress bar and only logs errors, like `--quiet`
+    pub fn quiet(self, quiet: bool) -> ArgumentsBuilder {
+        self.flag("--quiet", quiet)
+    }
+
+    /// Copies without asking for confirmation, like `--yes`
+    pub fn yes(self, yes: bool) -> ArgumentsBuilder {
+        self.flag("--yes", yes)
+    }
+
+    /// Validates the options like the command line does
+    pub fn build(self) -> anyhow::Result<Arguments> {
+        let cli = std::iter::once(OsString::from("filescraper"))
+            .chain(self.options)
+            .chain(std::iter::once(OsString::from("--")))
+            .chain(self.roots);
+        let mut cli_args = CliArgs::try_parse_from(cli)?;
+        if self.folders.is_some() {
+            cli_args.folders = self.folders;
+        }
+        cli_args.convert()
+    }
+
+    fn flag(mut self, flag: &str, set: bool) -> ArgumentsBuilder {
+        self.options.retain(|option| option != flag);
+        if set {
+            self.options.push(flag.into());
+        }
+        self
+    }
+
+    fn value_enum(self, option: &str, value: impl ValueEnum) -> ArgumentsBuilder {
+        let value = value.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default();
+        self.option(option, value)
+    }
+
+    /// Sets an option taking a single value, replacing the value set before
+    fn option(mut self, option: &str, value: impl Into<OsString>) -> ArgumentsBuilder {
+        let prefix = format!("{}=", option);
+        self.options.retain(|existing| !existing.to_string_lossy().starts_with(&prefix));
+        self.push(option, value)
+    }
+
+    /// Adds another value to an option that can be passed multiple times
+    fn push(mut self, option: &str, value: impl Into<OsString>) -> ArgumentsBuilder {
+        let mut option_with_value = OsString::from(format!("{}=", option));
+        option_with_value.push(value.into());
+        self.options.push(option_with_value);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use bytesize::ByteSize;
+
+    use crate::args::{ArgumentsBuilder, FolderFilterMode, Mode};
+
+    #[test]
+    fn test_setting_options_again_replaces_them() {
+        let args = ArgumentsBuilder::new("source", "target")
+            .mode(Mode::Hardlink)
+            .mode(Mode::Move)
+            .dry_run(true)
+            .dry_run(false)
+            .target_folders(["docs"])
+            .ignore_folders(["My Documents", "cache"])
+            .target_extensions(["jpg"])
+            .target_extensions(["png"])
+            .min_size(ByteSize::kib(1))
+            .build()
+            .unwrap();
+        assert_eq!(args.mode, Mode::Move);
+        assert_eq!(args.min_size, Some(1024));
+        assert!(!args.dry_run);
+        assert!(!args.should_copy(Path::new("source").join("My Documents").join("a.png").as_path()));
+        assert!(args.should_copy(Path::new("source").join("docs").join("a.jpg").as_path()));
+        assert!(!args.should_copy(Path::new("source").join("docs").join("a.txt").as_path()));
+        assert!(matches!(args.folders, FolderFilterMode::Ignored(_)));
+    }
+
+    #[test]
+    fn test_folder_names_are_taken_as_they_are() {
+        let args = ArgumentsBuilder::new("source", "target")
+            .ignore_folders(["say \"cheese\"", "\""])
+            .build()
+            .unwrap();
+        assert!(!args.should_copy_of_type(Path::new("source").join("say \"cheese\"").as_path(), true));
+        assert!(!args.should_copy_of_type(Path::new("source").join("\"").as_path(), true));
+        assert!(args.should_copy_of_type(Path::new("source").join("cheese").as_path(), true));
+    }
+
+    #[test]
+    fn test_symlink_setters() {
+        let args = ArgumentsBuilder::new("source", "target").only_symlinks(true).build().unwrap();
+        assert!(args.only_symlinks);
+        assert!(ArgumentsBuilder::new("source", "target").only_symlinks(true).exclude_symlinks(true).build().is_err());
+    }
+}
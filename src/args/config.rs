@@ -0,0 +1,167 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use bytesize::ByteSize;
+use serde::Deserialize;
+
+use crate::args::{CliArgs, parse_special_options};
+
+/// The subset of [CliArgs] that can be stored in a TOML config file. Filters use the same
+/// `"<Ignore|Target> <values...>"` notation as on the command line
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    source: Option<String>,
+    target: Option<String>,
+    file_extensions: Option<String>,
+    exclude_extensions: Option<Vec<String>>,
+    folders: Option<String>,
+    follow_links: Option<bool>,
+    max_depth: Option<usize>,
+    min_depth: Option<usize>,
+    min_size: Option<ByteSize>,
+    max_size: Option<ByteSize>,
+}
+
+impl CliArgs {
+    /// Loads the arguments stored in the TOML file at `path`, all options not present in the file keep their defaults
+    pub fn from_config(path: &Path) -> anyhow::Result<CliArgs> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read config file {}", path.to_string_lossy()))?;
+        let config: ConfigFile = toml::from_str(&content)
+            .with_context(|| format!("Could not parse config file {}", path.to_string_lossy()))?;
+        let file_extensions = config.file_extensions
+            .map(|file_extensions| parse_special_options(&file_extensions))
+            .transpose()
+            .map_err(|err| anyhow!("Invalid file_extensions in config file: {}", err))?;
+        let (file_extensions_mode, file_extensions) = match file_extensions {
+            Some(file_extensions) => { (Some(file_extensions.target), file_extensions.values) }
+            None => { (None, Vec::new()) }
+        };
+        let folders = config.folders
+            .map(|folders| parse_special_options(&folders))
+            .transpose()
+            .map_err(|err| anyhow!("Invalid folders in config file: {}", err))?;
+        Ok(CliArgs {
+            source_root_file_path: config.source,
+            target_root_file_path: config.target,
+            file_extensions,
+            file_extensions_mode,
+            exclude_extensions: config.exclude_extensions.unwrap_or_default(),
+            folders,
+            follow_links: config.follow_links.unwrap_or(false),
+            max_depth: config.max_depth,
+            min_depth: config.min_depth,
+            min_size: config.min_size,
+            max_size: config.max_size,
+            ..Default::default()
+        })
+    }
+
+    /// Fills every option that was not explicitly passed on the command line with the value from `config`
+    pub(crate) fn merged_with(&self, config: CliArgs) -> CliArgs {
+        let mut merged = self.clone();
+        merged.config = None;
+        merged.source_root_file_path = merged.source_root_file_path.or(config.source_root_file_path);
+        merged.target_root_file_path = merged.target_root_file_path.or(config.target_root_file_path);
+        if merged.file_extensions.is_empty() {
+            merged.file_extensions = config.file_extensions;
+        }
+        merged.file_extensions_mode = merged.file_extensions_mode.or(config.file_extensions_mode);
+        if merged.exclude_extensions.is_empty() {
+            merged.exclude_extensions = config.exclude_extensions;
+        }
+        merged.folders = merged.folders.or(config.folders);
+        merged.follow_links = match (self.follow_links, self.no_follow_links) {
+            (true, _) => { true }
+            (false, true) => { false }
+            (false, false) => { config.follow_links }
+        };
+        merged.max_depth = merged.max_depth.or(config.max_depth);
+        merged.min_depth = merged.min_depth.or(config.min_depth);
+        merged.min_size = merged.min_size.or(config.min_size);
+        merged.max_size = merged.max_size.or(config.max_size);
+        merged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use crate::args::CliArgs;
+    use crate::args::TargetMode::{Ignore, Target};
+
+    const SAMPLE_CONFIG: &str = r#"
+source = "data"
+target = "backup"
+file_extensions = "Target jpg png"
+exclude_extensions = ["thumb.jpg"]
+folders = "Ignore cache"
+follow_links = true
+max_depth = 4
+min_size = "1K"
+"#;
+
+    #[test]
+    fn test_from_config() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let config_path = temp_dir.path().join("filescraper.toml");
+        std::fs::write(&config_path, SAMPLE_CONFIG).unwrap();
+        let cli_args = CliArgs::from_config(&config_path).unwrap();
+        assert_eq!(cli_args.source_root_file_path.as_deref(), Some("data"));
+        assert_eq!(cli_args.target_root_file_path.as_deref(), Some("backup"));
+        assert_eq!(cli_args.file_extensions_mode, Some(Target));
+        assert_eq!(cli_args.file_extensions, vec!["jpg", "png"]);
+        assert_eq!(cli_args.exclude_extensions, vec!["thumb.jpg"]);
+        assert_eq!(cli_args.folders.clone().unwrap().target, Ignore);
+        assert!(cli_args.follow_links);
+        assert_eq!(cli_args.max_depth, Some(4));
+        assert_eq!(cli_args.min_size.map(|size| size.as_u64()), Some(1_000));
+        let args = cli_args.convert().unwrap();
+        assert_eq!(args.source_root_file_path, "data");
+        assert_eq!(args.min_size, Some(1_000));
+    }
+
+    #[test]
+    fn test_cli_wins_over_config() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let config_path = temp_dir.path().join("filescraper.toml");
+        std::fs::write(&config_path, SAMPLE_CONFIG).unwrap();
+        let cli_args = CliArgs {
+            target_root_file_path: Some("elsewhere".to_string()),
+            max_depth: Some(1),
+            config: Some(config_path),
+            ..Default::default()
+        };
+        let args = cli_args.convert().unwrap();
+        assert_eq!(args.source_root_file_path, "data");
+        assert_eq!(args.target_root_file_path, "elsewhere");
+        assert_eq!(args.max_depth, Some(1));
+        assert!(args.follow_links);
+    }
+
+    #[test]
+    fn test_no_follow_links_wins_over_config() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let config_path = temp_dir.path().join("filescraper.toml");
+        std::fs::write(&config_path, SAMPLE_CONFIG).unwrap();
+        let follow_links = |flags: &[&str]| {
+            let mut cli = vec!["filescraper", "--config", config_path.to_str().unwrap()];
+            cli.extend_from_slice(flags);
+            CliArgs::parse_from(cli).convert().unwrap().follow_links
+        };
+        assert!(follow_links(&[]));
+        assert!(!follow_links(&["--no-follow-links"]));
+        assert!(follow_links(&["--no-follow-links", "--follow-links"]));
+        assert!(!follow_links(&["--follow-links", "--no-follow-links"]));
+    }
+
+    #[test]
+    fn test_unknown_config_keys_are_rejected() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let config_path = temp_dir.path().join("filescraper.toml");
+        std::fs::write(&config_path, "sauce = \"data\"").unwrap();
+        assert!(CliArgs::from_config(&config_path).is_err());
+    }
+}
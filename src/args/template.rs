@@ -0,0 +1,200 @@
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use chrono::{DateTime, Datelike, Local};
+
+/// A destination layout like `{year}/{ext}/{name}`, expanded for every copied file below the target root
+#[derive(Clone, PartialEq, Debug)]
+pub struct TargetTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum TemplatePart {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Placeholder {
+    /// The file name including its extension
+    Name,
+    /// The file name without its extension
+    Stem,
+    /// The extension without its dot, empty for files without one
+    Ext,
+    /// The directory of the file relative to the source root
+    Dir,
+    /// The year, month and day the file was last modified in local time
+    Year,
+    Month,
+    Day,
+}
+
+impl Placeholder {
+    fn parse(name: &str) -> Option<Placeholder> {
+        match name {
+            "name" => { Some(Placeholder::Name) }
+            "stem" => { Some(Placeholder::Stem) }
+            "ext" => { Some(Placeholder::Ext) }
+            "dir" => { Some(Placeholder::Dir) }
+            "year" => { Some(Placeholder::Year) }
+            "month" => { Some(Placeholder::Month) }
+            "day" => { Some(Placeholder::Day) }
+            _ => { None }
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Placeholder::Name => { "name" }
+            Placeholder::Stem => { "stem" }
+            Placeholder::Ext => { "ext" }
+            Placeholder::Dir => { "dir" }
+            Placeholder::Year => { "year" }
+            Placeholder::Month => { "month" }
+            Placeholder::Day => { "day" }
+        }
+    }
+
+    fn needs_modification_time(&self) -> bool {
+        matches!(self, Placeholder::Year | Placeholder::Month | Placeholder::Day)
+    }
+}
+
+impl TargetTemplate {
+    /// Parses a template made of literal text and the placeholders `{name}`, `{stem}`, `{ext}`, `{dir}`,
+    /// `{year}`, `{month}` and `{day}`. Templates escaping the target root are rejected
+    pub fn parse(template: &str) -> Result<TargetTemplate, String> {
+        if Path::new(template).components().any(|component| !matches!(component, Component::Normal(_) | Component::CurDir)) {
+            return Err(format!("The target template '{}' must stay below the target root", template));
+        }
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(TemplatePart::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..].find('}')
+                .ok_or_else(|| format!("Unclosed placeholder in the target template '{}'", template))?;
+            let name = &rest[start + 1..start + end];
+            let placeholder = Placeholder::parse(name)
+                .ok_or_else(|| format!("Unknown placeholder {{{}}} in the target template '{}'", name, template))?;
+            parts.push(TemplatePart::Placeholder(placeholder));
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Literal(rest.to_string()));
+        }
+        Ok(TargetTemplate { parts })
+    }
+
+    /// Expands the template for `relative_path`, the source file's path below the source root. The
+    /// modification time is only read if a date placeholder is used. Only `{dir}` may expand to nothing, for
+    /// files right in the source root, so a file without an extension can not silently lose its `{ext}` folder
+    pub fn expand(&self, relative_path: &Path, modified: impl FnOnce() -> std::io::Result<SystemTime>) -> std::io::Result<PathBuf> {
+        let needs_date = self.parts.iter()
+            .any(|part| matches!(part, TemplatePart::Placeholder(placeholder) if placeholder.needs_modification_time()));
+        let date = if needs_date { Some(DateTime::<Local>::from(modified()?)) } else { None };
+        let lossy = |value: Option<&std::ffi::OsStr>| value.map(|value| value.to_string_lossy().to_string()).unwrap_or_default();
+        let mut expanded = String::new();
+        for part in &self.parts {
+            let value = match part {
+                TemplatePart::Literal(literal) => { literal.clone() }
+                TemplatePart::Placeholder(Placeholder::Name) => { lossy(relative_path.file_name()) }
+                TemplatePart::Placeholder(Placeholder::Stem) => { lossy(relative_path.file_stem()) }
+                TemplatePart::Placeholder(Placeholder::Ext) => { lossy(relative_path.extension()) }
+                TemplatePart::Placeholder(Placeholder::Dir) => { lossy(relative_path.parent().map(|parent| parent.as_os_str())) }
+                TemplatePart::Placeholder(Placeholder::Year) => { date.map(|date| format!("{:04}", date.year())).unwrap_or_default() }
+                TemplatePart::Placeholder(Placeholder::Month) => { date.map(|date| format!("{:02}", date.month())).unwrap_or_default() }
+                TemplatePart::Placeholder(Placeholder::Day) => { date.map(|date| format!("{:02}", date.day())).unwrap_or_default() }
+            };
+            if let TemplatePart::Placeholder(placeholder) = part {
+                if value.is_empty() && *placeholder != Placeholder::Dir {
+                    return Err(empty_expansion(&format!("{{{}}}", placeholder.name()), relative_path));
+                }
+            }
+            expanded.push_str(&value);
+        }
+        // An empty leading `{dir}` leaves a separator at the start, which must not make the path absolute
+        let expanded: PathBuf = Path::new(&expanded).components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect();
+        if expanded.file_name().is_none() {
+            return Err(empty_expansion("the target template", relative_path));
+        }
+        Ok(expanded)
+    }
+}
+
+fn empty_expansion(what: &str, relative_path: &Path) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidInput, format!("{} expands to nothing for {}", what, relative_path.to_string_lossy()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::time::SystemTime;
+
+    use chrono::{Local, TimeZone};
+
+    use crate::args::template::TargetTemplate;
+
+    fn expand(template: &str, relative_path: &str) -> PathBuf {
+        let modified = Local.with_ymd_and_hms(2023, 4, 9, 12, 0, 0).unwrap();
+        TargetTemplate::parse(template).unwrap()
+            .expand(Path::new(relative_path), || Ok(SystemTime::from(modified)))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_placeholders() {
+        assert_eq!(expand("{name}", "photos/holiday.jpg"), Path::new("holiday.jpg"));
+        assert_eq!(expand("{stem}", "photos/holiday.jpg"), Path::new("holiday"));
+        assert_eq!(expand("{ext}", "photos/holiday.jpg"), Path::new("jpg"));
+        assert_eq!(expand("{dir}", "photos/2023/holiday.jpg"), Path::new("photos/2023"));
+        assert_eq!(expand("{year}", "holiday.jpg"), Path::new("2023"));
+        assert_eq!(expand("{month}", "holiday.jpg"), Path::new("04"));
+        assert_eq!(expand("{day}", "holiday.jpg"), Path::new("09"));
+    }
+
+    #[test]
+    fn test_literal_separators() {
+        assert_eq!(expand("{year}/{ext}/{name}", "photos/holiday.jpg"), Path::new("2023/jpg/holiday.jpg"));
+        assert_eq!(expand("sorted/{year}-{month}/{stem}_copy.{ext}", "holiday.jpg"), Path::new("sorted/2023-04/holiday_copy.jpg"));
+        assert_eq!(expand("{ext}/{dir}/{name}", "holiday.jpg"), Path::new("jpg/holiday.jpg"));
+    }
+
+    #[test]
+    fn test_empty_expansions_are_rejected() {
+        let expand = |template: &str, relative_path: &str| {
+            TargetTemplate::parse(template).unwrap().expand(Path::new(relative_path), || Ok(SystemTime::now()))
+        };
+        let err = expand("by-ext/{ext}/{name}", "README").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("{ext}"));
+        assert!(expand("{stem}.{ext}", "docs/README").is_err());
+        assert!(expand("{dir}", "README").is_err());
+        assert!(expand(".", "README").is_err());
+        assert_eq!(expand("{dir}/{name}", "README").unwrap(), Path::new("README"));
+    }
+
+    #[test]
+    fn test_modification_time_is_only_read_when_needed() {
+        let template = TargetTemplate::parse("{ext}/{name}").unwrap();
+        let expanded = template.expand(Path::new("a.txt"), || panic!("the modification time is not needed"));
+        assert_eq!(expanded.unwrap(), Path::new("txt/a.txt"));
+        let template = TargetTemplate::parse("{year}/{name}").unwrap();
+        assert!(template.expand(Path::new("a.txt"), || Err(std::io::ErrorKind::NotFound.into())).is_err());
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        assert!(TargetTemplate::parse("{year}/{extension}").unwrap_err().contains("{extension}"));
+        assert!(TargetTemplate::parse("{year/{name}").is_err());
+        assert!(TargetTemplate::parse("{name").is_err());
+        assert!(TargetTemplate::parse("../{name}").is_err());
+        assert!(TargetTemplate::parse("/absolute/{name}").is_err());
+    }
+}
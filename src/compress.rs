@@ -0,0 +1,69 @@
+use std::fs::File;
+use std::io::{BufWriter, Read};
+use std::path::Path;
+
+use crate::args::Compression;
+use crate::throttle::{copy_chunks, CHUNK_SIZE, RateLimiter};
+
+/// Copies the content of `source_path` to `target_path` like [std::fs::copy], but encoded with `compression` at
+/// `level`. Each chunk read from the source is paid for at `rate_limiter` if one is given, the returned number
+/// of bytes is the uncompressed size
+pub fn copy_compressed(source_path: &Path, target_path: &Path, compression: Compression, level: i32,
+                       rate_limiter: Option<&RateLimiter>) -> std::io::Result<u64> {
+    let mut source = File::open(source_path)?;
+    let permissions = source.metadata()?.permissions();
+    let (copied, target) = compress_chunks(&mut source, File::create(target_path)?, compression, level, rate_limiter)?;
+    target.set_permissions(permissions)?;
+    Ok(copied)
+}
+
+/// Encodes everything read from `source` into `target` like [copy_compressed] and hands back the flushed target
+pub(crate) fn compress_chunks(source: &mut impl Read, target: File, compression: Compression, level: i32,
+                              rate_limiter: Option<&RateLimiter>) -> std::io::Result<(u64, File)> {
+    let target = BufWriter::new(target);
+    let (copied, target) = match compression {
+        Compression::None => {
+            let mut target = target;
+            (copy_chunks(source, &mut target, CHUNK_SIZE, rate_limiter)?, target)
+        }
+        Compression::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(target, flate2::Compression::new(level.unsigned_abs()));
+            let copied = copy_chunks(source, &mut encoder, CHUNK_SIZE, rate_limiter)?;
+            (copied, encoder.finish()?)
+        }
+        Compression::Zstd => {
+            let mut encoder = zstd::Encoder::new(target, level)?;
+            let copied = copy_chunks(source, &mut encoder, CHUNK_SIZE, rate_limiter)?;
+            (copied, encoder.finish()?)
+        }
+    };
+    Ok((copied, target.into_inner().map_err(|err| err.into_error())?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::args::Compression;
+    use crate::compress::copy_compressed;
+
+    #[test]
+    fn test_copy_compressed_decompresses_to_source() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let source = temp_dir.path().join("source.txt");
+        let content = "compressible text ".repeat(10_000);
+        std::fs::write(&source, &content).unwrap();
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let target = temp_dir.path().join(format!("target.txt.{}", compression.extension().unwrap()));
+            assert_eq!(copy_compressed(&source, &target, compression, 9, None).unwrap(), content.len() as u64);
+            assert!(std::fs::metadata(&target).unwrap().len() < content.len() as u64 / 10);
+            let mut decompressed = String::new();
+            let target = std::fs::File::open(&target).unwrap();
+            match compression {
+                Compression::Gzip => { flate2::read::GzDecoder::new(target).read_to_string(&mut decompressed).unwrap(); }
+                _ => { zstd::Decoder::new(target).unwrap().read_to_string(&mut decompressed).unwrap(); }
+            }
+            assert_eq!(decompressed, content);
+        }
+    }
+}
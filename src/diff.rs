@@ -0,0 +1,159 @@
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use walkdir::DirEntry;
+
+use crate::args::Arguments;
+use crate::is_same_file_at_target;
+
+/// How a source file relates to its target as printed by `--diff`
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum DiffStatus {
+    /// The target does not exist yet
+    New,
+    /// The target exists with a different size or modification time
+    Changed,
+    /// The target exists with the same size and modification time
+    Same,
+}
+
+impl Display for DiffStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let label = match self {
+            DiffStatus::New => { "new" }
+            DiffStatus::Changed => { "changed" }
+            DiffStatus::Same => { "same" }
+        };
+        write!(f, "{}", label)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct DiffEntry {
+    pub source_path: PathBuf,
+    pub target_path: PathBuf,
+    pub status: DiffStatus,
+}
+
+/// How many files ended up in each [DiffStatus]
+#[derive(Default, PartialEq, Debug)]
+pub struct DiffSummary {
+    pub new: usize,
+    pub changed: usize,
+    pub same: usize,
+}
+
+impl DiffSummary {
+    pub fn of(entries: &[DiffEntry]) -> DiffSummary {
+        entries.iter().fold(DiffSummary::default(), |mut summary, entry| {
+            match entry.status {
+                DiffStatus::New => { summary.new += 1 }
+                DiffStatus::Changed => { summary.changed += 1 }
+                DiffStatus::Same => { summary.same += 1 }
+            }
+            summary
+        })
+    }
+}
+
+impl Display for DiffSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} new, {} changed, {} same", self.new, self.changed, self.same)
+    }
+}
+
+/// Compares `source_path` to `target_path` the same way `--skip-existing` does
+pub fn classify(source_path: &Path, target_path: &Path) -> DiffStatus {
+    if std::fs::symlink_metadata(target_path).is_err() {
+        DiffStatus::New
+    } else if is_same_file_at_target(source_path, target_path) {
+        DiffStatus::Same
+    } else {
+        DiffStatus::Changed
+    }
+}
+
+/// Classifies every file of `files` against the target it would be copied to, sorted by source path.
+/// Directories are left out, as are files whose target path can not be determined
+pub fn diff_files(args: &Arguments, files: impl IntoIterator<Item=DirEntry>) -> Vec<DiffEntry> {
+    let mut entries: Vec<DiffEntry> = files.into_iter()
+        .filter(|entry| !entry.file_type().is_dir())
+        .filter_map(|entry| {
+            let source_path = entry.into_path();
+            match args.transform_source_to_target_path(&source_path) {
+                Ok(target_path) => {
+                    let status = classify(&source_path, &target_path);
+                    Some(DiffEntry { source_path, target_path, status })
+                }
+                Err(err) => {
+                    warn!("Could not determine the target of {} due to {}", source_path.to_string_lossy(), err);
+                    None
+                }
+            }
+        })
+        .collect();
+    entries.sort_by(|a, b| a.source_path.cmp(&b.source_path));
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+
+    use clap::Parser;
+
+    use crate::args::CliArgs;
+    use crate::diff::{classify, diff_files, DiffStatus, DiffSummary};
+    use crate::discover_files;
+
+    #[test]
+    fn test_classify() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("a.txt"), "a").unwrap();
+        assert_eq!(classify(&source.path().join("a.txt"), &target.path().join("a.txt")), DiffStatus::New);
+
+        std::fs::write(target.path().join("a.txt"), "old content").unwrap();
+        assert_eq!(classify(&source.path().join("a.txt"), &target.path().join("a.txt")), DiffStatus::Changed);
+
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        std::fs::write(target.path().join("a.txt"), "b").unwrap();
+        File::options().write(true).open(source.path().join("a.txt")).unwrap().set_modified(modified).unwrap();
+        File::options().write(true).open(target.path().join("a.txt")).unwrap().set_modified(modified).unwrap();
+        assert_eq!(classify(&source.path().join("a.txt"), &target.path().join("a.txt")), DiffStatus::Same);
+
+        File::options().write(true).open(target.path().join("a.txt")).unwrap()
+            .set_modified(modified + Duration::from_secs(60)).unwrap();
+        assert_eq!(classify(&source.path().join("a.txt"), &target.path().join("a.txt")), DiffStatus::Changed);
+    }
+
+    #[test]
+    fn test_diff_files() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir(source.path().join("nested")).unwrap();
+        std::fs::write(source.path().join("new.txt"), "new").unwrap();
+        std::fs::write(source.path().join("nested").join("changed.txt"), "changed").unwrap();
+        std::fs::write(source.path().join("same.txt"), "same").unwrap();
+        std::fs::create_dir(target.path().join("nested")).unwrap();
+        std::fs::write(target.path().join("nested").join("changed.txt"), "outdated").unwrap();
+        std::fs::copy(source.path().join("same.txt"), target.path().join("same.txt")).unwrap();
+        let modified = std::fs::metadata(source.path().join("same.txt")).unwrap().modified().unwrap();
+        File::options().write(true).open(target.path().join("same.txt")).unwrap().set_modified(modified).unwrap();
+
+        let args = CliArgs::parse_from(["filescraper", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--preserve-empty-dirs"])
+            .convert().unwrap();
+        let entries = diff_files(&args, discover_files(&args));
+        let statuses: Vec<(String, DiffStatus)> = entries.iter()
+            .map(|entry| (entry.source_path.file_name().unwrap().to_string_lossy().to_string(), entry.status))
+            .collect();
+        assert_eq!(statuses, vec![("changed.txt".to_string(), DiffStatus::Changed), ("new.txt".to_string(), DiffStatus::New),
+                                  ("same.txt".to_string(), DiffStatus::Same)]);
+        assert_eq!(entries[1].target_path, target.path().join("new.txt"));
+        let summary = DiffSummary::of(&entries);
+        assert_eq!(summary, DiffSummary { new: 1, changed: 1, same: 1 });
+        assert_eq!(summary.to_string(), "1 new, 1 changed, 1 same");
+    }
+}
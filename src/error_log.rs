@@ -0,0 +1,46 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// The file of `--error-log`, which lists every failed source path followed by a tab and the error on its own
+/// line. It can be passed back in via `--from-error-log` to retry the failed files
+pub struct ErrorLog {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl ErrorLog {
+    /// Creates or truncates the error log at `path`
+    pub fn create(path: &Path) -> std::io::Result<ErrorLog> {
+        Ok(ErrorLog { writer: Mutex::new(BufWriter::new(File::create(path)?)) })
+    }
+
+    pub fn record(&self, source_path: &Path, err: &std::io::Error) -> std::io::Result<()> {
+        let error = err.to_string().replace(['\n', '\r', '\t'], " ");
+        let mut writer = self.writer.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        writeln!(writer, "{}\t{}", source_path.to_string_lossy(), error)
+    }
+
+    pub fn flush(&self) -> std::io::Result<()> {
+        self.writer.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+    use std::path::Path;
+
+    use crate::error_log::ErrorLog;
+
+    #[test]
+    fn test_record() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("errors.log");
+        let error_log = ErrorLog::create(&path).unwrap();
+        error_log.record(Path::new("source/a.txt"), &std::io::Error::new(ErrorKind::NotFound, "gone")).unwrap();
+        error_log.record(Path::new("source/b.txt"), &std::io::Error::other("first\nsecond")).unwrap();
+        error_log.flush().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "source/a.txt\tgone\nsource/b.txt\tfirst second\n");
+    }
+}
@@ -0,0 +1,212 @@
+use std::path::{Component, Path, PathBuf};
+
+use log::{debug, info, warn};
+use walkdir::WalkDir;
+
+use crate::{is_eligible, is_hidden, SkipCounts};
+use crate::args::Arguments;
+
+/// Finds the entries below the target root of `--delete-extraneous` whose source no longer exists although the
+/// filters would copy it. Directories come after their content, so they can be removed once that is gone.
+/// The walk never follows symlinks and everything outside the target root is left alone, the same goes for
+/// the files this run writes itself like `--manifest`. Entries the discovery would never visit, like hidden
+/// ones and those outside the depth limits, are kept just like files the type, size or time filters reject
+pub fn find_extraneous(args: &Arguments) -> Vec<PathBuf> {
+    let target_root = Path::new(&args.target_root_file_path);
+    let source_root = Path::new(&args.source_root_file_path);
+    let own_files: Vec<PathBuf> = [&args.manifest, &args.checksum_manifest, &args.error_log, &args.resume]
+        .into_iter()
+        .flatten()
+        .filter_map(|path| std::path::absolute(path).ok())
+        .collect();
+    let mut walk_dir = WalkDir::new(target_root)
+        .follow_links(false)
+        .min_depth(args.min_depth.unwrap_or(0).max(1))
+        .contents_first(true)
+        .sort_by_file_name();
+    if let Some(max_depth) = args.max_depth {
+        walk_dir = walk_dir.max_depth(max_depth);
+    }
+    let skipped = SkipCounts::default();
+    walk_dir
+        .into_iter()
+        .filter_map(|e| e.map_err(|err| debug!("Could not access {}", err)).ok())
+        .filter(|e| {
+            let Ok(relative_path) = e.path().strip_prefix(target_root) else {
+                return false;
+            };
+            if !relative_path.components().all(|component| matches!(component, Component::Normal(_))) {
+                return false;
+            }
+            if std::path::absolute(e.path()).is_ok_and(|path| own_files.contains(&path)) {
+                return false;
+            }
+            if args.exclude_hidden && is_below_hidden(target_root, relative_path) {
+                return false;
+            }
+            let source_path = source_root.join(relative_path);
+            if std::fs::symlink_metadata(&source_path).is_ok() {
+                return false;
+            }
+            if e.file_type().is_dir() {
+                return args.should_copy_of_type(&source_path, true);
+            }
+            // The target entry stands in for its vanished source when checking the type, size and time filters
+            is_eligible(args, e, &|_, is_dir| args.skip_reason_of_type(&source_path, is_dir), &skipped)
+        })
+        .map(|e| e.into_path())
+        .collect()
+}
+
+/// Whether `relative_path` or any of its parents below `target_root` is hidden. The walk yields directories after
+/// their content, so hidden directories can not be left out before it
+fn is_below_hidden(target_root: &Path, relative_path: &Path) -> bool {
+    relative_path.ancestors()
+        .filter(|ancestor| !ancestor.as_os_str().is_empty())
+        .any(|ancestor| ancestor.file_name().is_some_and(|file_name| is_hidden(file_name, &target_root.join(ancestor))))
+}
+
+/// Deletes the `extraneous` entries found by [find_extraneous], directories only if they became empty.
+/// Returns how many entries were deleted and the failures
+pub fn delete_extraneous(extraneous: &[PathBuf], dry_run: bool) -> (usize, Vec<(PathBuf, std::io::Error)>) {
+    let mut deleted = 0;
+    let mut failures = Vec::new();
+    for path in extraneous {
+        let is_dir = std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_dir());
+        if dry_run {
+            info!("Would delete {}", path.to_string_lossy());
+            continue;
+        }
+        let result = if is_dir { std::fs::remove_dir(path) } else { std::fs::remove_file(path) };
+        match result {
+            Ok(_) => {
+                debug!("Deleted {} as it no longer exists at the source", path.to_string_lossy());
+                deleted += 1;
+            }
+            Err(err) if is_dir && err.kind() == std::io::ErrorKind::DirectoryNotEmpty => {
+                debug!("Kept {} as it still contains files", path.to_string_lossy());
+            }
+            Err(err) => {
+                warn!("Could not delete {} due to {}", path.to_string_lossy(), err);
+                failures.push((path.clone(), err));
+            }
+        }
+    }
+    (deleted, failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use clap::Parser;
+
+    use crate::args::{Arguments, CliArgs};
+    use crate::extraneous::{delete_extraneous, find_extraneous};
+
+    fn arguments(flags: &[&str], source: &Path, target: &Path) -> Arguments {
+        let mut cli = vec!["filescraper", source.to_str().unwrap(), target.to_str().unwrap(), "--delete-extraneous"];
+        cli.extend_from_slice(flags);
+        CliArgs::parse_from(cli).convert().unwrap()
+    }
+
+    #[test]
+    fn test_delete_stale_target_files() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir(source.path().join("kept")).unwrap();
+        std::fs::write(source.path().join("kept").join("current.txt"), "current").unwrap();
+        std::fs::create_dir_all(target.path().join("kept")).unwrap();
+        std::fs::create_dir_all(target.path().join("gone")).unwrap();
+        std::fs::write(target.path().join("kept").join("current.txt"), "current").unwrap();
+        std::fs::write(target.path().join("kept").join("stale.txt"), "stale").unwrap();
+        std::fs::write(target.path().join("gone").join("stale.txt"), "stale").unwrap();
+        std::fs::write(target.path().join("unfiltered.jpg"), "not copied by the filters").unwrap();
+        std::fs::write(target.path().join("manifest.json"), "{}").unwrap();
+        let manifest = target.path().join("manifest.json");
+        let args = arguments(&["--file-extensions", "txt json", "--manifest", manifest.to_str().unwrap()],
+                             source.path(), target.path());
+
+        let extraneous = find_extraneous(&args);
+        assert_eq!(extraneous, vec![
+            target.path().join("gone").join("stale.txt"),
+            target.path().join("gone"),
+            target.path().join("kept").join("stale.txt"),
+        ]);
+        let (deleted, failures) = delete_extraneous(&extraneous, true);
+        assert_eq!(deleted, 0);
+        assert!(failures.is_empty());
+        assert!(target.path().join("kept").join("stale.txt").exists());
+
+        let (deleted, failures) = delete_extraneous(&extraneous, false);
+        assert_eq!(deleted, 3);
+        assert!(failures.is_empty());
+        assert!(target.path().join("kept").join("current.txt").exists());
+        assert!(!target.path().join("kept").join("stale.txt").exists());
+        assert!(!target.path().join("gone").exists());
+        assert!(target.path().join("unfiltered.jpg").exists());
+        assert!(target.path().join("manifest.json").exists());
+    }
+
+    #[test]
+    fn test_entries_the_discovery_never_visits_are_kept() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(target.path().join(".git")).unwrap();
+        std::fs::create_dir_all(target.path().join("deep").join("deeper")).unwrap();
+        std::fs::write(target.path().join(".git").join("config"), "config").unwrap();
+        std::fs::write(target.path().join(".hidden.txt"), "hidden").unwrap();
+        std::fs::write(target.path().join("deep").join("deeper").join("stale.txt"), "stale").unwrap();
+        std::fs::write(target.path().join("deep").join("stale.txt"), "stale").unwrap();
+        std::fs::write(target.path().join("empty.txt"), "").unwrap();
+        std::fs::write(target.path().join("top.txt"), "top").unwrap();
+        let args = arguments(&["--exclude-hidden", "--max-depth", "2", "--exclude-empty-files"], source.path(), target.path());
+        let extraneous = find_extraneous(&args);
+        assert_eq!(extraneous, vec![
+            target.path().join("deep").join("deeper"),
+            target.path().join("deep").join("stale.txt"),
+            target.path().join("deep"),
+            target.path().join("top.txt"),
+        ]);
+        let (deleted, failures) = delete_extraneous(&extraneous, false);
+        assert_eq!(deleted, 2);
+        assert!(failures.is_empty());
+        assert!(target.path().join(".git").join("config").exists());
+        assert!(target.path().join(".hidden.txt").exists());
+        assert!(target.path().join("deep").join("deeper").join("stale.txt").exists());
+        assert!(target.path().join("empty.txt").exists());
+
+        let args = arguments(&["--min-depth", "2"], source.path(), target.path());
+        assert!(!find_extraneous(&args).contains(&target.path().join("top.txt")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_out_of_the_target_are_not_followed() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("precious.txt"), "precious").unwrap();
+        std::os::unix::fs::symlink(outside.path(), target.path().join("link")).unwrap();
+        let args = arguments(&[], source.path(), target.path());
+        let extraneous = find_extraneous(&args);
+        assert_eq!(extraneous, vec![target.path().join("link")]);
+        delete_extraneous(&extraneous, false);
+        assert!(!target.path().join("link").exists());
+        assert!(outside.path().join("precious.txt").exists());
+    }
+
+    #[test]
+    fn test_delete_extraneous_conflicts() {
+        let parse = |flags: &[&str]| {
+            let mut cli = vec!["filescraper", "source", "target", "--delete-extraneous"];
+            cli.extend_from_slice(flags);
+            CliArgs::try_parse_from(cli).map(|cli_args| cli_args.convert())
+        };
+        assert!(parse(&["--flatten"]).is_err());
+        assert!(parse(&["--mode", "move"]).unwrap().is_err());
+        assert!(parse(&["--source", "other"]).unwrap().is_err());
+        assert!(parse(&["--overwrite", "rename"]).unwrap().is_err());
+        assert!(parse(&["--overwrite", "always"]).unwrap().is_ok());
+    }
+}
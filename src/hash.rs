@@ -0,0 +1,142 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use sha2::{Digest, Sha256, Sha512};
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::args::ChecksumAlgorithm;
+
+/// Hashes the content of the file at `path` with SHA-256
+pub fn hash_file(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut hasher = Sha256::new();
+    read_chunks(path, |chunk| hasher.update(chunk))?;
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Hashes the content of the file at `path` with the non-cryptographic but much faster XXH3, which is
+/// good enough to tell duplicates apart
+pub fn content_hash(path: &Path) -> std::io::Result<u128> {
+    let mut hasher = Xxh3::new();
+    read_chunks(path, |chunk| hasher.update(chunk))?;
+    Ok(hasher.digest128())
+}
+
+/// Computes the [content_hash] of everything read through it, so a copy can hash its source in the same pass
+pub(crate) struct HashingReader<R> {
+    inner: R,
+    hasher: Xxh3,
+}
+
+impl<R> HashingReader<R> {
+    pub(crate) fn new(inner: R) -> HashingReader<R> {
+        HashingReader { inner, hasher: Xxh3::new() }
+    }
+
+    pub(crate) fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// The hash of everything read so far
+    pub(crate) fn digest(&self) -> u128 {
+        self.hasher.digest128()
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buffer)?;
+        self.hasher.update(&buffer[..read]);
+        Ok(read)
+    }
+}
+
+/// The lowercase hex checksum of the file at `path` as printed by `sha256sum`, `sha512sum` or `xxh128sum`
+pub fn checksum(path: &Path, algorithm: ChecksumAlgorithm) -> std::io::Result<String> {
+    match algorithm {
+        ChecksumAlgorithm::Sha256 => { Ok(to_hex(&hash_file(path)?)) }
+        ChecksumAlgorithm::Sha512 => {
+            let mut hasher = Sha512::new();
+            read_chunks(path, |chunk| hasher.update(chunk))?;
+            Ok(to_hex(&hasher.finalize()))
+        }
+        ChecksumAlgorithm::Xxh128 => { Ok(format!("{:032x}", content_hash(path)?)) }
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn read_chunks(path: &Path, mut consume: impl FnMut(&[u8])) -> std::io::Result<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(());
+        }
+        consume(&buffer[..read]);
+    }
+}
+
+/// Whether `src` and `dst` have identical content
+pub fn verify_copy(src: &Path, dst: &Path) -> std::io::Result<bool> {
+    Ok(hash_file(src)? == hash_file(dst)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::args::ChecksumAlgorithm;
+    use crate::hash::{checksum, content_hash, HashingReader, verify_copy};
+
+    #[test]
+    fn test_verify_copy() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let original = temp_dir.path().join("original.txt");
+        let copy = temp_dir.path().join("copy.txt");
+        let corrupted = temp_dir.path().join("corrupted.txt");
+        std::fs::write(&original, "content").unwrap();
+        std::fs::write(&copy, "content").unwrap();
+        std::fs::write(&corrupted, "c0ntent").unwrap();
+        assert!(verify_copy(&original, &copy).unwrap());
+        assert!(!verify_copy(&original, &corrupted).unwrap());
+        assert!(verify_copy(&original, &temp_dir.path().join("missing.txt")).is_err());
+    }
+
+    #[test]
+    fn test_checksum() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("abc.txt");
+        std::fs::write(&path, "abc").unwrap();
+        assert_eq!(checksum(&path, ChecksumAlgorithm::Sha256).unwrap(),
+                   "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(checksum(&path, ChecksumAlgorithm::Sha512).unwrap(),
+                   "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd\
+                   454d4423643ce80e2a9ac94fa54ca49f");
+        assert_eq!(checksum(&path, ChecksumAlgorithm::Xxh128).unwrap().len(), 32);
+    }
+
+    #[test]
+    fn test_hashing_reader_matches_content_hash() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("content.bin");
+        std::fs::write(&path, vec![3u8; 200_000]).unwrap();
+        let mut reader = HashingReader::new(std::fs::File::open(&path).unwrap());
+        std::io::copy(&mut reader, &mut std::io::sink()).unwrap();
+        assert_eq!(reader.digest(), content_hash(&path).unwrap());
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let original = temp_dir.path().join("original.txt");
+        let duplicate = temp_dir.path().join("duplicate.txt");
+        let other = temp_dir.path().join("other.txt");
+        std::fs::write(&original, vec![7u8; 100_000]).unwrap();
+        std::fs::write(&duplicate, vec![7u8; 100_000]).unwrap();
+        std::fs::write(&other, vec![7u8; 100_001]).unwrap();
+        assert_eq!(content_hash(&original).unwrap(), content_hash(&duplicate).unwrap());
+        assert_ne!(content_hash(&original).unwrap(), content_hash(&other).unwrap());
+    }
+}
@@ -0,0 +1,70 @@
+use std::process::{Command, ExitStatus};
+
+use crate::CopyReport;
+
+/// Runs `command` through the platform's shell once a copy finished, with its outcome in the environment:
+/// `FILESCRAPER_COPIED`, `FILESCRAPER_FAILED`, `FILESCRAPER_SKIPPED`, `FILESCRAPER_BYTES`,
+/// `FILESCRAPER_DURATION_MS` and `FILESCRAPER_INTERRUPTED` (`true` or `false`)
+pub fn run_post_command(command: &str, report: &CopyReport) -> std::io::Result<ExitStatus> {
+    shell(command)
+        .env("FILESCRAPER_COPIED", report.copied.to_string())
+        .env("FILESCRAPER_FAILED", report.failures.len().to_string())
+        .env("FILESCRAPER_SKIPPED", report.skipped.to_string())
+        .env("FILESCRAPER_BYTES", report.bytes_copied.to_string())
+        .env("FILESCRAPER_DURATION_MS", report.duration.as_millis().to_string())
+        .env("FILESCRAPER_INTERRUPTED", report.interrupted.to_string())
+        .status()
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use crate::CopyReport;
+    use crate::hook::run_post_command;
+
+    fn report() -> CopyReport {
+        CopyReport {
+            duration: Duration::from_millis(1500),
+            copied: 2,
+            bytes_copied: 1024,
+            skipped: 1,
+            duplicates: 0,
+            failures: vec![(PathBuf::from("a.txt"), std::io::Error::other("failed"))],
+            permission_denied: 0,
+            extension_stats: Vec::new(),
+            interrupted: false,
+            budget: None,
+        }
+    }
+
+    #[test]
+    fn test_run_post_command() {
+        assert!(run_post_command("true", &report()).unwrap().success());
+        assert!(!run_post_command("false", &report()).unwrap().success());
+        assert_eq!(run_post_command("exit 3", &report()).unwrap().code(), Some(3));
+    }
+
+    #[test]
+    fn test_post_command_environment() {
+        let command = "test \"$FILESCRAPER_COPIED\" = 2 && test \"$FILESCRAPER_FAILED\" = 1 \
+            && test \"$FILESCRAPER_SKIPPED\" = 1 && test \"$FILESCRAPER_BYTES\" = 1024 \
+            && test \"$FILESCRAPER_DURATION_MS\" = 1500 && test \"$FILESCRAPER_INTERRUPTED\" = false";
+        assert!(run_post_command(command, &report()).unwrap().success());
+    }
+}
@@ -1,101 +1,3711 @@
-use std::env::consts::OS;
-use std::path::Path;
-use std::time::{Duration, Instant};
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::ffi::OsStr;
+use std::fmt::{Display, Formatter};
+use std::fs::{File, FileTimes, Metadata, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
 use atomic_counter::{AtomicCounter, RelaxedCounter};
 use colorful::core::color_string::CString;
-use indicatif::{ParallelProgressIterator, ProgressBar, ProgressStyle};
-use log::{debug, info, warn};
-use rayon::iter::IntoParallelRefIterator;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::{debug, info, Level, log, warn};
+use rayon::iter::{IntoParallelRefIterator, ParallelBridge};
 use rayon::iter::ParallelIterator;
+use rayon::{ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
+use supports_color::Stream;
 use walkdir::{DirEntry, WalkDir};
-use crate::args::Arguments;
+use crate::adaptive::{AdaptiveConcurrency, DEFAULT_MAX_CONCURRENCY};
+use crate::archive::Archive;
+use crate::args::{Arguments, ColorChoice, Compression, FlattenCollision, Mode, OnError, Order, OverwritePolicy, ProgressTarget, SkipReason};
+use crate::manifest::{ChecksumManifest, Manifest, ManifestEntry, PreviousChecksums};
+use crate::compress::{compress_chunks, copy_compressed};
+use crate::error_log::ErrorLog;
+use crate::hash::HashingReader;
+use crate::long_paths::long_path;
+use crate::progress::ProgressWriter;
+use crate::resume::ResumeState;
+use crate::semaphore::Semaphore;
+use crate::stats::{ExtensionStat, ExtensionStats};
+use crate::throttle::{CHUNK_SIZE, copy_buffered, copy_chunks, copy_throttled};
 
 
+pub mod adaptive;
+pub mod archive;
 pub mod args;
+pub mod compress;
+pub mod diff;
+pub mod error_log;
+pub mod extraneous;
+pub mod hash;
+pub mod hook;
+pub mod logging;
+mod long_paths;
+pub mod manifest;
+pub mod progress;
+pub mod resume;
+pub mod semaphore;
+pub mod stats;
+pub mod throttle;
+pub mod tree;
+pub mod watch;
+mod xattrs;
 
 pub fn gather_files_for_copying(args: &Arguments) -> Vec<DirEntry> {
-    let files: Vec<DirEntry> = WalkDir::new(Path::new(&args.source_root_file_path))
+    discover_files(args).collect()
+}
+
+/// Walks the source root like [gather_files_for_copying], but decides with `predicate` instead of the
+/// extension, folder and regex filters which paths are copied. Depth, size and hidden entry limits still apply.
+/// Combine it with [Arguments::should_copy] to keep the built-in path filters as well
+pub fn gather_files_with<'a, F>(args: &'a Arguments, predicate: F) -> Vec<DirEntry>
+    where F: Fn(&Path) -> bool + Send + Sync + 'a {
+    discover_files_with(args, predicate).collect()
+}
+
+/// Lazily yields the files eligible for copying, so copying can start while the discovery is still running.
+/// The parallel walk can not be streamed and discovers everything before yielding the first entry
+pub fn discover_files(args: &Arguments) -> Box<dyn Iterator<Item=DirEntry> + Send + '_> {
+    discover_files_by(args, |path, is_dir| args.skip_reason_of_type(path, is_dir))
+}
+
+/// The lazy counterpart of [gather_files_with]. When following links, files reachable through several
+/// paths are only yielded the first time
+pub fn discover_files_with<'a, F>(args: &'a Arguments, predicate: F) -> Box<dyn Iterator<Item=DirEntry> + Send + 'a>
+    where F: Fn(&Path) -> bool + Send + Sync + 'a {
+    discover_files_by(args, move |path, _| (!predicate(path)).then_some(SkipReason::Predicate))
+}
+
+/// Discovers the files `skip_reason` does not reject and logs how many were skipped for which reason once done
+fn discover_files_by<'a, F>(args: &'a Arguments, skip_reason: F) -> Box<dyn Iterator<Item=DirEntry> + Send + 'a>
+    where F: Fn(&Path, bool) -> Option<SkipReason> + Send + Sync + 'a {
+    let skipped = Arc::new(SkipCounts::default());
+    let files = walk_source_root(args, skip_reason, skipped.clone());
+    let files: Box<dyn Iterator<Item=DirEntry> + Send + 'a> = if args.follow_links {
+        let mut unique_files = UniqueFiles::default();
+        Box::new(files.filter(move |e| unique_files.first_occurrence(e)))
+    } else {
+        files
+    };
+    let files = match args.max_files {
+        Some(max_files) => { limit_files(files, max_files) }
+        None => { files }
+    };
+    Box::new(ReportSkipped::new(files, skipped, args.skip_permission_errors))
+}
+
+/// Ends `files` right after the `max_files`-th file, so the walk does not continue any further.
+/// Directories kept by `--preserve-empty-dirs` do not count
+fn limit_files<'a>(mut files: Box<dyn Iterator<Item=DirEntry> + Send + 'a>, max_files: usize) -> Box<dyn Iterator<Item=DirEntry> + Send + 'a> {
+    let mut remaining = max_files;
+    Box::new(std::iter::from_fn(move || {
+        if remaining == 0 {
+            return None;
+        }
+        let entry = files.next()?;
+        if !entry.file_type().is_dir() {
+            remaining -= 1;
+        }
+        Some(entry)
+    }))
+}
+
+/// Walks all source roots one after the other. Every entry is tagged with the root it was found in before
+/// `skip_reason` sees it, as the filters already look up the source root of the entry
+fn walk_source_root<'a, F>(args: &'a Arguments, skip_reason: F, skipped: Arc<SkipCounts>) -> Box<dyn Iterator<Item=DirEntry> + Send + 'a>
+    where F: Fn(&Path, bool) -> Option<SkipReason> + Send + Sync + 'a {
+    let skip_reason = Arc::new(skip_reason);
+    Box::new(args.source_roots.iter().enumerate().flat_map(move |(index, source_root)| {
+        let skip_reason = skip_reason.clone();
+        walk_root(args, Path::new(source_root), tagging_source_root(args, index, move |path: &Path, is_dir| skip_reason(path, is_dir)), skipped.clone())
+    }))
+}
+
+/// Wraps `skip_reason` to tag every entry it is asked about with the source root at `index`
+pub(crate) fn tagging_source_root<'a, F>(args: &'a Arguments, index: usize, skip_reason: F) -> impl Fn(&Path, bool) -> Option<SkipReason> + Send + Sync + 'a
+    where F: Fn(&Path, bool) -> Option<SkipReason> + Send + Sync + 'a {
+    move |path, is_dir| {
+        args.tag_source_root(path, index);
+        skip_reason(path, is_dir)
+    }
+}
+
+fn walk_root<'a, F>(args: &'a Arguments, source_root: &'a Path, skip_reason: F, skipped: Arc<SkipCounts>) -> Box<dyn Iterator<Item=DirEntry> + Send + 'a>
+    where F: Fn(&Path, bool) -> Option<SkipReason> + Send + Sync + 'a {
+    walk_below(args, source_root, 0, skip_reason, skipped)
+}
+
+/// Walks `start`, which is located `depth` levels below its source root, with all the filters of [walk_root].
+/// The depth limits count from the source root, so walking a subdirectory yields the entries a walk of the
+/// whole source root would have yielded below it
+pub(crate) fn walk_below<'a, F>(args: &'a Arguments, start: &'a Path, depth: usize, skip_reason: F, skipped: Arc<SkipCounts>) -> Box<dyn Iterator<Item=DirEntry> + Send + 'a>
+    where F: Fn(&Path, bool) -> Option<SkipReason> + Send + Sync + 'a {
+    let min_depth = args.min_depth.map(|min_depth| min_depth.saturating_sub(depth));
+    let max_depth = match args.max_depth.map(|max_depth| max_depth.checked_sub(depth)) {
+        Some(None) => { return Box::new(std::iter::empty()); }
+        Some(max_depth) => { max_depth }
+        None => { None }
+    };
+    if args.parallel_walk {
+        return Box::new(gather_files_in_parallel(args, start, min_depth, max_depth, &skip_reason, skipped).into_iter());
+    }
+    if args.respect_gitignore {
+        return walk_respecting_gitignore(args, start, min_depth, max_depth, skip_reason, skipped);
+    }
+    let mut walk_dir = WalkDir::new(start)
         .follow_links(args.follow_links)
-        .into_iter().filter(|e| {
+        .same_file_system(args.one_filesystem);
+    if let Some(min_depth) = min_depth {
+        walk_dir = walk_dir.min_depth(min_depth);
+    }
+    if let Some(max_depth) = max_depth {
+        walk_dir = walk_dir.max_depth(max_depth);
+    }
+    let visited = VisitedDirectories::default();
+    let mut symlink_hops = SymlinkHops::default();
+    let hidden_skipped = skipped.clone();
+    let error_skipped = skipped.clone();
+    let files = walk_dir
+        .into_iter()
+        .filter_entry(move |e| {
+            if args.exclude_hidden && e.depth() > 0 && is_hidden(e.file_name(), e.path()) {
+                debug!("Skipped hidden {}", e.path().to_string_lossy());
+                hidden_skipped.record(SkipReason::Hidden);
+                return false;
+            }
+            if !args.follow_links {
+                return true;
+            }
+            if let Some(limit) = args.follow_links_depth_limit {
+                if symlink_hops.hops(e) > limit {
+                    warn!("Skipped {} as it is reached through more than {} symlinks", e.path().to_string_lossy(), limit);
+                    return false;
+                }
+            }
+            !e.file_type().is_dir() || visited.first_visit(e.path())
+        })
+        .filter(move |e| {
         match e {
             Ok(_) => { true }
             Err(err) => {
-                debug!("Could not access {}", err);
+                match (err.loop_ancestor(), err.path()) {
+                    (Some(_), Some(path)) => { warn!("Skipped symlink cycle at {}", path.to_string_lossy()) }
+                    _ => { skip_inaccessible(err, err.io_error(), &error_skipped) }
+                }
                 false
             }
         }
     })
         .filter_map(|e| e.ok())
-        .filter(|e| {
-            if !args.should_copy(e.path()) {
-                debug!("Skipped copying for {}", e.path().to_str().unwrap_or_else(|| "<could not read path>"));
-                return false;
+        .filter(move |e| is_eligible(args, e, &skip_reason, &skipped));
+    Box::new(files)
+}
+
+/// Discovers the files with [jwalk], which reads directories in parallel. Every found path is then
+/// stat-ed again, also in parallel, to turn it into a [DirEntry], so all entries report a depth of 0.
+/// Followed links are limited and deduplicated like in [walk_below], while reading their parent directory
+fn gather_files_in_parallel(args: &Arguments, source_root: &Path, min_depth: Option<usize>, max_depth: Option<usize>,
+                            skip_reason: &(impl Fn(&Path, bool) -> Option<SkipReason> + Sync), skipped: Arc<SkipCounts>) -> Vec<DirEntry> {
+    let mut walk_dir = jwalk::WalkDir::new(source_root)
+        .follow_links(args.follow_links)
+        .skip_hidden(false);
+    if let Some(min_depth) = min_depth {
+        walk_dir = walk_dir.min_depth(min_depth);
+    }
+    if let Some(max_depth) = max_depth {
+        walk_dir = walk_dir.max_depth(max_depth);
+    }
+    let root_device = if args.one_filesystem { device_id(source_root) } else { None };
+    if args.exclude_hidden || root_device.is_some() || args.follow_links {
+        let exclude_hidden = args.exclude_hidden;
+        let follow_links = args.follow_links;
+        let follow_links_depth_limit = args.follow_links_depth_limit;
+        let visited = VisitedDirectories::default();
+        let symlink_hops = Mutex::new(SymlinkHops::default());
+        let hidden_skipped = skipped.clone();
+        walk_dir = walk_dir.process_read_dir(move |_, _, _, children| {
+            children.retain(|child| {
+                let Ok(child) = child.as_ref() else {
+                    return true;
+                };
+                let path = child.path();
+                if exclude_hidden && child.depth > 0 && is_hidden(&child.file_name, &path) {
+                    hidden_skipped.record(SkipReason::Hidden);
+                    return false;
+                }
+                if root_device.is_some_and(|device| child.file_type.is_dir() && !is_on_device(&path, device)) {
+                    return false;
+                }
+                if !follow_links {
+                    return true;
+                }
+                if let Some(limit) = follow_links_depth_limit {
+                    let hops = symlink_hops.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .record(&path, child.path_is_symlink(), child.file_type.is_dir());
+                    if hops > limit {
+                        warn!("Skipped {} as it is reached through more than {} symlinks", path.to_string_lossy(), limit);
+                        return false;
+                    }
+                }
+                !child.file_type.is_dir() || visited.first_visit(&path)
+            });
+        });
+    }
+    let paths: Vec<PathBuf> = walk_dir.into_iter()
+        .filter_map(|e| {
+            match e {
+                Ok(entry) => { Some(entry.path()) }
+                Err(err) => {
+                    skip_inaccessible(&err, err.io_error(), &skipped);
+                    None
+                }
             }
-            true
         })
         .collect();
-    files
+    paths.par_iter()
+        .filter_map(|path| {
+            entry_for_path(path, args.follow_links)
+                .map_err(|err| skip_inaccessible(&err, Some(&err), &skipped))
+                .ok()
+        })
+        .filter(|e| is_eligible(args, e, skip_reason, &skipped))
+        .collect()
 }
 
-pub fn copy(args: Arguments, files: Vec<DirEntry>) -> Duration {
-    let start_time = Instant::now();
-    info!("Beginning copy-process...");
-    let counter = RelaxedCounter::new(0);
-    let bar = create_progress_bar(files.len() as u64);
-    files.par_iter().progress_with(bar).for_each(|entry| {
-        let source_path = entry.path();
-        let source_path_string = source_path.to_string_lossy().to_string();
-        let target_path = args.transform_source_to_target_path(source_path);
-        let target_path_parent = target_path.parent().unwrap();
-        std::fs::create_dir_all(target_path_parent).unwrap();
-        match std::fs::copy(source_path, target_path) {
-            Ok(_) => { debug!("Successfully copied {}", source_path_string) }
-            Err(err) => { warn!("Failed to copy {} due to {}", source_path_string, err) }
-        }
-        counter.inc();
-    });
-    info!("Finished copying all files!");
-    start_time.elapsed()
+/// Walks with the [ignore] crate, which leaves out everything matched by ignore files. Its entries are stat-ed
+/// again to turn them into a [DirEntry], so they all report a depth of 0
+fn walk_respecting_gitignore<'a, F>(args: &'a Arguments, source_root: &Path, min_depth: Option<usize>, max_depth: Option<usize>,
+                                   skip_reason: F, skipped: Arc<SkipCounts>) -> Box<dyn Iterator<Item=DirEntry> + Send + 'a>
+    where F: Fn(&Path, bool) -> Option<SkipReason> + Send + Sync + 'a {
+    let mut walk_builder = ignore::WalkBuilder::new(source_root);
+    walk_builder
+        .follow_links(args.follow_links)
+        .max_depth(max_depth)
+        .hidden(false)
+        .same_file_system(args.one_filesystem)
+        .require_git(false);
+    if args.exclude_hidden {
+        let hidden_skipped = skipped.clone();
+        walk_builder.filter_entry(move |e| {
+            let hidden = e.depth() > 0 && is_hidden(e.file_name(), e.path());
+            if hidden {
+                hidden_skipped.record(SkipReason::Hidden);
+            }
+            !hidden
+        });
+    }
+    let min_depth = min_depth.unwrap_or(0);
+    let error_skipped = skipped.clone();
+    let files = walk_builder.build()
+        .filter_map(move |e| {
+            match e {
+                Ok(entry) => { Some(entry) }
+                Err(err) => {
+                    skip_inaccessible(&err, err.io_error(), &error_skipped);
+                    None
+                }
+            }
+        })
+        .filter(move |entry| entry.depth() >= min_depth)
+        .filter_map({
+            let skipped = skipped.clone();
+            move |entry| {
+                entry_for_path(entry.path(), args.follow_links)
+                    .map_err(|err| skip_inaccessible(&err, Some(&err), &skipped))
+                    .ok()
+            }
+        })
+        .filter(move |e| is_eligible(args, e, &skip_reason, &skipped));
+    Box::new(files)
 }
 
-pub fn is_colorful_supported() -> bool {
-    "linux".eq(OS)
+/// Logs an entry the walk could not access and counts it if that was due to missing permissions
+fn skip_inaccessible(err: &impl Display, io_error: Option<&std::io::Error>, skipped: &SkipCounts) {
+    if io_error.is_some_and(|io_error| io_error.kind() == ErrorKind::PermissionDenied) {
+        skipped.record(SkipReason::PermissionDenied);
+    }
+    debug!("Could not access {}", err);
 }
 
-pub fn print_colorful_when_supported(message: &str, function: fn(&str) -> CString) {
-    if is_colorful_supported() {
-        let message = function(message);
-        println!("{}", message);
+/// The id of the device `path` is located on
+#[cfg(unix)]
+fn device_id(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata(path).ok().map(|metadata| metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn device_id(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Whether `path` is located on `device`, paths whose device can not be read count as located on it
+fn is_on_device(path: &Path, device: u64) -> bool {
+    device_id(path).is_none_or(|path_device| path_device == device)
+}
+
+/// Whether the entry is a dotfile, on Windows also whether it carries the hidden attribute
+fn is_hidden(file_name: &OsStr, path: &Path) -> bool {
+    if file_name.to_string_lossy().starts_with('.') {
+        return true;
+    }
+    has_hidden_attribute(path)
+}
+
+#[cfg(windows)]
+fn has_hidden_attribute(path: &Path) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    std::fs::symlink_metadata(path)
+        .map(|metadata| metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(windows))]
+fn has_hidden_attribute(_path: &Path) -> bool {
+    false
+}
+
+/// Stats `path` into a [DirEntry] of depth 0
+fn entry_for_path(path: &Path, follow_links: bool) -> std::io::Result<DirEntry> {
+    match WalkDir::new(path).follow_links(follow_links).max_depth(0).into_iter().next() {
+        Some(entry) => { Ok(entry?) }
+        None => { Err(std::io::Error::new(ErrorKind::NotFound, format!("walking {} yielded no entry", path.to_string_lossy()))) }
+    }
+}
+
+/// Reads newline-separated source paths from `list`, or from stdin if it is `-`, instead of walking
+/// the source root. The listed paths still have to be located below the source root and pass the filters
+pub fn gather_files_from_list(args: &Arguments, list: &Path) -> std::io::Result<Vec<DirEntry>> {
+    if list == Path::new("-") {
+        Ok(collect_listed_files(args, std::io::stdin().lock()))
     } else {
-        println!("{}", message);
+        Ok(collect_listed_files(args, BufReader::new(File::open(list)?)))
     }
 }
 
-pub fn create_progress_bar(items: u64) -> ProgressBar {
-    let bar = ProgressBar::new(items);
-    bar.enable_steady_tick(Duration::from_secs(1));
-    let style: Option<ProgressStyle> = match ProgressStyle::with_template
-        ("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}") {
-        Ok(_style) => { Some(_style) }
-        Err(_) => {
-            debug!("Could not retrieve progress bar style!");
-            None
+fn collect_listed_files(args: &Arguments, list: impl BufRead) -> Vec<DirEntry> {
+    let mut unique_files = UniqueFiles::default();
+    let skipped = SkipCounts::default();
+    let files = list.lines()
+        .map_while(|line| line.map_err(|err| warn!("Could not read the file list due to {}", err)).ok())
+        .map(|line| listed_path(&line, args.from_error_log).to_string())
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| {
+            match entry_for_path(Path::new(&line), args.follow_links) {
+                Ok(entry) => { Some(entry) }
+                Err(err) => {
+                    warn!("Skipped listed path {} due to {}", line, err);
+                    None
+                }
+            }
+        })
+        .filter(|e| is_eligible(args, e, &|path, is_dir| args.skip_reason_of_type(path, is_dir), &skipped))
+        .filter(|e| unique_files.first_occurrence(e))
+        .collect();
+    skipped.log(args.skip_permission_errors);
+    files
+}
+
+/// The path of a `--from-list` line. A line of an `--error-log` ends at its first tab, which the error follows
+fn listed_path(line: &str, error_log: bool) -> &str {
+    let path = if error_log { line.split('\t').next().unwrap_or_default() } else { line };
+    path.trim()
+}
+
+/// Whether the entry passes all filters, files that do not are counted in `skipped`
+fn is_eligible(args: &Arguments, e: &DirEntry, skip_reason: &impl Fn(&Path, bool) -> Option<SkipReason>, skipped: &SkipCounts) -> bool {
+    let is_dir = e.file_type().is_dir();
+    let skip = |reason: SkipReason| {
+        if !is_dir {
+            skipped.record(reason);
         }
+        false
     };
-    if style.is_some() {
-        let mut style = style.unwrap();
-        style = style.progress_chars("##-");
-        bar.set_style(style);
+    if let Some(reason) = skip_reason(e.path(), is_dir) {
+        debug!("Skipped copying for {} ({})", e.path().to_str().unwrap_or("<could not read path>"), reason);
+        return skip(reason);
+    }
+    if args.exclude_symlinks && e.path_is_symlink() {
+        debug!("Skipped copying for {} as it is a symlink", e.path().to_string_lossy());
+        return skip(SkipReason::Symlink);
+    }
+    if args.only_symlinks && !e.path_is_symlink() {
+        return skip(SkipReason::NotSymlink);
+    }
+    if is_dir {
+        return args.preserve_empty_dirs;
+    }
+    if !args.has_size_limits() && !args.has_time_limits() {
+        return true;
+    }
+    match e.metadata() {
+        Ok(metadata) => {
+            if args.exclude_empty_files && metadata.len() == 0 {
+                debug!("Skipped copying for {} as it is empty", e.path().to_string_lossy());
+                return skip(SkipReason::Empty);
+            }
+            if !args.is_within_size_limits(metadata.len()) {
+                debug!("Skipped copying for {} due to its size of {} bytes", e.path().to_string_lossy(), metadata.len());
+                return skip(SkipReason::Size);
+            }
+            if !args.has_time_limits() {
+                return true;
+            }
+            match metadata.modified() {
+                Ok(modified) => {
+                    if !args.is_within_time_limits(modified) {
+                        debug!("Skipped copying for {} due to its modification time", e.path().to_string_lossy());
+                        return skip(SkipReason::Time);
+                    }
+                    true
+                }
+                Err(err) => {
+                    debug!("Could not read the modification time of {} due to {}", e.path().to_string_lossy(), err);
+                    skip(SkipReason::Time)
+                }
+            }
+        }
+        Err(err) => {
+            debug!("Could not read the metadata of {} due to {}", e.path().to_string_lossy(), err);
+            false
+        }
     }
-    bar
 }
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {
-        let result = 2 + 2;
-        assert_eq!(result, 4);
+/// How many files the discovery skipped for each reason. Entries outside of the depth limits are never
+/// visited by the walk and hidden directories count as a single entry, as their content is not visited either
+#[derive(Default)]
+struct SkipCounts {
+    counts: Mutex<HashMap<SkipReason, usize>>,
+}
+
+impl SkipCounts {
+    fn record(&self, reason: SkipReason) {
+        *self.counts.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).entry(reason).or_default() += 1;
+    }
+
+    /// The counts from the most to the least common reason, like `412 (ext), 89 (folder)`
+    fn breakdown(&self) -> Option<String> {
+        let counts = self.counts.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut counts: Vec<(SkipReason, usize)> = counts.iter().map(|(reason, count)| (*reason, *count)).collect();
+        counts.sort_by_key(|(reason, count)| (std::cmp::Reverse(*count), *reason));
+        let breakdown: Vec<String> = counts.iter().map(|(reason, count)| format!("{} ({})", count, reason)).collect();
+        (!breakdown.is_empty()).then(|| breakdown.join(", "))
+    }
+
+    /// Logs the [SkipCounts::breakdown] and, unless `skip_permission_errors` is set, warns about entries
+    /// that could not be read due to missing permissions
+    fn log(&self, skip_permission_errors: bool) {
+        if let Some(breakdown) = self.breakdown() {
+            info!("Skipped: {}", breakdown);
+        }
+        let permission_denied = self.counts.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(&SkipReason::PermissionDenied).copied().unwrap_or(0);
+        if permission_denied > 0 {
+            log!(permission_error_level(skip_permission_errors),
+                "{} entries could not be read due to missing permissions, re-run with elevated privileges to include them",
+                permission_denied);
+        }
+    }
+}
+
+/// `--skip-permission-errors` turns the messages about missing permissions into debug ones
+fn permission_error_level(skip_permission_errors: bool) -> Level {
+    if skip_permission_errors { Level::Debug } else { Level::Warn }
+}
+
+/// Passes the discovered files through and logs the [SkipCounts] once they are exhausted
+struct ReportSkipped<I> {
+    files: I,
+    skipped: Arc<SkipCounts>,
+    skip_permission_errors: bool,
+    reported: bool,
+}
+
+impl<I> ReportSkipped<I> {
+    fn new(files: I, skipped: Arc<SkipCounts>, skip_permission_errors: bool) -> ReportSkipped<I> {
+        ReportSkipped { files, skipped, skip_permission_errors, reported: false }
+    }
+}
+
+impl<I: Iterator<Item=DirEntry>> Iterator for ReportSkipped<I> {
+    type Item = DirEntry;
+
+    fn next(&mut self) -> Option<DirEntry> {
+        let next = self.files.next();
+        if next.is_none() && !self.reported {
+            self.reported = true;
+            self.skipped.log(self.skip_permission_errors);
+        }
+        next
+    }
+}
+
+/// Remembers the canonical paths of all directories seen during a walk, so directories reachable
+/// through several links are only descended into once
+#[derive(Default)]
+struct VisitedDirectories {
+    visited: Mutex<HashSet<PathBuf>>,
+}
+
+impl VisitedDirectories {
+    fn first_visit(&self, path: &Path) -> bool {
+        let canonical = match std::fs::canonicalize(path) {
+            Ok(canonical) => { canonical }
+            Err(err) => {
+                debug!("Could not resolve {} due to {}", path.to_string_lossy(), err);
+                return true;
+            }
+        };
+        let first_visit = self.visited.lock().map(|mut visited| visited.insert(canonical)).unwrap_or(true);
+        if !first_visit {
+            warn!("Skipped {} as it links to an already visited directory", path.to_string_lossy());
+        }
+        first_visit
+    }
+}
+
+/// Remembers the canonical paths of all gathered entries, so a file reachable through several paths is
+/// only copied once. Entries that can not be resolved are compared by their path as is
+#[derive(Default)]
+struct UniqueFiles {
+    seen: HashSet<PathBuf>,
+}
+
+impl UniqueFiles {
+    fn first_occurrence(&mut self, e: &DirEntry) -> bool {
+        let canonical = std::fs::canonicalize(e.path()).unwrap_or_else(|err| {
+            debug!("Could not resolve {} due to {}", e.path().to_string_lossy(), err);
+            e.path().to_path_buf()
+        });
+        let first_occurrence = self.seen.insert(canonical);
+        if !first_occurrence {
+            warn!("Skipped {} as the same file was already gathered through another path", e.path().to_string_lossy());
+        }
+        first_occurrence
+    }
+}
+
+/// Counts how many symlinks have been followed to reach an entry. Relies on parents being
+/// seen before their children, which is how [WalkDir] traverses and [jwalk] reads directories
+#[derive(Default)]
+struct SymlinkHops {
+    directory_hops: HashMap<PathBuf, usize>,
+}
+
+impl SymlinkHops {
+    fn hops(&mut self, entry: &DirEntry) -> usize {
+        self.record(entry.path(), entry.path_is_symlink(), entry.file_type().is_dir())
+    }
+
+    fn record(&mut self, path: &Path, is_symlink: bool, is_dir: bool) -> usize {
+        let parent_hops = path.parent()
+            .and_then(|parent| self.directory_hops.get(parent))
+            .copied()
+            .unwrap_or(0);
+        let hops = parent_hops + usize::from(is_symlink);
+        if is_dir {
+            self.directory_hops.insert(path.to_path_buf(), hops);
+        }
+        hops
+    }
+}
+
+/// The outcome of a [copy] run
+pub struct CopyReport {
+    pub duration: Duration,
+    pub copied: usize,
+    pub bytes_copied: u64,
+    pub skipped: usize,
+    /// Files skipped or linked by `--dedup` as their content was already copied
+    pub duplicates: usize,
+    pub failures: Vec<(PathBuf, std::io::Error)>,
+    /// Files that could not be copied due to missing permissions, with `--skip-permission-errors` they are
+    /// counted as skipped instead of as failures
+    pub permission_denied: usize,
+    /// The copied files and bytes per extension, sorted by bytes, if `--stats` was set
+    pub extension_stats: Vec<ExtensionStat>,
+    /// Whether the run was stopped via [Arguments::stop_signal] before all files were processed
+    pub interrupted: bool,
+    /// How much of the `--max-total-size` budget was used, if one was set
+    pub budget: Option<BudgetUsage>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BudgetUsage {
+    pub used: u64,
+    pub limit: u64,
+    /// Whether files were left out because they did not fit into the budget anymore
+    pub exhausted: bool,
+}
+
+/// The bytes the files currently being copied or already copied reserved from `--max-total-size`
+struct SizeBudget {
+    limit: u64,
+    used: AtomicU64,
+    exhausted: AtomicBool,
+}
+
+impl SizeBudget {
+    fn new(limit: u64) -> SizeBudget {
+        SizeBudget { limit, used: AtomicU64::new(0), exhausted: AtomicBool::new(false) }
+    }
+
+    /// Reserves `size` bytes if they still fit, otherwise the budget counts as exhausted
+    fn try_reserve(&self, size: u64) -> bool {
+        let reserved = self.used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| used.checked_add(size).filter(|total| *total <= self.limit))
+            .is_ok();
+        if !reserved {
+            self.exhausted.store(true, Ordering::SeqCst);
+        }
+        reserved
+    }
+
+    /// Returns a reservation that was not used by a copy after all
+    fn release(&self, size: u64) {
+        self.used.fetch_sub(size, Ordering::SeqCst);
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.exhausted.load(Ordering::SeqCst)
+    }
+
+    fn usage(&self) -> BudgetUsage {
+        BudgetUsage { used: self.used.load(Ordering::SeqCst), limit: self.limit, exhausted: self.is_exhausted() }
+    }
+}
+
+/// Lets a running copy wind down early, e.g. on Ctrl-C. Once requested no new copies are started, while the
+/// running ones still complete. [StopSignal::abort] deals with copies that should not be waited for
+#[derive(Clone, Default, Debug)]
+pub struct StopSignal {
+    requested: Arc<AtomicBool>,
+    in_flight: Arc<Mutex<HashSet<PathBuf>>>,
+    resume_state: Arc<Mutex<Weak<ResumeState>>>,
+}
+
+impl StopSignal {
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Deletes the partially written targets of all copies still running and writes the buffered paths of the
+    /// resume state, the process should exit right afterwards
+    pub fn abort(&self) {
+        self.request();
+        let in_flight = self.in_flight.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for target in in_flight.iter() {
+            if let Err(err) = std::fs::remove_file(target) {
+                warn!("Could not delete the partial copy {} due to {}", target.to_string_lossy(), err);
+            }
+        }
+        let resume_state = self.resume_state.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).upgrade();
+        if let Some(resume_state) = resume_state {
+            if let Err(err) = resume_state.flush() {
+                warn!("Could not write the resume state due to {}", err);
+            }
+        }
+    }
+
+    /// Registers the resume state of the running copy to be flushed by [StopSignal::abort] while it is alive
+    fn flush_on_abort(&self, resume_state: &Arc<ResumeState>) {
+        *self.resume_state.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::downgrade(resume_state);
+    }
+
+    /// Registers `target` as being written until the returned guard is dropped
+    fn track(&self, target: &Path) -> InFlightCopy<'_> {
+        let target = target.to_path_buf();
+        self.in_flight.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(target.clone());
+        InFlightCopy { signal: self, target }
+    }
+}
+
+struct InFlightCopy<'a> {
+    signal: &'a StopSignal,
+    target: PathBuf,
+}
+
+impl Drop for InFlightCopy<'_> {
+    fn drop(&mut self) {
+        self.signal.in_flight.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(&self.target);
+    }
+}
+
+impl CopyReport {
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// The effective throughput over the whole run
+    pub fn bytes_per_second(&self) -> u64 {
+        let seconds = self.duration.as_secs_f64();
+        if seconds == 0.0 {
+            return 0;
+        }
+        (self.bytes_copied as f64 / seconds) as u64
+    }
+}
+
+/// Errors that prevent [copy] from producing a [CopyReport] at all. Failures of individual files
+/// are not errors, they are collected in [CopyReport::failures] instead
+#[derive(Debug)]
+pub enum CopyError {
+    /// A worker thread panicked while holding the failure collector
+    CollectorPoisoned,
+    /// The manifest requested via `--manifest` could not be written
+    ManifestWrite(std::io::Error),
+    /// The state file requested via `--resume` could not be read or written
+    ResumeState(std::io::Error),
+    /// The file requested via `--error-log` could not be written
+    ErrorLog(std::io::Error),
+    /// The earlier checksum manifest requested via `--since-manifest` could not be read
+    SinceManifest(std::io::Error),
+    /// The archive requested via `--archive` could not be created or completed
+    Archive(std::io::Error),
+    /// The run stopped after the first failed copy as requested via `--on-error abort`
+    Aborted(PathBuf, std::io::Error),
+    /// The worker pool requested via `--threads` could not be created
+    ThreadPool(ThreadPoolBuildError),
+}
+
+impl Display for CopyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CopyError::CollectorPoisoned => { write!(f, "Could not collect copy failures, a worker thread panicked") }
+            CopyError::ManifestWrite(err) => { write!(f, "Could not write the manifest due to {}", err) }
+            CopyError::ResumeState(err) => { write!(f, "Could not access the resume state file due to {}", err) }
+            CopyError::ErrorLog(err) => { write!(f, "Could not write the error log due to {}", err) }
+            CopyError::SinceManifest(err) => { write!(f, "Could not read the earlier checksum manifest due to {}", err) }
+            CopyError::Archive(err) => { write!(f, "Could not write the archive due to {}", err) }
+            CopyError::Aborted(path, err) => { write!(f, "Aborted after failing to copy {} due to {}", path.to_string_lossy(), err) }
+            CopyError::ThreadPool(err) => { write!(f, "Could not create the worker pool due to {}", err) }
+        }
+    }
+}
+
+impl std::error::Error for CopyError {}
+
+/// Progress of a running copy, reported from the worker threads for every processed entry
+#[derive(Debug)]
+pub enum CopyEvent<'a> {
+    /// `file` is about to be copied
+    Started { file: &'a Path },
+    /// `file` was processed, `bytes_copied` is 0 if it was skipped or failed. `size` is the file's size
+    /// regardless of the outcome, the running totals cover all entries processed so far
+    Finished { file: &'a Path, size: u64, bytes_copied: u64, processed: usize, total_bytes_copied: u64 },
+}
+
+pub fn copy(args: Arguments, files: Vec<DirEntry>) -> Result<CopyReport, CopyError> {
+    copy_files(&args, files)
+}
+
+/// Copies like [copy], but only borrows the arguments, e.g. to keep using them for [watch::watch] afterwards
+pub fn copy_files(args: &Arguments, files: Vec<DirEntry>) -> Result<CopyReport, CopyError> {
+    let length = if args.progress_by_bytes { total_size(&files) } else { files.len() as u64 };
+    let bar = create_progress_bar(length, args.progress_by_bytes, progress_draw_target(args));
+    let on_progress = advance_progress_bar(&bar, args.progress_by_bytes);
+    if let Some(progress_writer) = &args.progress_writer {
+        progress_writer.set_total(files.len());
+    }
+    let report = copy_with(args, &on_progress, |copy_entry| files.par_iter().for_each(copy_entry));
+    bar.finish();
+    report
+}
+
+/// Copies like [copy], but reports the progress to `on_progress` instead of drawing a progress bar.
+/// It is called concurrently from the worker threads, so it should return quickly
+pub fn copy_with_progress(args: Arguments, files: Vec<DirEntry>, on_progress: impl Fn(CopyEvent) + Sync) -> Result<CopyReport, CopyError> {
+    if let Some(progress_writer) = &args.progress_writer {
+        progress_writer.set_total(files.len());
+    }
+    copy_with(&args, &on_progress, |copy_entry| files.par_iter().for_each(copy_entry))
+}
+
+/// Copies the files while they are still being discovered, e.g. by [discover_files]. As their total is unknown
+/// until the discovery finishes, the progress bar starts out as a spinner
+pub fn copy_streaming(args: &Arguments, files: impl Iterator<Item=DirEntry> + Send) -> Result<CopyReport, CopyError> {
+    let bar = create_spinner(args.progress_by_bytes, progress_draw_target(args));
+    let files = files.take_while(|_| !args.stop_signal.is_requested());
+    let files = DiscoveryProgress::new(files, bar.clone(), args.progress_by_bytes, args.progress_writer.as_ref());
+    let report = copy_with(args, &advance_progress_bar(&bar, args.progress_by_bytes),
+                           |copy_entry| files.par_bridge().for_each(|entry| copy_entry(&entry)));
+    bar.finish();
+    report
+}
+
+/// Shows the current file's name on `bar` and advances it by files or bytes
+fn advance_progress_bar(bar: &ProgressBar, by_bytes: bool) -> impl Fn(CopyEvent) + Sync + '_ {
+    let show_file_names = !bar.is_hidden();
+    move |event| {
+        match event {
+            CopyEvent::Started { file } => {
+                if show_file_names {
+                    let file_name = file.file_name().unwrap_or_default().to_string_lossy();
+                    bar.set_message(truncate_file_name(&file_name, MAX_FILE_NAME_LENGTH));
+                }
+            }
+            CopyEvent::Finished { size, .. } => { bar.inc(if by_bytes { size } else { 1 }) }
+        }
+    }
+}
+
+fn progress_draw_target(args: &Arguments) -> ProgressDrawTarget {
+    let (draw_target, stream) = match args.progress_to {
+        ProgressTarget::Stderr => { (ProgressDrawTarget::stderr(), Stream::Stderr) }
+        ProgressTarget::Stdout => { (ProgressDrawTarget::stdout(), Stream::Stdout) }
+        ProgressTarget::Hidden => { return ProgressDrawTarget::hidden(); }
+    };
+    // indicatif colors the bar through console, which would only look at stdout to decide on colors
+    console::set_colors_enabled(is_colorful_supported_on(stream));
+    draw_target
+}
+
+/// Runs `copy_all`, which has to feed every entry to the copy function it is handed
+fn copy_with<F>(args: &Arguments, on_progress: &(dyn Fn(CopyEvent) + Sync), copy_all: F) -> Result<CopyReport, CopyError>
+    where F: FnOnce(&(dyn Fn(&DirEntry) + Sync)) + Send {
+    let start_time = Instant::now();
+    info!("Beginning copy-process...");
+    let counter = RelaxedCounter::new(0);
+    let skipped = RelaxedCounter::new(0);
+    let duplicates = RelaxedCounter::new(0);
+    let bytes_copied = AtomicU64::new(0);
+    let failures: Mutex<Vec<(PathBuf, std::io::Error)>> = Mutex::new(Vec::new());
+    let manifest_entries: Mutex<Vec<ManifestEntry>> = Mutex::new(Vec::new());
+    let aborted = AtomicBool::new(false);
+    let error_log = args.error_log.as_deref().map(ErrorLog::create).transpose().map_err(CopyError::ErrorLog)?;
+    let permission_denied = RelaxedCounter::new(0);
+    let record_failure = |source_path: &Path, err: std::io::Error| {
+        let file = source_path.to_string_lossy();
+        if err.kind() == ErrorKind::PermissionDenied {
+            permission_denied.inc();
+            if args.skip_permission_errors {
+                debug!(file = file.as_ref(); "Skipped {} due to {}", file, err);
+                skipped.inc();
+                return;
+            }
+        }
+        warn!(file = file.as_ref(); "Failed to copy {} due to {}", file, err);
+        if let Some(error_log) = &error_log {
+            if let Err(log_err) = error_log.record(source_path, &err) {
+                warn!("Could not record {} in the error log due to {}", file, log_err);
+            }
+        }
+        if args.on_error == OnError::Abort {
+            aborted.store(true, Ordering::SeqCst);
+        }
+        if let Ok(mut failures) = failures.lock() {
+            failures.push((source_path.to_path_buf(), err));
+        }
+    };
+    let flattened_targets = FlattenedTargets::default();
+    let content_hashes = ContentHashes::default();
+    let size_budget = args.max_total_size.map(SizeBudget::new);
+    let open_files = args.max_open_files.map(Semaphore::new);
+    let extension_stats = args.stats.then(ExtensionStats::default);
+    let checksum_manifest = args.checksum_manifest.is_some().then(ChecksumManifest::default);
+    let target_root = long_path(Path::new(&args.target_root_file_path));
+    let archive = args.archive.as_deref().filter(|_| !args.dry_run).map(Archive::create).transpose().map_err(CopyError::Archive)?;
+    let resume_state = args.resume.as_deref().map(ResumeState::open).transpose().map_err(CopyError::ResumeState)?
+        .map(Arc::new);
+    let previous_checksums = args.since_manifest.as_deref().map(PreviousChecksums::read).transpose().map_err(CopyError::SinceManifest)?;
+    if let Some(resume_state) = &resume_state {
+        info!("Resuming, {} files were already copied by earlier runs", resume_state.completed_count());
+        args.stop_signal.flush_on_abort(resume_state);
     }
-}
\ No newline at end of file
+    let copy_entry = |entry: &DirEntry| -> u64 {
+        let source_path = entry.path();
+        let source_path_string = source_path.to_string_lossy().to_string();
+        if resume_state.as_ref().is_some_and(|resume_state| resume_state.is_completed(source_path)) {
+            debug!("Skipped {} as an earlier run already copied it", source_path_string);
+            skipped.inc();
+            return 0;
+        }
+        let target_path = match args.transform_source_to_target_path(source_path) {
+            Ok(target_path) => { target_path }
+            Err(err) => {
+                record_failure(source_path, err);
+                return 0;
+            }
+        };
+        if (args.flatten || args.target_template.is_some()) && entry.file_type().is_dir() {
+            return 0;
+        }
+        let collisions = if args.flatten || args.target_template.is_some() {
+            Some(args.flatten_collisions)
+        } else if args.source_roots.len() > 1 && !entry.file_type().is_dir() {
+            Some(args.source_collisions)
+        } else {
+            None
+        };
+        let target_path = match collisions {
+            Some(collisions) => {
+                let target_path_string = target_path.to_string_lossy().to_string();
+                match flattened_targets.claim(target_path, collisions) {
+                    Some(target_path) => { target_path }
+                    None => {
+                        debug!("Skipped {} as another file was already copied to {}", source_path_string, target_path_string);
+                        skipped.inc();
+                        return 0;
+                    }
+                }
+            }
+            None => { target_path }
+        };
+        if entry.file_type().is_dir() {
+            let created = match &archive {
+                Some(archive) => {
+                    archive_path(&target_root, source_path, &target_path)
+                        .and_then(|archive_path| archive.add(source_path, &archive_path)).map(|_| ())
+                }
+                None if args.dry_run => {
+                    info!("Would create directory {}", target_path.to_string_lossy());
+                    Ok(())
+                }
+                None => { create_dirs(&target_path, args.chmod_dirs) }
+            };
+            if let Err(err) = created {
+                record_failure(source_path, err);
+            }
+            return 0;
+        }
+        let copies_link = args.copy_links_as_links && entry.path_is_symlink();
+        // A link pointing at the same file as the source link is still a different link
+        if !copies_link && is_same_file(source_path, &target_path) {
+            warn!("Skipped {} as its target {} is the file itself", source_path_string, target_path.to_string_lossy());
+            skipped.inc();
+            return 0;
+        }
+        if let Some(previous_checksums) = &previous_checksums {
+            let relative_target = target_path.strip_prefix(&target_root).unwrap_or(&target_path);
+            match previous_checksums.unchanged(relative_target, source_path) {
+                Ok(Some(checksum)) => {
+                    debug!("Skipped {} as its checksum matches the earlier manifest", source_path_string);
+                    // Keeps the new manifest complete, so it can serve as the next run's --since-manifest
+                    if let Some(checksum_manifest) = &checksum_manifest {
+                        let checksum = if previous_checksums.algorithm() == args.checksum_algo {
+                            Ok(checksum)
+                        } else {
+                            hash::checksum(source_path, args.checksum_algo)
+                        };
+                        match checksum {
+                            Ok(checksum) => { checksum_manifest.record(relative_target.to_path_buf(), checksum) }
+                            Err(err) => { warn!("Could not compute the checksum of {} due to {}", source_path_string, err) }
+                        }
+                    }
+                    skipped.inc();
+                    return 0;
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    record_failure(source_path, err);
+                    return 0;
+                }
+            }
+        }
+        let reserved = entry_size(entry);
+        if size_budget.as_ref().is_some_and(|size_budget| !size_budget.try_reserve(reserved)) {
+            info!("Stopped starting new copies as {} does not fit into the remaining size budget", source_path_string);
+            skipped.inc();
+            return 0;
+        }
+        let release_reservation = || {
+            if let Some(size_budget) = &size_budget {
+                size_budget.release(reserved);
+            }
+        };
+        if args.dry_run {
+            info!(file = source_path_string.as_str(); "Would copy {} to {}", source_path_string, target_path.to_string_lossy());
+            return 0;
+        }
+        // Decided before anything is written, so a simulated failure never leaves a placeholder or link behind
+        let simulated_failure = args.simulate_errors.is_some_and(|simulation| simulation.fails(source_path));
+        let renamed = !simulated_failure && args.overwrite == OverwritePolicy::Rename
+            && std::fs::symlink_metadata(&target_path).is_ok()
+            && !(args.skip_existing && is_same_file_at_target(source_path, &target_path));
+        let target_path = if renamed {
+            match reserve_numbered_path(&target_path) {
+                Ok(numbered_path) => { numbered_path }
+                Err(err) => {
+                    release_reservation();
+                    record_failure(source_path, err);
+                    return 0;
+                }
+            }
+        } else {
+            target_path
+        };
+        let outcome = match &archive {
+            _ if simulated_failure => {
+                Err(std::io::Error::other("simulated failure of --simulate-error-rate"))
+            }
+            // The content behind a link belongs to another entry, so links never take part in --dedup
+            _ if copies_link => { copy_link(args, source_path, &target_path) }
+            Some(archive) => {
+                deduplicate(args, &content_hashes, source_path, &target_path, || {
+                    archive_path(&target_root, source_path, &target_path)
+                        .and_then(|archive_path| archive.add(source_path, &archive_path)).map(CopyOutcome::Copied)
+                })
+            }
+            None if args.mode == Mode::Copy => {
+                copy_file(args, source_path, &target_path, args.dedup.then_some(&content_hashes))
+            }
+            None => {
+                deduplicate(args, &content_hashes, source_path, &target_path, || copy_file(args, source_path, &target_path, None))
+            }
+        };
+        if renamed && !matches!(outcome, Ok(CopyOutcome::Copied(_) | CopyOutcome::Duplicate { linked: true, .. })) {
+            release_numbered_path(&target_path);
+        }
+        if args.manifest.is_some() {
+            let entry = match &outcome {
+                Ok(CopyOutcome::Copied(bytes)) => { Some((*bytes, true)) }
+                Ok(CopyOutcome::Skipped(_)) => { None }
+                Ok(CopyOutcome::Duplicate { linked: true, .. }) => { Some((entry_size(entry), true)) }
+                Ok(CopyOutcome::Duplicate { linked: false, .. }) => { None }
+                Err(_) => { Some((entry.metadata().map(|metadata| metadata.len()).unwrap_or(0), false)) }
+            };
+            if let (Some((size, success)), Ok(mut manifest_entries)) = (entry, manifest_entries.lock()) {
+                manifest_entries.push(ManifestEntry {
+                    source: source_path.to_path_buf(),
+                    target: target_path.clone(),
+                    size,
+                    success,
+                });
+            }
+        }
+        if let (Some(checksum_manifest), Ok(CopyOutcome::Copied(_) | CopyOutcome::Duplicate { linked: true, .. })) = (&checksum_manifest, &outcome) {
+            match hash::checksum(&target_path, args.checksum_algo) {
+                Ok(checksum) => {
+                    let relative_target = target_path.strip_prefix(&target_root).unwrap_or(&target_path);
+                    checksum_manifest.record(relative_target.to_path_buf(), checksum);
+                }
+                Err(err) => { warn!("Could not compute the checksum of {} due to {}", target_path.to_string_lossy(), err) }
+            }
+        }
+        if let (Some(resume_state), Ok(CopyOutcome::Copied(_) | CopyOutcome::Duplicate { linked: true, .. })) = (&resume_state, &outcome) {
+            if let Err(err) = resume_state.record(source_path) {
+                warn!("Could not record {} in the resume state file due to {}", source_path_string, err);
+            }
+        }
+        match outcome {
+            Ok(CopyOutcome::Copied(bytes)) => {
+                debug!(file = source_path_string.as_str(); "Successfully copied {} ({} bytes)", source_path_string, bytes);
+                if let Some(extension_stats) = &extension_stats {
+                    extension_stats.record(&entry.file_name().to_string_lossy(), bytes);
+                }
+                counter.inc();
+                bytes_copied.fetch_add(bytes, Ordering::Relaxed);
+                bytes
+            }
+            Ok(CopyOutcome::Skipped(reason)) => {
+                debug!(file = source_path_string.as_str(); "Skipped {} as {}", source_path_string, reason);
+                release_reservation();
+                skipped.inc();
+                0
+            }
+            Ok(CopyOutcome::Duplicate { first_target, linked }) => {
+                let action = if linked { "Linked" } else { "Skipped" };
+                debug!("{} {} as it duplicates {}", action, source_path_string, first_target.to_string_lossy());
+                release_reservation();
+                duplicates.inc();
+                0
+            }
+            Err(err) => {
+                release_reservation();
+                record_failure(source_path, err);
+                0
+            }
+        }
+    };
+    let processed = RelaxedCounter::new(0);
+    let adaptive_concurrency = args.adaptive_threads.then(|| {
+        AdaptiveConcurrency::new(args.threads.filter(|threads| *threads > 0).unwrap_or(DEFAULT_MAX_CONCURRENCY))
+    });
+    let copy_and_report = |entry: &DirEntry| {
+        if args.stop_signal.is_requested() || aborted.load(Ordering::SeqCst)
+            || size_budget.as_ref().is_some_and(|size_budget| size_budget.is_exhausted()) {
+            return;
+        }
+        let _permit = adaptive_concurrency.as_ref().map(|concurrency| concurrency.acquire());
+        let _open_files = open_files.as_ref().map(|open_files| open_files.acquire(OPEN_FILES_PER_COPY));
+        let file = entry.path();
+        let size = entry_size(entry);
+        on_progress(CopyEvent::Started { file });
+        let copied = copy_entry(entry);
+        let processed = processed.inc() + 1;
+        let total_bytes_copied = bytes_copied.load(Ordering::Relaxed);
+        on_progress(CopyEvent::Finished { file, size, bytes_copied: copied, processed, total_bytes_copied });
+        if let Some(progress_writer) = &args.progress_writer {
+            progress_writer.update(processed, total_bytes_copied);
+        }
+    };
+    let run = || copy_all(&copy_and_report);
+    let threads = adaptive_concurrency.as_ref().map_or(args.threads, |concurrency| Some(concurrency.max_concurrency()));
+    let pool = build_thread_pool(threads).map_err(CopyError::ThreadPool)?;
+    let archive_in_flight = archive.as_ref().map(|archive| args.stop_signal.track(archive.part_path()));
+    std::thread::scope(|scope| {
+        let _finish_monitor = adaptive_concurrency.as_ref().map(|concurrency| {
+            scope.spawn(|| concurrency.monitor(&bytes_copied));
+            concurrency.finish_on_drop()
+        });
+        match pool {
+            Some(pool) => { pool.install(run) }
+            None => { run() }
+        }
+    });
+    if let Some(progress_writer) = &args.progress_writer {
+        progress_writer.finish(processed.get(), bytes_copied.load(Ordering::Relaxed));
+    }
+    if let Some(resume_state) = &resume_state {
+        resume_state.flush().map_err(CopyError::ResumeState)?;
+    }
+    let mut failures = failures.into_inner().map_err(|_| CopyError::CollectorPoisoned)?;
+    if let Some(error_log) = &error_log {
+        error_log.flush().map_err(CopyError::ErrorLog)?;
+    }
+    drop(archive_in_flight);
+    if let Some(archive) = archive {
+        archive.finish().map_err(CopyError::Archive)?;
+    }
+    if aborted.into_inner() && !failures.is_empty() {
+        let (path, err) = failures.remove(0);
+        return Err(CopyError::Aborted(path, err));
+    }
+    info!("Finished copying all files!");
+    if permission_denied.get() > 0 {
+        log!(permission_error_level(args.skip_permission_errors),
+            "{} files could not be copied due to missing permissions, re-run with elevated privileges to include them",
+            permission_denied.get());
+    }
+    let duration = start_time.elapsed();
+    if let Some(manifest_path) = &args.manifest {
+        let entries = manifest_entries.into_inner().map_err(|_| CopyError::CollectorPoisoned)?;
+        Manifest::new(duration, entries).write_atomically(manifest_path).map_err(CopyError::ManifestWrite)?;
+    }
+    if let (Some(checksum_manifest), Some(checksum_manifest_path)) = (checksum_manifest, &args.checksum_manifest) {
+        checksum_manifest.write_atomically(checksum_manifest_path).map_err(CopyError::ManifestWrite)?;
+    }
+    Ok(CopyReport {
+        duration,
+        copied: counter.get(),
+        bytes_copied: bytes_copied.into_inner(),
+        skipped: skipped.get(),
+        duplicates: duplicates.get(),
+        failures,
+        permission_denied: permission_denied.get(),
+        extension_stats: extension_stats.map(ExtensionStats::into_sorted).unwrap_or_default(),
+        interrupted: args.stop_signal.is_requested(),
+        budget: size_budget.map(|size_budget| size_budget.usage()),
+    })
+}
+
+/// The path of `target_path` within the `--archive` at `target_root`, which has to be the same [long_path] as
+/// the target paths. A single file copied into it keeps its file name
+fn archive_path(target_root: &Path, source_path: &Path, target_path: &Path) -> std::io::Result<PathBuf> {
+    match target_path.strip_prefix(target_root) {
+        Ok(relative_path) if relative_path.as_os_str().is_empty() => { Ok(PathBuf::from(source_path.file_name().unwrap_or_default())) }
+        Ok(relative_path) => { Ok(relative_path.to_path_buf()) }
+        Err(_) => {
+            Err(std::io::Error::new(ErrorKind::InvalidInput,
+                                    format!("{} is not located within the archive {}", target_path.to_string_lossy(), target_root.to_string_lossy())))
+        }
+    }
+}
+
+/// A copy keeps its source and its target open
+const OPEN_FILES_PER_COPY: usize = 2;
+
+/// The target paths already taken by flattened or templated files or files of multiple source roots in the
+/// current run
+#[derive(Default)]
+struct FlattenedTargets {
+    claimed: Mutex<HashSet<PathBuf>>,
+}
+
+impl FlattenedTargets {
+    /// Reserves `target_path`, or if it is already taken either the first free suffixed variant or nothing
+    fn claim(&self, target_path: PathBuf, collisions: FlattenCollision) -> Option<PathBuf> {
+        let mut claimed = self.claimed.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if claimed.insert(target_path.clone()) {
+            return Some(target_path);
+        }
+        if collisions == FlattenCollision::Skip {
+            return None;
+        }
+        (1..).map(|suffix| suffixed_path(&target_path, suffix))
+            .find(|candidate| claimed.insert(candidate.clone()))
+    }
+}
+
+/// Appends `_<suffix>` to the file stem, so `photo.jpg` becomes `photo_1.jpg`
+fn suffixed_path(path: &Path, suffix: usize) -> PathBuf {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    let file_name = match path.extension() {
+        Some(extension) => { format!("{}_{}.{}", stem, suffix, extension.to_string_lossy()) }
+        None => { format!("{}_{}", stem, suffix) }
+    };
+    path.with_file_name(file_name)
+}
+
+/// Appends ` (<number>)` to the file stem, so `photo.jpg` becomes `photo (1).jpg`
+fn numbered_path(path: &Path, number: usize) -> PathBuf {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    let file_name = match path.extension() {
+        Some(extension) => { format!("{} ({}).{}", stem, number, extension.to_string_lossy()) }
+        None => { format!("{} ({})", stem, number) }
+    };
+    path.with_file_name(file_name)
+}
+
+/// Finds the first [numbered_path] of `path` that does not exist yet for `--overwrite rename` and creates it
+/// as an empty placeholder. As it is created with `create_new`, concurrent copies can never pick the same one
+fn reserve_numbered_path(path: &Path) -> std::io::Result<PathBuf> {
+    for number in 1.. {
+        let candidate = numbered_path(path, number);
+        match OpenOptions::new().write(true).create_new(true).open(&candidate) {
+            Ok(_) => { return Ok(candidate); }
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => {}
+            Err(err) => { return Err(err); }
+        }
+    }
+    unreachable!("ran out of numbers for {}", path.to_string_lossy())
+}
+
+/// Deletes the placeholder of [reserve_numbered_path] again if nothing was written to it
+fn release_numbered_path(path: &Path) {
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_file() && metadata.len() == 0) {
+        if let Err(err) = std::fs::remove_file(path) {
+            warn!("Could not delete the unused target {} due to {}", path.to_string_lossy(), err);
+        }
+    }
+}
+
+/// Passes discovered entries through while counting them, so the progress bar can switch from a spinner
+/// to a bar of known length once the discovery is exhausted
+struct DiscoveryProgress<'a, I> {
+    files: I,
+    bar: ProgressBar,
+    progress_writer: Option<&'a ProgressWriter>,
+    by_bytes: bool,
+    discovered: u64,
+    progress_length: u64,
+    finished: bool,
+}
+
+impl<'a, I> DiscoveryProgress<'a, I> {
+    fn new(files: I, bar: ProgressBar, by_bytes: bool, progress_writer: Option<&'a ProgressWriter>) -> DiscoveryProgress<'a, I> {
+        DiscoveryProgress { files, bar, progress_writer, by_bytes, discovered: 0, progress_length: 0, finished: false }
+    }
+}
+
+impl<I: Iterator<Item=DirEntry>> Iterator for DiscoveryProgress<'_, I> {
+    type Item = DirEntry;
+
+    fn next(&mut self) -> Option<DirEntry> {
+        let next = self.files.next();
+        match &next {
+            Some(entry) => {
+                self.discovered += 1;
+                self.progress_length += if self.by_bytes { entry_size(entry) } else { 1 };
+            }
+            None if !self.finished => {
+                self.finished = true;
+                info!("Found {} files and directories eligible for copying", self.discovered);
+                self.bar.set_length(self.progress_length);
+                if let Some(progress_writer) = self.progress_writer {
+                    progress_writer.set_total(self.discovered as usize);
+                }
+                if let Some(style) = progress_style(self.by_bytes) {
+                    self.bar.set_style(style);
+                }
+            }
+            None => {}
+        }
+        next
+    }
+}
+
+/// Sorts `files` for copying them in `order`, reading the metadata of every file only once
+pub fn sort_files(files: &mut [DirEntry], order: Order) {
+    match order {
+        Order::Path => { files.sort_by(|first, second| first.path().cmp(second.path())) }
+        Order::SizeAsc => { files.sort_by_cached_key(entry_size) }
+        Order::SizeDesc => { files.sort_by_cached_key(|entry| std::cmp::Reverse(entry_size(entry))) }
+        Order::Mtime => {
+            files.sort_by_cached_key(|entry| {
+                entry.metadata().ok().and_then(|metadata| metadata.modified().ok()).unwrap_or(SystemTime::UNIX_EPOCH)
+            })
+        }
+    }
+}
+
+/// The summed size of all files in `files` in bytes, directories do not count
+pub fn total_size(files: &[DirEntry]) -> u64 {
+    files.iter().map(entry_size).sum()
+}
+
+/// The number of files in `files` and their summed size in bytes, directories count for neither
+pub fn count_files(files: impl IntoIterator<Item=DirEntry>) -> (usize, u64) {
+    files.into_iter()
+        .filter(|entry| !entry.file_type().is_dir())
+        .fold((0, 0), |(count, bytes), entry| (count + 1, bytes + entry_size(&entry)))
+}
+
+fn entry_size(entry: &DirEntry) -> u64 {
+    if entry.file_type().is_dir() {
+        return 0;
+    }
+    entry.metadata().map(|metadata| metadata.len()).unwrap_or(0)
+}
+
+/// Builds a dedicated pool with `threads` workers, `None` or 0 keep rayon's global default pool
+fn build_thread_pool(threads: Option<usize>) -> Result<Option<ThreadPool>, ThreadPoolBuildError> {
+    match threads {
+        None | Some(0) => { Ok(None) }
+        Some(threads) => { ThreadPoolBuilder::new().num_threads(threads).build().map(Some) }
+    }
+}
+
+enum CopyOutcome {
+    Copied(u64),
+    Skipped(&'static str),
+    /// The content was already copied to `first_target`, `linked` tells whether a hardlink to it was created
+    Duplicate { first_target: PathBuf, linked: bool },
+}
+
+/// The target of the first file seen for each content hash
+#[derive(Default)]
+struct ContentHashes {
+    first_targets: Mutex<HashMap<u128, PathBuf>>,
+}
+
+impl ContentHashes {
+    /// The target the content of `hash` was first copied to
+    fn first_target(&self, hash: u128) -> Option<PathBuf> {
+        let first_targets = self.first_targets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        first_targets.get(&hash).cloned()
+    }
+
+    /// Remembers `target_path` as the first target of `hash` once it was copied successfully, so a failed or
+    /// skipped copy never turns later files with the same content into duplicates of nothing
+    fn register(&self, hash: u128, target_path: &Path) {
+        let mut first_targets = self.first_targets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Entry::Vacant(vacant) = first_targets.entry(hash) {
+            vacant.insert(target_path.to_path_buf());
+        }
+    }
+}
+
+/// Runs `copy` unless the content of `source_path` was already copied in this run. This hashes the source up
+/// front, which is meant for the modes and the archive that do not read the content from the copying threads
+/// anyway, [copy_file] hashes regular copies while writing them instead
+fn deduplicate(args: &Arguments, hashes: &ContentHashes, source_path: &Path, target_path: &Path,
+               copy: impl FnOnce() -> std::io::Result<CopyOutcome>) -> std::io::Result<CopyOutcome> {
+    if !args.dedup {
+        return copy();
+    }
+    let hash = hash::content_hash(source_path)?;
+    if let Some(duplicate) = duplicate_of(args, hashes, hash, source_path, target_path)? {
+        return Ok(duplicate);
+    }
+    let outcome = copy()?;
+    if let CopyOutcome::Copied(_) = outcome {
+        hashes.register(hash, target_path);
+    }
+    Ok(outcome)
+}
+
+/// The outcome for a file whose content hashes to `hash` if that content was already copied, [None] means it
+/// still has to be copied. If linking to the first copy fails, e.g. as it is on another device, the file is
+/// copied instead
+fn duplicate_of(args: &Arguments, hashes: &ContentHashes, hash: u128, source_path: &Path, target_path: &Path)
+                -> std::io::Result<Option<CopyOutcome>> {
+    let Some(first_target) = hashes.first_target(hash) else {
+        return Ok(None);
+    };
+    if !args.dedup_link {
+        return Ok(Some(CopyOutcome::Duplicate { first_target, linked: false }));
+    }
+    if !args.overwrite.allows_writing(source_path, target_path) {
+        return Ok(None);
+    }
+    create_parent_dirs(target_path, args.chmod_dirs)?;
+    match hard_link_file(&first_target, target_path, args) {
+        Ok(_) => { Ok(Some(CopyOutcome::Duplicate { first_target, linked: true })) }
+        Err(err) => {
+            debug!("Could not link {} to {} due to {}, copying it instead", target_path.to_string_lossy(), first_target.to_string_lossy(), err);
+            Ok(None)
+        }
+    }
+}
+
+/// Copies `source_path` to `target_path` in the configured mode. With `hashes`, which `--dedup` passes for
+/// [Mode::Copy], the content is hashed while it is written to the part file, and the part file is dropped
+/// again if the content turns out to be a duplicate
+fn copy_file(args: &Arguments, source_path: &Path, target_path: &Path, hashes: Option<&ContentHashes>) -> std::io::Result<CopyOutcome> {
+    if args.skip_existing && is_same_file_at_target(source_path, target_path) {
+        return Ok(CopyOutcome::Skipped("it already exists at the target"));
+    }
+    if !args.overwrite.allows_writing(source_path, target_path) {
+        return Ok(CopyOutcome::Skipped("the overwrite policy forbids replacing the target"));
+    }
+    create_parent_dirs(target_path, args.chmod_dirs)?;
+    let source_times = if args.preserve_timestamps && args.mode != Mode::Symlink {
+        read_file_times(source_path)
+    } else {
+        None
+    };
+    let source_metadata = if args.preserve_permissions && matches!(args.mode, Mode::Copy | Mode::Move) {
+        read_metadata(source_path)
+    } else {
+        None
+    };
+    let source_xattrs = if args.preserve_xattrs && matches!(args.mode, Mode::Copy | Mode::Move) {
+        xattrs::read(source_path)
+    } else {
+        None
+    };
+    let part_file = (args.mode == Mode::Copy).then(|| PartFile::for_target(target_path));
+    let written_path = part_file.as_ref().map_or_else(|| target_path.to_path_buf(), |part_file| part_file.path.clone());
+    let hash_while_copying = hashes.is_some() && args.mode == Mode::Copy;
+    let (bytes, content_hash) = with_retries(args.retries, source_path, || match args.mode {
+        Mode::Copy if hash_while_copying => {
+            let _in_flight = args.stop_signal.track(&written_path);
+            copy_contents_hashed(source_path, &written_path, args).map(|(bytes, hash)| (bytes, Some(hash)))
+        }
+        Mode::Copy => {
+            let _in_flight = args.stop_signal.track(&written_path);
+            copy_contents(source_path, &written_path, args).map(|bytes| (bytes, None))
+        }
+        Mode::Move => { move_file(source_path, target_path, args).map(|bytes| (bytes, None)) }
+        Mode::Hardlink => { hard_link_file(source_path, target_path, args).map(|bytes| (bytes, None)) }
+        Mode::Symlink => { symlink_file(source_path, target_path).map(|bytes| (bytes, None)) }
+    })?;
+    if let (Some(hashes), Some(content_hash)) = (hashes, content_hash) {
+        if let Some(duplicate) = duplicate_of(args, hashes, content_hash, source_path, target_path)? {
+            return Ok(duplicate);
+        }
+    }
+    let part_file = match part_file {
+        Some(part_file) if args.verify => { Some(verified(source_path, part_file, target_path, args.verify_delete_on_mismatch)?) }
+        part_file => { part_file }
+    };
+    if let Some(source_times) = source_times {
+        apply_file_times(&written_path, source_times);
+    }
+    if let Some(source_xattrs) = source_xattrs {
+        xattrs::apply(&written_path, &source_xattrs);
+    }
+    // After the timestamps and extended attributes, as those can not be written to a file that became readonly
+    if let Some(source_metadata) = source_metadata {
+        apply_permissions(&written_path, &source_metadata);
+    }
+    // Hardlinks and symlinks share their permissions with the source, so only real copies get the mode
+    if let Some(mode) = args.chmod.filter(|_| matches!(args.mode, Mode::Copy | Mode::Move)) {
+        set_mode(&written_path, mode)?;
+    }
+    if let Some(part_file) = part_file {
+        part_file.commit(target_path)?;
+    }
+    if let (Some(hashes), Some(content_hash)) = (hashes, content_hash) {
+        hashes.register(content_hash, target_path);
+    }
+    Ok(CopyOutcome::Copied(bytes))
+}
+
+/// Compares the copy in `part_file` with the checksum of `source_path` for `--verify`. A mismatching copy is
+/// still moved to `target_path` for inspection, unless `delete_on_mismatch` deletes it
+fn verified(source_path: &Path, part_file: PartFile, target_path: &Path, delete_on_mismatch: bool) -> std::io::Result<PartFile> {
+    if hash::verify_copy(source_path, &part_file.path)? {
+        return Ok(part_file);
+    }
+    if !delete_on_mismatch {
+        part_file.commit(target_path)?;
+    }
+    Err(std::io::Error::new(ErrorKind::InvalidData, "the copy does not match the source's checksum"))
+}
+
+/// The sibling `<name>.<process>-<number>.part` a copy is written to first, so the target only ever appears
+/// completely. The process id and a number unique within the process keep it from ever replacing a real file
+/// named `<name>.part` or the part file of a concurrent copy. It is deleted again unless it was renamed into place
+struct PartFile {
+    path: PathBuf,
+    committed: bool,
+}
+
+static PART_FILE_NUMBER: AtomicU64 = AtomicU64::new(0);
+
+impl PartFile {
+    fn for_target(target_path: &Path) -> PartFile {
+        let number = PART_FILE_NUMBER.fetch_add(1, Ordering::Relaxed);
+        let mut file_name = target_path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(format!(".{}-{}.part", std::process::id(), number));
+        PartFile { path: target_path.with_file_name(file_name), committed: false }
+    }
+
+    fn commit(mut self, target_path: &Path) -> std::io::Result<()> {
+        std::fs::rename(&self.path, target_path)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for PartFile {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        match std::fs::remove_file(&self.path) {
+            Err(err) if err.kind() != ErrorKind::NotFound => {
+                warn!("Could not delete the partial copy {} due to {}", self.path.to_string_lossy(), err)
+            }
+            _ => {}
+        }
+    }
+}
+
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(100);
+
+/// Runs `operation` and retries it up to `retries` times with an exponential backoff as long as it fails
+/// with a transient error
+fn with_retries<T>(retries: u32, path: &Path, mut operation: impl FnMut() -> std::io::Result<T>) -> std::io::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match operation() {
+            Err(err) if attempt < retries && is_transient(&err) => {
+                let delay = RETRY_BASE_DELAY * 2u32.saturating_pow(attempt);
+                attempt += 1;
+                debug!("Retrying {} in {:?} ({}/{}) after {}", path.to_string_lossy(), delay, attempt, retries, err);
+                std::thread::sleep(delay);
+            }
+            result => { return result; }
+        }
+    }
+}
+
+fn is_transient(err: &std::io::Error) -> bool {
+    matches!(err.kind(), ErrorKind::Interrupted | ErrorKind::TimedOut)
+}
+
+fn create_parent_dirs(path: &Path, dir_mode: Option<u32>) -> std::io::Result<()> {
+    let parent = path.parent().ok_or_else(|| {
+        std::io::Error::new(ErrorKind::InvalidInput, format!("{} has no parent directory", path.to_string_lossy()))
+    })?;
+    create_dirs(parent, dir_mode)
+}
+
+/// Creates `dir` with all its missing ancestors and sets the permission bits of the newly created ones to
+/// `dir_mode`, directories that already existed keep theirs
+fn create_dirs(dir: &Path, dir_mode: Option<u32>) -> std::io::Result<()> {
+    let Some(dir_mode) = dir_mode else {
+        return std::fs::create_dir_all(dir);
+    };
+    let missing: Vec<&Path> = dir.ancestors()
+        .take_while(|ancestor| !ancestor.as_os_str().is_empty() && !ancestor.exists())
+        .collect();
+    std::fs::create_dir_all(dir)?;
+    missing.iter().rev().try_for_each(|created| set_mode(created, dir_mode))
+}
+
+fn read_file_times(path: &Path) -> Option<FileTimes> {
+    let times = std::fs::metadata(path)
+        .and_then(|metadata| Ok(FileTimes::new().set_accessed(metadata.accessed()?).set_modified(metadata.modified()?)));
+    match times {
+        Ok(times) => { Some(times) }
+        Err(err) => {
+            warn!("Could not read timestamps of {} due to {}", path.to_string_lossy(), err);
+            None
+        }
+    }
+}
+
+fn apply_file_times(path: &Path, times: FileTimes) {
+    let result = File::options().write(true).open(path)
+        .and_then(|file| file.set_times(times));
+    if let Err(err) = result {
+        warn!("Could not preserve timestamps of {} due to {}", path.to_string_lossy(), err);
+    }
+}
+
+fn read_metadata(path: &Path) -> Option<Metadata> {
+    match std::fs::metadata(path) {
+        Ok(metadata) => { Some(metadata) }
+        Err(err) => {
+            warn!("Could not read permissions of {} due to {}", path.to_string_lossy(), err);
+            None
+        }
+    }
+}
+
+fn apply_permissions(path: &Path, source_metadata: &Metadata) {
+    if let Err(err) = set_permissions(path, source_metadata) {
+        warn!("Could not preserve permissions of {} due to {}", path.to_string_lossy(), err);
+    }
+}
+
+/// Copies owner and group first, as changing them clears the setuid and setgid bits. Only root may hand files
+/// to other users, for everyone else the owner stays unchanged
+#[cfg(unix)]
+fn set_permissions(path: &Path, source_metadata: &Metadata) -> std::io::Result<()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    match std::os::unix::fs::chown(path, Some(source_metadata.uid()), Some(source_metadata.gid())) {
+        Err(err) if err.kind() == ErrorKind::PermissionDenied => {
+            debug!("Could not preserve the owner of {} due to {}", path.to_string_lossy(), err)
+        }
+        result => { result? }
+    }
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(source_metadata.mode() & 0o7777))
+}
+
+#[cfg(not(unix))]
+fn set_permissions(path: &Path, source_metadata: &Metadata) -> std::io::Result<()> {
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    permissions.set_readonly(source_metadata.permissions().readonly());
+    std::fs::set_permissions(path, permissions)
+}
+
+/// Sets the permission bits of `path` to `mode` for `--chmod` and `--chmod-dirs`
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+}
+
+/// [args::CliArgs::convert] rejects `--chmod` and `--chmod-dirs` outside of Unix
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Whether both paths resolve to the same file, copying it onto itself would truncate it
+fn is_same_file(source_path: &Path, target_path: &Path) -> bool {
+    match (std::fs::canonicalize(source_path), std::fs::canonicalize(target_path)) {
+        (Ok(source), Ok(target)) => { source == target }
+        _ => { false }
+    }
+}
+
+/// Whether the target already exists with the same length and modification time as the source
+pub(crate) fn is_same_file_at_target(source_path: &Path, target_path: &Path) -> bool {
+    let (Ok(source), Ok(target)) = (std::fs::metadata(source_path), std::fs::metadata(target_path)) else {
+        return false;
+    };
+    match (source.modified(), target.modified()) {
+        (Ok(source_modified), Ok(target_modified)) => {
+            source.len() == target.len() && source_modified == target_modified
+        }
+        _ => false,
+    }
+}
+
+/// Moves via a cheap rename where possible and falls back to copying and deleting the source,
+/// e.g. when source and target live on different filesystems
+fn move_file(source_path: &Path, target_path: &Path, args: &Arguments) -> std::io::Result<u64> {
+    let bytes = std::fs::metadata(source_path)?.len();
+    match std::fs::rename(long_path(source_path), target_path) {
+        Ok(_) => { return Ok(bytes); }
+        Err(err) => {
+            debug!("Could not rename {} ({}), falling back to copy and delete", source_path.to_string_lossy(), err)
+        }
+    }
+    let bytes = copy_through_part_file(source_path, target_path, args, false)?;
+    if let Err(err) = std::fs::remove_file(source_path) {
+        warn!("Copied {} but failed to delete the source due to {}", source_path.to_string_lossy(), err);
+        return Err(err);
+    }
+    Ok(bytes)
+}
+
+/// Links `target_path` to the content of `source_path`, replacing an existing target as links can not
+/// overwrite. Copies instead if both are on different devices, that copy is checked by `--verify` like any other
+fn hard_link_file(source_path: &Path, target_path: &Path, args: &Arguments) -> std::io::Result<u64> {
+    let bytes = std::fs::metadata(source_path)?.len();
+    if std::fs::symlink_metadata(target_path).is_ok() {
+        std::fs::remove_file(target_path)?;
+    }
+    match std::fs::hard_link(long_path(source_path), target_path) {
+        Ok(_) => { Ok(bytes) }
+        Err(err) if err.kind() == ErrorKind::CrossesDevices => {
+            warn!("Could not hardlink {} across devices, copying it instead", source_path.to_string_lossy());
+            copy_through_part_file(source_path, target_path, args, args.verify)
+        }
+        Err(err) => { Err(err) }
+    }
+}
+
+/// Copies the content like [copy_contents] into a [PartFile] that is renamed to `target_path` once complete,
+/// for the modes that only fall back to copying. With `verify` the copy is [verified] before the rename
+fn copy_through_part_file(source_path: &Path, target_path: &Path, args: &Arguments, verify: bool) -> std::io::Result<u64> {
+    let part_file = PartFile::for_target(target_path);
+    let bytes = {
+        let _in_flight = args.stop_signal.track(&part_file.path);
+        copy_contents(source_path, &part_file.path, args)?
+    };
+    let part_file = if verify { verified(source_path, part_file, target_path, args.verify_delete_on_mismatch)? } else { part_file };
+    part_file.commit(target_path)?;
+    Ok(bytes)
+}
+
+/// Files of at least 16 MiB are copied through the `--copy-buffer-size` buffer, smaller ones are left to
+/// [std::fs::copy] so the buffer is not allocated again for every small file. The buffer does not win
+/// everywhere: on ext4, where [std::fs::copy] uses `copy_file_range`, a 16 MiB file took 11.7ms with
+/// [std::fs::copy] and 15.2ms through an 8 MiB buffer, a 256 MiB file 165ms and 265ms
+const BUFFERED_COPY_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// Copies the file content, throttled in chunks if a maximum rate is set and through an explicit buffer for
+/// large files if `--copy-buffer-size` is set
+fn copy_contents(source_path: &Path, target_path: &Path, args: &Arguments) -> std::io::Result<u64> {
+    let source_path = long_path(source_path);
+    let source_path = source_path.as_ref();
+    if args.compress != Compression::None {
+        return copy_compressed(source_path, target_path, args.compress, args.compression_level, args.rate_limiter.as_ref());
+    }
+    match (args.rate_limiter.as_ref(), args.copy_buffer_size) {
+        (Some(rate_limiter), None) => { copy_throttled(source_path, target_path, rate_limiter) }
+        (rate_limiter, Some(buffer_size)) if rate_limiter.is_some()
+            || std::fs::metadata(source_path)?.len() >= BUFFERED_COPY_THRESHOLD => {
+            copy_buffered(source_path, target_path, buffer_size, rate_limiter)
+        }
+        _ => { std::fs::copy(source_path, target_path) }
+    }
+}
+
+/// Copies like [copy_contents], but also computes the [hash::content_hash] of the source from the same reads,
+/// so `--dedup` reads every copied file only once. The content always goes through a buffer of
+/// `--copy-buffer-size`, as [std::fs::copy] may never hand it to the process
+fn copy_contents_hashed(source_path: &Path, target_path: &Path, args: &Arguments) -> std::io::Result<(u64, u128)> {
+    let mut source = HashingReader::new(File::open(long_path(source_path))?);
+    let permissions = source.get_ref().metadata()?.permissions();
+    let target = File::create(target_path)?;
+    let (copied, target) = if args.compress != Compression::None {
+        compress_chunks(&mut source, target, args.compress, args.compression_level, args.rate_limiter.as_ref())?
+    } else {
+        let mut target = target;
+        let buffer_size = args.copy_buffer_size.unwrap_or(CHUNK_SIZE);
+        (copy_chunks(&mut source, &mut target, buffer_size, args.rate_limiter.as_ref())?, target)
+    };
+    target.set_permissions(permissions)?;
+    Ok((copied, source.digest()))
+}
+
+/// Recreates the symbolic link at `source_path` at `target_path` for `--copy-links-as-links`, keeping its
+/// link target unchanged. Like [symlink_file] it counts as 0 bytes
+fn copy_link(args: &Arguments, source_path: &Path, target_path: &Path) -> std::io::Result<CopyOutcome> {
+    if !args.overwrite.allows_writing(source_path, target_path) {
+        return Ok(CopyOutcome::Skipped("the overwrite policy forbids replacing the target"));
+    }
+    create_parent_dirs(target_path, args.chmod_dirs)?;
+    let link_target = std::fs::read_link(source_path)?;
+    if std::fs::symlink_metadata(target_path).is_ok() {
+        std::fs::remove_file(target_path)?;
+    }
+    create_symlink(&link_target, target_path)?;
+    if args.mode == Mode::Move {
+        std::fs::remove_file(source_path)?;
+    }
+    Ok(CopyOutcome::Copied(0))
+}
+
+/// Creates a symbolic link at `target_path` pointing at the absolute `source_path`, replacing an existing target.
+/// No bytes are transferred, so the link counts as 0 bytes
+fn symlink_file(source_path: &Path, target_path: &Path) -> std::io::Result<u64> {
+    let absolute_source = std::path::absolute(source_path)?;
+    if std::fs::symlink_metadata(target_path).is_ok() {
+        std::fs::remove_file(target_path)?;
+    }
+    create_symlink(&absolute_source, target_path)?;
+    Ok(0)
+}
+
+#[cfg(unix)]
+fn create_symlink(original: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+fn create_symlink(original: &Path, link: &Path) -> std::io::Result<()> {
+    /// Windows' ERROR_PRIVILEGE_NOT_HELD
+    const PRIVILEGE_NOT_HELD: i32 = 1314;
+    std::os::windows::fs::symlink_file(original, link).map_err(|err| {
+        if err.raw_os_error() == Some(PRIVILEGE_NOT_HELD) || err.kind() == ErrorKind::PermissionDenied {
+            std::io::Error::new(ErrorKind::PermissionDenied,
+                                "creating symlinks requires the symlink privilege, run as administrator or enable the developer mode")
+        } else {
+            err
+        }
+    })
+}
+
+#[cfg(not(any(unix, windows)))]
+fn create_symlink(_original: &Path, _link: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(ErrorKind::Unsupported, "symlinks are not supported on this platform"))
+}
+
+static COLOR_CHOICE: OnceLock<ColorChoice> = OnceLock::new();
+
+/// Overrides the terminal capability detection of [is_colorful_supported], can only be set once
+pub fn set_color_choice(choice: ColorChoice) {
+    if COLOR_CHOICE.set(choice).is_err() {
+        debug!("The color choice was already set");
+    }
+}
+
+/// Whether stdout, which [print_colorful_when_supported] prints to, is colored. An explicit `--color always`
+/// wins over `NO_COLOR`, as per the no-color.org convention
+pub fn is_colorful_supported() -> bool {
+    is_colorful_supported_on(Stream::Stdout)
+}
+
+/// Like [is_colorful_supported], but for the terminal behind `stream`
+fn is_colorful_supported_on(stream: Stream) -> bool {
+    let choice = COLOR_CHOICE.get().copied().unwrap_or_default();
+    uses_colors(choice, std::env::var_os("NO_COLOR").as_deref(), || supports_color::on(stream).is_some())
+}
+
+/// Whether `choice` colors the output with `no_color` as the value of `NO_COLOR`. The terminal is only asked
+/// via `terminal_supports_color` for [ColorChoice::Auto]
+fn uses_colors(choice: ColorChoice, no_color: Option<&OsStr>, terminal_supports_color: impl FnOnce() -> bool) -> bool {
+    match choice {
+        ColorChoice::Always => { true }
+        ColorChoice::Never => { false }
+        ColorChoice::Auto => { no_color.is_none_or(|value| value.is_empty()) && terminal_supports_color() }
+    }
+}
+
+pub fn print_colorful_when_supported(message: &str, function: fn(&str) -> CString) {
+    if is_colorful_supported() {
+        let message = function(message);
+        println!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
+
+/// Creates a bar of the given length, which counts either files or bytes if `by_bytes` is set
+pub fn create_progress_bar(length: u64, by_bytes: bool, draw_target: ProgressDrawTarget) -> ProgressBar {
+    let bar = ProgressBar::with_draw_target(Some(length), draw_target);
+    bar.enable_steady_tick(Duration::from_secs(1));
+    if let Some(style) = progress_style(by_bytes) {
+        bar.set_style(style);
+    }
+    bar
+}
+
+/// Creates a spinner for an unknown amount of files or bytes, its length can be set later on
+pub fn create_spinner(by_bytes: bool, draw_target: ProgressDrawTarget) -> ProgressBar {
+    let bar = ProgressBar::with_draw_target(None, draw_target);
+    bar.enable_steady_tick(Duration::from_millis(250));
+    let template = if by_bytes {
+        "{spinner} [{elapsed_precise}] {bytes:>7} {msg}"
+    } else {
+        "{spinner} [{elapsed_precise}] {pos:>7} {msg}"
+    };
+    match ProgressStyle::with_template(template) {
+        Ok(style) => { bar.set_style(style) }
+        Err(_) => { debug!("Could not retrieve spinner style!") }
+    }
+    bar
+}
+
+/// Longest file name shown next to the progress bar, so it does not wrap on regular terminals
+const MAX_FILE_NAME_LENGTH: usize = 30;
+
+/// Shortens `file_name` to at most `max_length` characters, marking the cut with an ellipsis
+fn truncate_file_name(file_name: &str, max_length: usize) -> String {
+    if file_name.chars().count() <= max_length {
+        return file_name.to_string();
+    }
+    let mut truncated: String = file_name.chars().take(max_length.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+fn progress_style(by_bytes: bool) -> Option<ProgressStyle> {
+    let template = if by_bytes {
+        "[{elapsed_precise}] {bar:40.cyan/blue} {bytes:>7}/{total_bytes:7} ({eta}) {msg}"
+    } else {
+        "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} ({eta}) {msg}"
+    };
+    match ProgressStyle::with_template(template) {
+        Ok(style) => { Some(style.progress_chars("##-")) }
+        Err(_) => {
+            debug!("Could not retrieve progress bar style!");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::ffi::OsStr;
+    use std::io::{ErrorKind, Read};
+    use std::fs::File;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, SystemTime};
+
+    use clap::Parser;
+    use indicatif::ProgressDrawTarget;
+    use rayon::iter::{IntoParallelIterator, ParallelIterator};
+    use walkdir::DirEntry;
+
+    use crate::{archive_path, BudgetUsage, build_thread_pool, skip_inaccessible, collect_listed_files, copy, copy_streaming, copy_through_part_file, copy_with_progress, count_files, CopyError, CopyEvent, CopyReport, StopSignal, create_spinner, discover_files, DiscoveryProgress, FlattenedTargets, numbered_path, PartFile, reserve_numbered_path, SizeBudget, SkipCounts, sort_files, suffixed_path, create_parent_dirs, create_progress_bar, gather_files_for_copying, gather_files_from_list, gather_files_with, total_size, truncate_file_name, uses_colors, verified, walk_source_root, with_retries};
+    use crate::args::{Arguments, CliArgs, ColorChoice, FlattenCollision, Order};
+    use crate::long_paths::long_path;
+    use crate::resume::ResumeState;
+
+    fn arguments(flags: &[&str], source: &Path, target: &Path) -> Arguments {
+        let mut cli = vec!["filescraper"];
+        cli.extend_from_slice(flags);
+        cli.push(source.to_str().unwrap());
+        cli.push(target.to_str().unwrap());
+        CliArgs::parse_from(cli).convert().unwrap()
+    }
+
+    #[test]
+    fn it_works() {
+        let result = 2 + 2;
+        assert_eq!(result, 4);
+    }
+
+    #[test]
+    fn test_max_depth_limits_recursion() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(source.path().join("a").join("b").join("c")).unwrap();
+        std::fs::write(source.path().join("top.txt"), "top").unwrap();
+        std::fs::write(source.path().join("a").join("b").join("deep.txt"), "deep").unwrap();
+        let args = arguments(&["--max-depth", "1", "--preserve-empty-dirs"], source.path(), target.path());
+        let mut children: Vec<String> = gather_files_for_copying(&args).iter()
+            .filter(|entry| entry.depth() > 0)
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect();
+        children.sort();
+        assert_eq!(children, vec!["a", "top.txt"]);
+    }
+
+    #[test]
+    fn test_min_and_max_depth_combined() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(source.path().join("a").join("b").join("c")).unwrap();
+        std::fs::write(source.path().join("top.txt"), "top").unwrap();
+        std::fs::write(source.path().join("a").join("middle.txt"), "middle").unwrap();
+        std::fs::write(source.path().join("a").join("b").join("deep.txt"), "deep").unwrap();
+        let args = arguments(&["--min-depth", "2", "--max-depth", "2", "--preserve-empty-dirs"], source.path(), target.path());
+        let mut names: Vec<String> = gather_files_for_copying(&args).iter()
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["b", "middle.txt"]);
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir(source.path().join("dir")).unwrap();
+        std::fs::write(source.path().join("tiny.txt"), vec![0u8; 10]).unwrap();
+        std::fs::write(source.path().join("dir").join("medium.txt"), vec![0u8; 2_000]).unwrap();
+        std::fs::write(source.path().join("large.txt"), vec![0u8; 5_000]).unwrap();
+        let args = arguments(&["--min-size", "1K", "--max-size", "4K", "--preserve-empty-dirs"], source.path(), target.path());
+        let mut names: Vec<String> = gather_files_for_copying(&args).iter()
+            .filter(|entry| entry.depth() > 0)
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["dir", "medium.txt"]);
+    }
+
+    #[test]
+    fn test_newer_than() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir(source.path().join("dir")).unwrap();
+        let now = SystemTime::now();
+        for (name, age) in [("fresh.txt", 0), ("dir/recent.txt", 60 * 60), ("stale.txt", 10 * 24 * 60 * 60)] {
+            let path = source.path().join(name);
+            std::fs::write(&path, name).unwrap();
+            File::options().write(true).open(&path).unwrap().set_modified(now - Duration::from_secs(age)).unwrap();
+        }
+        let args = arguments(&["--newer-than", "7d"], source.path(), target.path());
+        let mut names: Vec<String> = gather_files_for_copying(&args).iter()
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["fresh.txt", "recent.txt"]);
+    }
+
+    #[test]
+    fn test_build_thread_pool() {
+        assert!(build_thread_pool(None).unwrap().is_none());
+        assert!(build_thread_pool(Some(0)).unwrap().is_none());
+        let pool = build_thread_pool(Some(3)).unwrap().unwrap();
+        assert_eq!(pool.current_num_threads(), 3);
+        assert_eq!(pool.install(rayon::current_num_threads), 3);
+    }
+
+    #[test]
+    fn test_total_size_ignores_directories() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir(source.path().join("dir")).unwrap();
+        std::fs::write(source.path().join("a.txt"), vec![0u8; 100]).unwrap();
+        std::fs::write(source.path().join("dir").join("b.txt"), vec![0u8; 23]).unwrap();
+        let args = arguments(&["--progress-by-bytes"], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        assert_eq!(total_size(&files), 123);
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 2);
+        assert_eq!(report.bytes_copied, 123);
+    }
+
+    #[test]
+    fn test_count_files() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir(source.path().join("dir")).unwrap();
+        std::fs::write(source.path().join("a.txt"), vec![0u8; 100]).unwrap();
+        std::fs::write(source.path().join("dir").join("b.txt"), vec![0u8; 23]).unwrap();
+        std::fs::write(source.path().join("c.jpg"), vec![0u8; 1000]).unwrap();
+        let args = arguments(&["--preserve-empty-dirs"], source.path(), target.path());
+        assert_eq!(count_files(discover_files(&args)), (3, 1123));
+        let args = arguments(&["--file-extensions", "txt"], source.path(), target.path());
+        assert_eq!(count_files(discover_files(&args)), (2, 123));
+    }
+
+    #[test]
+    fn test_bytes_per_second() {
+        let report = CopyReport {
+            duration: Duration::from_secs(4),
+            copied: 2,
+            bytes_copied: 1_000,
+            skipped: 0,
+            duplicates: 0,
+            failures: vec![],
+            permission_denied: 0,
+            extension_stats: Vec::new(),
+            interrupted: false,
+            budget: None,
+        };
+        assert_eq!(report.bytes_per_second(), 250);
+        let report = CopyReport { duration: Duration::ZERO, ..report };
+        assert_eq!(report.bytes_per_second(), 0);
+    }
+
+    #[test]
+    fn test_create_parent_dirs_without_parent() {
+        let result = create_parent_dirs(Path::new("/"), None);
+        assert!(result.is_err());
+        let temp_dir = tempfile::tempdir().unwrap();
+        create_parent_dirs(&temp_dir.path().join("a").join("b").join("file.txt"), None).unwrap();
+        assert!(temp_dir.path().join("a").join("b").is_dir());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_links_terminates_on_symlink_cycle() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir(source.path().join("dir")).unwrap();
+        std::fs::write(source.path().join("dir").join("file.txt"), "content").unwrap();
+        std::os::unix::fs::symlink(source.path(), source.path().join("dir").join("loop")).unwrap();
+        std::os::unix::fs::symlink(source.path().join("dir"), source.path().join("alias")).unwrap();
+        for flags in [&["--follow-links"][..], &["--follow-links", "--parallel-walk"][..]] {
+            let args = arguments(flags, source.path(), target.path());
+            let skipped = Arc::new(SkipCounts::default());
+            let files: Vec<String> = walk_source_root(&args, |path, is_dir| args.skip_reason_of_type(path, is_dir), skipped)
+                .filter(|entry| entry.file_name() == "file.txt")
+                .map(|entry| entry.path().to_string_lossy().to_string())
+                .collect();
+            assert_eq!(files.len(), 1, "{:?}", flags);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_links_depth_limit() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(outside.path().join("first")).unwrap();
+        std::fs::create_dir_all(outside.path().join("second")).unwrap();
+        std::fs::write(outside.path().join("first").join("one_hop.txt"), "1").unwrap();
+        std::fs::write(outside.path().join("second").join("two_hops.txt"), "2").unwrap();
+        std::os::unix::fs::symlink(outside.path().join("first"), source.path().join("first")).unwrap();
+        std::os::unix::fs::symlink(outside.path().join("second"), outside.path().join("first").join("second")).unwrap();
+        for parallel_walk in [false, true] {
+            let mut flags = vec!["--follow-links", "--follow-links-depth-limit", "1"];
+            if parallel_walk {
+                flags.push("--parallel-walk");
+            }
+            let args = arguments(&flags, source.path(), target.path());
+            let mut names: Vec<String> = gather_files_for_copying(&args).iter()
+                .filter(|entry| entry.file_type().is_file())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect();
+            names.sort();
+            assert_eq!(names, vec!["one_hop.txt"], "{:?}", flags);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_links_gathers_aliased_files_once() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("real.txt"), "content").unwrap();
+        std::os::unix::fs::symlink(source.path().join("real.txt"), source.path().join("alias.txt")).unwrap();
+        for flags in [&["--follow-links"][..], &["--follow-links", "--parallel-walk"][..]] {
+            let args = arguments(flags, source.path(), target.path());
+            let files: Vec<DirEntry> = gather_files_for_copying(&args).into_iter()
+                .filter(|entry| entry.file_type().is_file())
+                .collect();
+            assert_eq!(files.len(), 1, "{:?}", flags);
+        }
+        let list = format!("{0}\n{0}\n", source.path().join("real.txt").to_string_lossy());
+        let args = arguments(&[], source.path(), target.path());
+        assert_eq!(collect_listed_files(&args, list.as_bytes()).len(), 1);
+    }
+
+    #[test]
+    fn test_respect_gitignore() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(source.path().join("target").join("debug")).unwrap();
+        std::fs::create_dir_all(source.path().join("src")).unwrap();
+        std::fs::write(source.path().join(".gitignore"), "target/\n*.log\n").unwrap();
+        std::fs::write(source.path().join("src").join(".ignore"), "generated.rs\n").unwrap();
+        std::fs::write(source.path().join("target").join("debug").join("app"), "binary").unwrap();
+        std::fs::write(source.path().join("src").join("main.rs"), "fn main() {}").unwrap();
+        std::fs::write(source.path().join("src").join("generated.rs"), "// generated").unwrap();
+        std::fs::write(source.path().join("build.log"), "log").unwrap();
+        let names = |flags: &[&str]| {
+            let args = arguments(flags, source.path(), target.path());
+            let mut names: Vec<String> = gather_files_for_copying(&args).iter()
+                .filter(|entry| entry.file_type().is_file())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(&["--respect-gitignore"]), vec![".gitignore", ".ignore", "main.rs"]);
+        assert_eq!(names(&["--respect-gitignore", "--exclude-hidden"]), vec!["main.rs"]);
+        assert_eq!(names(&["--respect-gitignore", "--file-extensions", "Ignore rs"]), vec![".gitignore", ".ignore"]);
+        assert_eq!(names(&[]).len(), 6);
+    }
+
+    #[test]
+    fn test_quiet_hides_progress_bar() {
+        assert!(create_progress_bar(10, false, ProgressDrawTarget::hidden()).is_hidden());
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        assert!(arguments(&["--quiet"], source.path(), target.path()).quiet);
+        assert!(!arguments(&[], source.path(), target.path()).quiet);
+    }
+
+    #[test]
+    fn test_no_color() {
+        let no_color = Some(OsStr::new("1"));
+        assert!(!uses_colors(ColorChoice::Auto, no_color, || true));
+        assert!(!uses_colors(ColorChoice::Never, no_color, || true));
+        assert!(uses_colors(ColorChoice::Always, no_color, || false));
+        assert!(uses_colors(ColorChoice::Auto, Some(OsStr::new("")), || true));
+        assert!(uses_colors(ColorChoice::Auto, None, || true));
+        assert!(!uses_colors(ColorChoice::Auto, None, || false));
+        assert!(!uses_colors(ColorChoice::Never, None, || true));
+    }
+
+    #[test]
+    fn test_collect_listed_files() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("listed.txt"), "listed").unwrap();
+        std::fs::write(source.path().join("unlisted.txt"), "unlisted").unwrap();
+        std::fs::write(source.path().join("ignored.jpg"), "ignored").unwrap();
+        let list = format!("{}\n{}\n\n{}\n",
+                           source.path().join("listed.txt").to_string_lossy(),
+                           source.path().join("missing.txt").to_string_lossy(),
+                           source.path().join("ignored.jpg").to_string_lossy());
+        let args = arguments(&["--file-extensions", "Ignore jpg"], source.path(), target.path());
+        let files = collect_listed_files(&args, list.as_bytes());
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].file_name(), "listed.txt");
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 1);
+        assert!(target.path().join("listed.txt").exists());
+        assert!(!target.path().join("unlisted.txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_listed_paths_keep_their_tabs_unless_read_from_an_error_log() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("tab\tseparated.txt"), "tab").unwrap();
+        std::fs::write(source.path().join("tab"), "prefix").unwrap();
+        let list = format!("{}\n", source.path().join("tab\tseparated.txt").to_string_lossy());
+        let args = arguments(&[], source.path(), target.path());
+        let files = collect_listed_files(&args, list.as_bytes());
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].file_name(), "tab\tseparated.txt");
+        let args = arguments(&["--from-error-log", "-"], source.path(), target.path());
+        let files = collect_listed_files(&args, list.as_bytes());
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].file_name(), "tab");
+    }
+
+    #[test]
+    fn test_parallel_walk_matches_sequential_walk() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(source.path().join("a").join("b")).unwrap();
+        std::fs::create_dir_all(source.path().join(".hidden")).unwrap();
+        std::fs::write(source.path().join("top.txt"), "top").unwrap();
+        std::fs::write(source.path().join("skipped.jpg"), "skipped").unwrap();
+        std::fs::write(source.path().join("a").join("b").join("deep.txt"), "deep").unwrap();
+        std::fs::write(source.path().join(".hidden").join("secret.txt"), "secret").unwrap();
+        let sorted_paths = |files: Vec<DirEntry>| {
+            let mut paths: Vec<PathBuf> = files.into_iter().map(|entry| entry.into_path()).collect();
+            paths.sort();
+            paths
+        };
+        let sequential = sorted_paths(gather_files_for_copying(&arguments(&["--file-extensions", "Ignore jpg", "--preserve-empty-dirs"], source.path(), target.path())));
+        let parallel = sorted_paths(gather_files_for_copying(&arguments(&["--file-extensions", "Ignore jpg", "--parallel-walk", "--preserve-empty-dirs"], source.path(), target.path())));
+        assert_eq!(sequential.len(), 7);
+        assert_eq!(sequential, parallel);
+    }
+
+    #[test]
+    fn test_exclude_hidden() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(source.path().join(".git").join("objects")).unwrap();
+        std::fs::create_dir_all(source.path().join("visible").join(".cache")).unwrap();
+        std::fs::write(source.path().join(".git").join("objects").join("blob.txt"), "blob").unwrap();
+        std::fs::write(source.path().join("visible").join(".cache").join("cached.txt"), "cached").unwrap();
+        std::fs::write(source.path().join("visible").join(".hidden.txt"), "hidden").unwrap();
+        std::fs::write(source.path().join("visible").join("kept.txt"), "kept").unwrap();
+        for flags in [vec!["--exclude-hidden"], vec!["--exclude-hidden", "--parallel-walk"]] {
+            let names: Vec<String> = gather_files_for_copying(&arguments(&flags, source.path(), target.path())).iter()
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect();
+            assert_eq!(names, vec!["kept.txt"]);
+        }
+        assert_eq!(gather_files_for_copying(&arguments(&[], source.path(), target.path())).len(), 4);
+    }
+
+    #[test]
+    fn test_gather_files_with_predicate() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir(source.path().join("nested")).unwrap();
+        std::fs::write(source.path().join("nested").join("keep.txt"), "keep").unwrap();
+        std::fs::write(source.path().join("drop.txt"), "drop").unwrap();
+        std::fs::write(source.path().join("huge.txt"), vec![0u8; 5_000]).unwrap();
+        for flags in [vec!["--max-size", "1K"], vec!["--max-size", "1K", "--parallel-walk"]] {
+            let args = arguments(&flags, source.path(), target.path());
+            let files = gather_files_with(&args, |path| !path.ends_with("drop.txt"));
+            let names: Vec<String> = files.iter().map(|entry| entry.file_name().to_string_lossy().to_string()).collect();
+            assert_eq!(names, vec!["keep.txt"]);
+        }
+    }
+
+    #[test]
+    fn test_with_retries_retries_transient_errors() {
+        let mut attempts = 0;
+        let result = with_retries(2, Path::new("file"), || {
+            attempts += 1;
+            if attempts < 3 { Err(std::io::Error::from(ErrorKind::TimedOut)) } else { Ok(attempts) }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        let mut attempts = 0;
+        let result: std::io::Result<()> = with_retries(2, Path::new("file"), || {
+            attempts += 1;
+            Err(std::io::Error::from(ErrorKind::NotFound))
+        });
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(attempts, 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hardlink_mode() {
+        use std::os::unix::fs::MetadataExt;
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("file.txt"), "content").unwrap();
+        std::fs::write(target.path().join("file.txt"), "outdated").unwrap();
+        let args = arguments(&["--quiet", "--mode", "hardlink"], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 1);
+        let source_metadata = std::fs::metadata(source.path().join("file.txt")).unwrap();
+        let target_metadata = std::fs::metadata(target.path().join("file.txt")).unwrap();
+        assert_eq!(source_metadata.ino(), target_metadata.ino());
+        assert_eq!(std::fs::read_to_string(target.path().join("file.txt")).unwrap(), "content");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_mode() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir(source.path().join("nested")).unwrap();
+        std::fs::write(source.path().join("nested").join("file.txt"), "content").unwrap();
+        let args = arguments(&["--quiet", "--mode", "symlink"], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 1);
+        let link = target.path().join("nested").join("file.txt");
+        assert!(std::fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(std::fs::read_link(&link).unwrap(), source.path().join("nested").join("file.txt"));
+        assert_eq!(std::fs::read_to_string(&link).unwrap(), "content");
+    }
+
+    #[test]
+    fn test_truncate_file_name() {
+        assert_eq!(truncate_file_name("short.txt", 10), "short.txt");
+        assert_eq!(truncate_file_name("a_rather_long_name.txt", 10), "a_rather_…");
+        assert_eq!(truncate_file_name("äöüäöüäöüäöü", 4), "äöü…");
+    }
+
+    #[test]
+    fn test_suffixed_path() {
+        assert_eq!(suffixed_path(Path::new("target/photo.jpg"), 1), Path::new("target/photo_1.jpg"));
+        assert_eq!(suffixed_path(Path::new("target/photo.jpg"), 12), Path::new("target/photo_12.jpg"));
+        assert_eq!(suffixed_path(Path::new("target/README"), 2), Path::new("target/README_2"));
+        assert_eq!(suffixed_path(Path::new("target/.bashrc"), 1), Path::new("target/.bashrc_1"));
+        assert_eq!(suffixed_path(Path::new("target/archive.tar.gz"), 1), Path::new("target/archive.tar_1.gz"));
+    }
+
+    #[test]
+    fn test_numbered_path() {
+        assert_eq!(numbered_path(Path::new("target/file.txt"), 1), Path::new("target/file (1).txt"));
+        assert_eq!(numbered_path(Path::new("target/file.txt"), 2), Path::new("target/file (2).txt"));
+        assert_eq!(numbered_path(Path::new("target/README"), 3), Path::new("target/README (3)"));
+        assert_eq!(numbered_path(Path::new("target/.bashrc"), 1), Path::new("target/.bashrc (1)"));
+    }
+
+    #[test]
+    fn test_reserve_numbered_path_sequence() {
+        let target = tempfile::tempdir().unwrap();
+        let path = target.path().join("file.txt");
+        std::fs::write(&path, "existing").unwrap();
+        std::fs::write(target.path().join("file (2).txt"), "existing").unwrap();
+        assert_eq!(reserve_numbered_path(&path).unwrap(), target.path().join("file (1).txt"));
+        assert_eq!(reserve_numbered_path(&path).unwrap(), target.path().join("file (3).txt"));
+        assert_eq!(reserve_numbered_path(&path).unwrap(), target.path().join("file (4).txt"));
+        assert!(target.path().join("file (4).txt").exists());
+    }
+
+    #[test]
+    fn test_reserve_numbered_path_concurrently() {
+        let target = tempfile::tempdir().unwrap();
+        let path = target.path().join("file.txt");
+        std::fs::write(&path, "existing").unwrap();
+        let reserved: HashSet<PathBuf> = (0..16).into_par_iter()
+            .map(|_| reserve_numbered_path(&path).unwrap())
+            .collect();
+        assert_eq!(reserved.len(), 16);
+    }
+
+    #[test]
+    fn test_overwrite_rename_keeps_both_files() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("file.txt"), "first").unwrap();
+        let args = arguments(&["--overwrite", "rename"], source.path(), target.path());
+        copy(args, gather_files_for_copying(&arguments(&[], source.path(), target.path()))).unwrap();
+        std::fs::write(source.path().join("file.txt"), "second").unwrap();
+        let args = arguments(&["--overwrite", "rename"], source.path(), target.path());
+        let report = copy(args, gather_files_for_copying(&arguments(&[], source.path(), target.path()))).unwrap();
+        assert_eq!(report.copied, 1);
+        assert_eq!(std::fs::read_to_string(target.path().join("file.txt")).unwrap(), "first");
+        assert_eq!(std::fs::read_to_string(target.path().join("file (1).txt")).unwrap(), "second");
+    }
+
+    #[test]
+    fn test_overwrite_rename_releases_unused_names() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("file.txt"), "same").unwrap();
+        copy(arguments(&["--preserve-timestamps"], source.path(), target.path()),
+             gather_files_for_copying(&arguments(&[], source.path(), target.path()))).unwrap();
+        let args = arguments(&["--overwrite", "rename", "--skip-existing"], source.path(), target.path());
+        let report = copy(args, gather_files_for_copying(&arguments(&[], source.path(), target.path()))).unwrap();
+        assert_eq!(report.skipped, 1);
+        assert!(!target.path().join("file (1).txt").exists());
+    }
+
+    #[test]
+    fn test_flattened_targets_claim() {
+        let targets = FlattenedTargets::default();
+        let claim = |name: &str| targets.claim(PathBuf::from(name), FlattenCollision::Suffix);
+        assert_eq!(claim("t/photo.jpg"), Some(PathBuf::from("t/photo.jpg")));
+        assert_eq!(claim("t/photo_2.jpg"), Some(PathBuf::from("t/photo_2.jpg")));
+        assert_eq!(claim("t/photo.jpg"), Some(PathBuf::from("t/photo_1.jpg")));
+        assert_eq!(claim("t/photo.jpg"), Some(PathBuf::from("t/photo_3.jpg")));
+        assert_eq!(claim("t/photo_1.jpg"), Some(PathBuf::from("t/photo_1_1.jpg")));
+        assert_eq!(targets.claim(PathBuf::from("t/photo.jpg"), FlattenCollision::Skip), None);
+        assert_eq!(targets.claim(PathBuf::from("t/other.jpg"), FlattenCollision::Skip), Some(PathBuf::from("t/other.jpg")));
+    }
+
+    #[test]
+    fn test_flatten() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        for folder in ["a", "b", "c"] {
+            std::fs::create_dir(source.path().join(folder)).unwrap();
+            std::fs::write(source.path().join(folder).join("photo.jpg"), folder).unwrap();
+        }
+        std::fs::write(source.path().join("a").join("unique.jpg"), "unique").unwrap();
+        let args = arguments(&["--quiet", "--flatten", "--preserve-empty-dirs"], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 4);
+        let mut names: Vec<String> = std::fs::read_dir(target.path()).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["photo.jpg", "photo_1.jpg", "photo_2.jpg", "unique.jpg"]);
+        let mut contents: Vec<String> = ["photo.jpg", "photo_1.jpg", "photo_2.jpg"].iter()
+            .map(|name| std::fs::read_to_string(target.path().join(name)).unwrap())
+            .collect();
+        contents.sort();
+        assert_eq!(contents, vec!["a", "b", "c"]);
+
+        let target = tempfile::tempdir().unwrap();
+        let args = arguments(&["--quiet", "--flatten", "--flatten-collisions", "skip"], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 2);
+        assert_eq!(report.skipped, 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_is_on_device() {
+        use crate::{device_id, is_on_device};
+
+        let source = tempfile::tempdir().unwrap();
+        std::fs::create_dir(source.path().join("nested")).unwrap();
+        let device = device_id(source.path()).unwrap();
+        assert!(is_on_device(&source.path().join("nested"), device));
+        assert!(!is_on_device(&source.path().join("nested"), device + 1));
+        assert!(is_on_device(&source.path().join("missing"), device + 1));
+        #[cfg(target_os = "linux")]
+        if let (Some(root_device), Some(proc_device)) = (device_id(Path::new("/")), device_id(Path::new("/proc"))) {
+            assert_eq!(is_on_device(Path::new("/proc"), root_device), root_device == proc_device);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_one_filesystem_keeps_the_source_filesystem() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir(source.path().join("nested")).unwrap();
+        std::fs::write(source.path().join("nested").join("a.txt"), "a").unwrap();
+        std::fs::write(source.path().join("b.txt"), "b").unwrap();
+        for flags in [vec!["--one-filesystem"], vec!["--one-filesystem", "--parallel-walk"], vec!["--one-filesystem", "--respect-gitignore"]] {
+            let files = gather_files_for_copying(&arguments(&flags, source.path(), target.path()));
+            assert_eq!(files.len(), 2, "{:?}", flags);
+        }
+    }
+
+    #[test]
+    fn test_exclude_empty_files() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("empty.txt"), "").unwrap();
+        std::fs::write(source.path().join("placeholder.jpg"), "").unwrap();
+        std::fs::write(source.path().join("a.txt"), "a").unwrap();
+        std::fs::write(source.path().join("b.txt"), vec![0u8; 2048]).unwrap();
+        let names = |flags: &[&str]| {
+            let mut names: Vec<String> = gather_files_for_copying(&arguments(flags, source.path(), target.path())).iter()
+                .filter(|entry| entry.file_type().is_file())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(&[]), vec!["a.txt", "b.txt", "empty.txt", "placeholder.jpg"]);
+        assert_eq!(names(&["--exclude-empty-files"]), vec!["a.txt", "b.txt"]);
+        assert_eq!(names(&["--exclude-empty-files", "--max-size", "1K"]), vec!["a.txt"]);
+        let skipped = Arc::new(SkipCounts::default());
+        let args = arguments(&["--exclude-empty-files"], source.path(), target.path());
+        let _: Vec<DirEntry> = walk_source_root(&args, |path, is_dir| args.skip_reason_of_type(path, is_dir), skipped.clone()).collect();
+        assert_eq!(skipped.breakdown().as_deref(), Some("2 (empty)"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exclude_and_only_symlinks() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("regular.txt"), "regular").unwrap();
+        std::os::unix::fs::symlink(source.path().join("regular.txt"), source.path().join("link.txt")).unwrap();
+        let names = |flags: &[&str]| {
+            let mut names: Vec<String> = gather_files_for_copying(&arguments(flags, source.path(), target.path())).iter()
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(&[]), vec!["link.txt", "regular.txt"]);
+        assert_eq!(names(&["--exclude-symlinks"]), vec!["regular.txt"]);
+        assert_eq!(names(&["--exclude-symlinks", "--follow-links"]), vec!["regular.txt"]);
+        assert_eq!(names(&["--only-symlinks"]), vec!["link.txt"]);
+        assert_eq!(names(&["--only-symlinks", "--parallel-walk"]), vec!["link.txt"]);
+        let skipped = Arc::new(SkipCounts::default());
+        let args = arguments(&["--exclude-symlinks"], source.path(), target.path());
+        let _: Vec<DirEntry> = walk_source_root(&args, |path, is_dir| args.skip_reason_of_type(path, is_dir), skipped.clone()).collect();
+        assert_eq!(skipped.breakdown().as_deref(), Some("1 (symlink)"));
+
+        let args = arguments(&["--quiet", "--exclude-symlinks"], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        assert_eq!(copy(args, files).unwrap().copied, 1);
+        assert!(std::fs::symlink_metadata(target.path().join("link.txt")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_links_as_links() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(source.path().join("real")).unwrap();
+        std::fs::write(source.path().join("real").join("file.txt"), "content").unwrap();
+        std::os::unix::fs::symlink(Path::new("real").join("file.txt"), source.path().join("relative.txt")).unwrap();
+        std::os::unix::fs::symlink(source.path().join("real"), source.path().join("absolute")).unwrap();
+        for _ in 0..2 {
+            let args = arguments(&["--quiet", "--copy-links-as-links"], source.path(), target.path());
+            let files = gather_files_for_copying(&args);
+            let report = copy(args, files).unwrap();
+            assert!(report.is_success());
+            assert_eq!(report.copied, 3);
+        }
+        assert_eq!(std::fs::read_link(target.path().join("relative.txt")).unwrap(), Path::new("real").join("file.txt"));
+        assert_eq!(std::fs::read_to_string(target.path().join("relative.txt")).unwrap(), "content");
+        assert_eq!(std::fs::read_link(target.path().join("absolute")).unwrap(), source.path().join("real"));
+        assert!(std::fs::symlink_metadata(target.path().join("real").join("file.txt")).unwrap().is_file());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_dedup_leaves_links_copied_as_links_alone() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("z.txt"), "same").unwrap();
+        std::os::unix::fs::symlink("z.txt", source.path().join("a-link.txt")).unwrap();
+        let args = arguments(&["--quiet", "--copy-links-as-links", "--dedup", "--threads", "1"], source.path(), target.path());
+        let mut files = gather_files_for_copying(&args);
+        sort_files(&mut files, Order::Path);
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 2);
+        assert_eq!(report.duplicates, 0);
+        assert_eq!(std::fs::read_link(target.path().join("a-link.txt")).unwrap(), Path::new("z.txt"));
+        assert!(std::fs::symlink_metadata(target.path().join("z.txt")).unwrap().is_file());
+    }
+
+    #[test]
+    fn test_max_files() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        for directory in ["a", "b", "c"] {
+            std::fs::create_dir_all(source.path().join(directory)).unwrap();
+            for file in 0..5 {
+                std::fs::write(source.path().join(directory).join(format!("{}.txt", file)), "file").unwrap();
+            }
+        }
+        for flags in [vec!["--max-files", "7"], vec!["--max-files", "7", "--parallel-walk"],
+                      vec!["--max-files", "7", "--preserve-empty-dirs"]] {
+            let files = gather_files_for_copying(&arguments(&flags, source.path(), target.path()));
+            assert_eq!(files.iter().filter(|entry| entry.file_type().is_file()).count(), 7, "{:?}", flags);
+        }
+        assert_eq!(gather_files_for_copying(&arguments(&["--max-files", "100"], source.path(), target.path())).len(), 15);
+        assert!(gather_files_for_copying(&arguments(&["--max-files", "0"], source.path(), target.path())).is_empty());
+    }
+
+    #[test]
+    fn test_skip_inaccessible_counts_permission_errors() {
+        let skipped = SkipCounts::default();
+        let denied = std::io::Error::from(ErrorKind::PermissionDenied);
+        skip_inaccessible(&denied, Some(&denied), &skipped);
+        let missing = std::io::Error::from(ErrorKind::NotFound);
+        skip_inaccessible(&missing, Some(&missing), &skipped);
+        skip_inaccessible(&"a loop", None, &skipped);
+        assert_eq!(skipped.breakdown().as_deref(), Some("1 (permission denied)"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_permission_denied() {
+        use std::os::unix::fs::PermissionsExt;
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("readable.txt"), "readable").unwrap();
+        std::fs::write(source.path().join("secret.txt"), "secret").unwrap();
+        std::fs::create_dir(source.path().join("private")).unwrap();
+        std::fs::write(source.path().join("private").join("hidden.txt"), "hidden").unwrap();
+        std::fs::set_permissions(source.path().join("secret.txt"), std::fs::Permissions::from_mode(0o000)).unwrap();
+        std::fs::set_permissions(source.path().join("private"), std::fs::Permissions::from_mode(0o000)).unwrap();
+        let restore = || std::fs::set_permissions(source.path().join("private"), std::fs::Permissions::from_mode(0o755)).unwrap();
+        if File::open(source.path().join("secret.txt")).is_ok() {
+            // Running as root, which may read everything
+            restore();
+            return;
+        }
+        let skipped = Arc::new(SkipCounts::default());
+        let args = arguments(&[], source.path(), target.path());
+        let files: Vec<DirEntry> = walk_source_root(&args, |path, is_dir| args.skip_reason_of_type(path, is_dir), skipped.clone()).collect();
+        assert_eq!(skipped.breakdown().as_deref(), Some("1 (permission denied)"));
+
+        let report = copy(arguments(&[], source.path(), target.path()), files.clone()).unwrap();
+        assert_eq!(report.permission_denied, 1);
+        assert_eq!(report.failures.len(), 1);
+        let report = copy(arguments(&["--skip-permission-errors"], source.path(), target.path()), files).unwrap();
+        assert_eq!(report.permission_denied, 1);
+        assert!(report.is_success());
+        assert_eq!(report.skipped, 1);
+        restore();
+    }
+
+    #[test]
+    fn test_multiple_source_roots() {
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        for (source, name) in [(&first, "first"), (&second, "second")] {
+            std::fs::create_dir(source.path().join("nested")).unwrap();
+            std::fs::write(source.path().join("nested").join("shared.txt"), name).unwrap();
+            std::fs::write(source.path().join(format!("{}.txt", name)), name).unwrap();
+        }
+        let second_root = second.path().to_str().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let args = arguments(&["--quiet", "--source", second_root], first.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        assert_eq!(files.len(), 4);
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 4);
+        assert_eq!(std::fs::read_to_string(target.path().join("first.txt")).unwrap(), "first");
+        assert_eq!(std::fs::read_to_string(target.path().join("second.txt")).unwrap(), "second");
+        let mut shared: Vec<String> = ["shared.txt", "shared_1.txt"].iter()
+            .map(|name| std::fs::read_to_string(target.path().join("nested").join(name)).unwrap())
+            .collect();
+        shared.sort();
+        assert_eq!(shared, vec!["first", "second"]);
+
+        let target = tempfile::tempdir().unwrap();
+        let args = arguments(&["--quiet", "--source", second_root, "--source-collisions", "skip"], first.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 3);
+        assert_eq!(report.skipped, 1);
+        assert!(!target.path().join("nested").join("shared_1.txt").exists());
+    }
+
+    #[test]
+    fn test_files_below_filtered_folders_are_not_copied() {
+        let source = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(source.path().join("node_modules").join("pkg")).unwrap();
+        std::fs::create_dir_all(source.path().join("src").join("app")).unwrap();
+        std::fs::write(source.path().join("node_modules").join("pkg").join("index.js"), "pkg").unwrap();
+        std::fs::write(source.path().join("src").join("app").join("main.js"), "app").unwrap();
+        std::fs::write(source.path().join("src").join("lib.js"), "lib").unwrap();
+        for walk in [None, Some("--parallel-walk"), Some("--respect-gitignore")] {
+            for (folders, copied) in [("Ignore node_modules", ["src/app/main.js", "src/lib.js"].as_slice()), ("Target app", &["src/app/main.js"])] {
+                let target = tempfile::tempdir().unwrap();
+                let mut flags = vec!["--quiet", "--folders", folders];
+                flags.extend(walk);
+                let args = arguments(&flags, source.path(), target.path());
+                let files = gather_files_for_copying(&args);
+                copy(args, files).unwrap();
+                for file in ["node_modules/pkg/index.js", "src/app/main.js", "src/lib.js"] {
+                    assert_eq!(target.path().join(file).is_file(), copied.contains(&file), "{} with {:?} and {:?}", file, folders, walk);
+                }
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_source_roots_below_other_roots_keep_their_own_prefix() {
+        let first = tempfile::tempdir().unwrap();
+        let elsewhere = tempfile::tempdir().unwrap();
+        std::fs::write(elsewhere.path().join("linked.txt"), "linked").unwrap();
+        std::os::unix::fs::symlink(elsewhere.path(), first.path().join("link")).unwrap();
+        let second_root = first.path().join("link");
+        let target = tempfile::tempdir().unwrap();
+        let args = arguments(&["--quiet", "--exclude-symlinks", "--source", second_root.to_str().unwrap()], first.path(),
+                             target.path());
+        let files = gather_files_for_copying(&args);
+        let linked = second_root.join("linked.txt");
+        assert_eq!(args.source_root_of(&linked), second_root.to_str().unwrap());
+        assert_eq!(args.transform_source_to_target_path(&linked).unwrap(), target.path().join("linked.txt"));
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 1);
+        assert!(target.path().join("linked.txt").is_file());
+    }
+
+    #[test]
+    fn test_dedup() {
+        let source = tempfile::tempdir().unwrap();
+        std::fs::create_dir(source.path().join("nested")).unwrap();
+        std::fs::write(source.path().join("a.txt"), "same").unwrap();
+        std::fs::write(source.path().join("nested").join("b.txt"), "same").unwrap();
+        std::fs::write(source.path().join("c.txt"), "different").unwrap();
+
+        let target = tempfile::tempdir().unwrap();
+        let args = arguments(&["--quiet", "--dedup"], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 2);
+        assert_eq!(report.duplicates, 1);
+        let copied_same = target.path().join("a.txt").exists() as u8 + target.path().join("nested").join("b.txt").exists() as u8;
+        assert_eq!(copied_same, 1);
+
+        let target = tempfile::tempdir().unwrap();
+        let args = arguments(&["--quiet", "--dedup", "--dedup-link", "--threads", "1"], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 2);
+        assert_eq!(report.duplicates, 1);
+        assert_eq!(std::fs::read_to_string(target.path().join("a.txt")).unwrap(), "same");
+        assert_eq!(std::fs::read_to_string(target.path().join("nested").join("b.txt")).unwrap(), "same");
+    }
+
+    #[test]
+    fn test_dedup_ignores_content_whose_copy_was_skipped() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("a.txt"), "same").unwrap();
+        std::fs::write(source.path().join("b.txt"), "same").unwrap();
+        std::fs::write(target.path().join("a.txt"), "older").unwrap();
+        for mode in ["copy", "hardlink"] {
+            let args = arguments(&["--quiet", "--dedup", "--overwrite", "never", "--threads", "1", "--mode", mode],
+                                 source.path(), target.path());
+            let mut files = gather_files_for_copying(&args);
+            sort_files(&mut files, Order::Path);
+            let report = copy(args, files).unwrap();
+            assert_eq!(report.copied, 1);
+            assert_eq!(report.duplicates, 0);
+            assert_eq!(std::fs::read_to_string(target.path().join("a.txt")).unwrap(), "older");
+            assert_eq!(std::fs::read_to_string(target.path().join("b.txt")).unwrap(), "same");
+            std::fs::remove_file(target.path().join("b.txt")).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_resume_after_partial_run() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let state = tempfile::tempdir().unwrap();
+        let state_file = state.path().join("state");
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            std::fs::write(source.path().join(name), name).unwrap();
+        }
+        std::fs::write(&state_file, format!("{}\n", source.path().join("a.txt").to_string_lossy())).unwrap();
+        let state_flag = state_file.to_string_lossy().to_string();
+        let args = arguments(&["--resume", &state_flag], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 2);
+        assert_eq!(report.skipped, 1);
+        assert!(!target.path().join("a.txt").exists());
+        assert!(target.path().join("b.txt").exists());
+        assert_eq!(std::fs::read_to_string(&state_file).unwrap().lines().count(), 3);
+
+        std::fs::remove_file(target.path().join("b.txt")).unwrap();
+        let args = arguments(&["--resume", &state_flag], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 0);
+        assert_eq!(report.skipped, 3);
+        assert!(!target.path().join("b.txt").exists());
+    }
+
+    #[test]
+    fn test_skip_counts() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir(source.path().join("build")).unwrap();
+        std::fs::create_dir(source.path().join(".git")).unwrap();
+        std::fs::write(source.path().join("main.rs"), "fn main() {}").unwrap();
+        std::fs::write(source.path().join("README.md"), "readme").unwrap();
+        std::fs::write(source.path().join("notes.md"), "notes").unwrap();
+        std::fs::write(source.path().join("build").join("out.rs"), "out").unwrap();
+        std::fs::write(source.path().join("large.rs"), "x".repeat(1024)).unwrap();
+        std::fs::write(source.path().join(".git").join("HEAD"), "ref").unwrap();
+        let args = arguments(&["--file-extensions", "rs", "--folders", "Ignore build", "--max-size", "100",
+            "--exclude-hidden"], source.path(), target.path());
+        let skipped = Arc::new(SkipCounts::default());
+        let files: Vec<DirEntry> = walk_source_root(&args, |path, is_dir| args.skip_reason_of_type(path, is_dir), skipped.clone()).collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(skipped.breakdown().unwrap(), "2 (ext), 1 (folder), 1 (hidden), 1 (size)");
+        assert_eq!(SkipCounts::default().breakdown(), None);
+    }
+
+    #[test]
+    fn test_adaptive_threads() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        for i in 0..50 {
+            std::fs::write(source.path().join(format!("{}.txt", i)), "content").unwrap();
+        }
+        for flags in [&["--adaptive-threads"][..], &["--adaptive-threads", "--threads", "1"][..]] {
+            let args = arguments(flags, source.path(), target.path());
+            let files = gather_files_for_copying(&args);
+            let report = copy_streaming(&args, files.into_iter()).unwrap();
+            assert_eq!(report.copied, 50, "{:?}", flags);
+        }
+    }
+
+    #[test]
+    fn test_target_template() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir(source.path().join("nested")).unwrap();
+        std::fs::write(source.path().join("nested").join("photo.jpg"), "photo").unwrap();
+        std::fs::write(source.path().join("notes.txt"), "notes").unwrap();
+        let args = arguments(&["--quiet", "--target-template", "{ext}/{name}"], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 2);
+        assert_eq!(std::fs::read_to_string(target.path().join("jpg").join("photo.jpg")).unwrap(), "photo");
+        assert_eq!(std::fs::read_to_string(target.path().join("txt").join("notes.txt")).unwrap(), "notes");
+    }
+
+    #[test]
+    fn test_target_template_collisions() {
+        let source = tempfile::tempdir().unwrap();
+        std::fs::create_dir(source.path().join("a")).unwrap();
+        std::fs::create_dir(source.path().join("b")).unwrap();
+        std::fs::write(source.path().join("a").join("photo.jpg"), "a").unwrap();
+        std::fs::write(source.path().join("b").join("photo.jpg"), "b").unwrap();
+        std::fs::write(source.path().join("README"), "readme").unwrap();
+        for (collisions, copied) in [("suffix", 2), ("skip", 1)] {
+            let target = tempfile::tempdir().unwrap();
+            let args = arguments(&["--quiet", "--target-template", "{ext}/{name}", "--flatten-collisions", collisions], source.path(), target.path());
+            let files = gather_files_for_copying(&args);
+            let report = copy(args, files).unwrap();
+            assert_eq!(report.copied, copied, "{}", collisions);
+            assert_eq!(report.failures.len(), 1);
+            assert_eq!(report.failures[0].0, source.path().join("README"));
+            let mut contents = vec![std::fs::read_to_string(target.path().join("jpg").join("photo.jpg")).unwrap()];
+            if let Ok(suffixed) = std::fs::read_to_string(target.path().join("jpg").join("photo_1.jpg")) {
+                contents.push(suffixed);
+            }
+            assert_eq!(contents.len(), copied);
+        }
+    }
+
+    #[test]
+    fn test_part_file_is_removed_unless_committed() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let target = temp_dir.path().join("file.txt");
+        // A real file that happens to be named like a part file must survive copies next to it
+        std::fs::write(temp_dir.path().join("file.txt.part"), "unrelated").unwrap();
+        let part_file = PartFile::for_target(&target);
+        assert_eq!(part_file.path.parent(), Some(temp_dir.path()));
+        assert_ne!(part_file.path, PartFile::for_target(&target).path);
+        assert_ne!(part_file.path, temp_dir.path().join("file.txt.part"));
+        std::fs::write(&part_file.path, "truncat").unwrap();
+        let part_path = part_file.path.clone();
+        drop(part_file);
+        assert!(!part_path.exists());
+
+        let part_file = PartFile::for_target(&target);
+        let part_path = part_file.path.clone();
+        std::fs::write(&part_file.path, "complete").unwrap();
+        part_file.commit(&target).unwrap();
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "complete");
+        assert!(!part_path.exists());
+        assert_eq!(std::fs::read_to_string(temp_dir.path().join("file.txt.part")).unwrap(), "unrelated");
+    }
+
+    #[test]
+    fn test_failed_copy_leaves_no_partial_file() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("a.txt"), "a").unwrap();
+        std::fs::write(source.path().join("b.txt"), "b").unwrap();
+        std::fs::create_dir_all(target.path().join("b.txt")).unwrap();
+        let args = arguments(&["--quiet"], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 1);
+        assert_eq!(report.failures.len(), 1);
+        let mut names: Vec<String> = std::fs::read_dir(target.path()).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["a.txt", "b.txt"]);
+        assert!(target.path().join("b.txt").is_dir());
+    }
+
+    #[test]
+    fn test_preserve_empty_dirs() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(source.path().join("full")).unwrap();
+        std::fs::create_dir_all(source.path().join("empty").join("nested")).unwrap();
+        std::fs::write(source.path().join("full").join("file.txt"), "file").unwrap();
+
+        let args = arguments(&["--quiet"], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        assert!(files.iter().all(|entry| !entry.file_type().is_dir()));
+        copy(args, files).unwrap();
+        assert!(!target.path().join("empty").exists());
+
+        let args = arguments(&["--quiet", "--preserve-empty-dirs"], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 1);
+        assert!(report.is_success());
+        assert!(target.path().join("empty").join("nested").is_dir());
+        assert!(target.path().join("full").join("file.txt").is_file());
+    }
+
+    #[test]
+    fn test_copy_with_progress() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("a.txt"), vec![0u8; 100]).unwrap();
+        std::fs::write(source.path().join("b.txt"), vec![0u8; 23]).unwrap();
+        let args = arguments(&[], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let started = Mutex::new(Vec::new());
+        let finished = Mutex::new(Vec::new());
+        let report = copy_with_progress(args, files, |event| {
+            match event {
+                CopyEvent::Started { file } => { started.lock().unwrap().push(file.to_path_buf()) }
+                CopyEvent::Finished { size, bytes_copied, processed, total_bytes_copied, .. } => {
+                    assert_eq!(size, bytes_copied);
+                    finished.lock().unwrap().push((processed, total_bytes_copied));
+                }
+            }
+        }).unwrap();
+        assert_eq!(report.copied, 2);
+        assert_eq!(started.into_inner().unwrap().len(), 2);
+        let mut finished = finished.into_inner().unwrap();
+        finished.sort();
+        assert_eq!(finished.iter().map(|(processed, _)| *processed).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(finished.last().unwrap().1, 123);
+    }
+
+    #[test]
+    fn test_stop_signal_prevents_new_copies() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("a.txt"), "a").unwrap();
+        std::fs::write(source.path().join("b.txt"), "b").unwrap();
+        let args = arguments(&["--quiet"], source.path(), target.path());
+        args.stop_signal.request();
+        let report = copy_streaming(&args, discover_files(&args)).unwrap();
+        assert!(report.interrupted);
+        assert_eq!(report.copied, 0);
+        assert!(std::fs::read_dir(target.path()).unwrap().next().is_none());
+
+        let args = arguments(&["--quiet"], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let report = copy(args, files).unwrap();
+        assert!(!report.interrupted);
+        assert_eq!(report.copied, 2);
+    }
+
+    #[test]
+    fn test_stop_signal_abort_deletes_in_flight_targets() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let partial = temp_dir.path().join("partial.txt");
+        let finished = temp_dir.path().join("finished.txt");
+        std::fs::write(&partial, "part").unwrap();
+        std::fs::write(&finished, "finished").unwrap();
+        let signal = StopSignal::default();
+        drop(signal.track(&finished));
+        let _in_flight = signal.track(&partial);
+        signal.abort();
+        assert!(signal.is_requested());
+        assert!(!partial.exists());
+        assert!(finished.exists());
+    }
+
+    #[test]
+    fn test_stop_signal_abort_flushes_the_resume_state() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let state_file = temp_dir.path().join("state");
+        let resume_state = Arc::new(ResumeState::open(&state_file).unwrap());
+        let signal = StopSignal::default();
+        signal.flush_on_abort(&resume_state);
+        resume_state.record(Path::new("source/a.txt")).unwrap();
+        assert_eq!(std::fs::read_to_string(&state_file).unwrap(), "");
+        signal.abort();
+        assert_eq!(std::fs::read_to_string(&state_file).unwrap(), "source/a.txt\n");
+        drop(resume_state);
+        signal.abort();
+    }
+
+    #[test]
+    fn test_copy_streaming() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(source.path().join("a").join("b")).unwrap();
+        std::fs::write(source.path().join("top.txt"), "top").unwrap();
+        std::fs::write(source.path().join("a").join("b").join("deep.txt"), "deep").unwrap();
+        let args = arguments(&["--quiet"], source.path(), target.path());
+        let report = copy_streaming(&args, discover_files(&args)).unwrap();
+        assert_eq!(report.copied, 2);
+        assert_eq!(std::fs::read_to_string(target.path().join("a").join("b").join("deep.txt")).unwrap(), "deep");
+    }
+
+    #[test]
+    fn test_discovery_progress_sets_length_when_exhausted() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("a.txt"), "a").unwrap();
+        std::fs::write(source.path().join("b.txt"), "b").unwrap();
+        let args = arguments(&[], source.path(), target.path());
+        let bar = create_spinner(false, ProgressDrawTarget::hidden());
+        let files: Vec<DirEntry> = DiscoveryProgress::new(discover_files(&args), bar.clone(), false, None).collect();
+        assert_eq!(files.len(), 2);
+        assert_eq!(bar.length(), Some(2));
+    }
+
+    #[test]
+    fn test_copy_reports_failures() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("kept.txt"), "kept").unwrap();
+        std::fs::write(source.path().join("vanished.txt"), "vanished").unwrap();
+        let args = arguments(&[], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        std::fs::remove_file(source.path().join("vanished.txt")).unwrap();
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 1);
+        assert_eq!(report.failures.len(), 1);
+        assert!(report.failures[0].0.ends_with("vanished.txt"));
+        assert!(!report.is_success());
+        assert!(target.path().join("kept.txt").exists());
+    }
+
+    #[test]
+    fn test_error_log_can_be_retried_from_list() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let logs = tempfile::tempdir().unwrap();
+        let error_log = logs.path().join("errors.log");
+        std::fs::write(source.path().join("kept.txt"), "kept").unwrap();
+        std::fs::write(source.path().join("vanished.txt"), "vanished").unwrap();
+        let args = arguments(&["--error-log", error_log.to_str().unwrap()], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        std::fs::rename(source.path().join("vanished.txt"), logs.path().join("vanished.txt")).unwrap();
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.failures.len(), 1);
+        let content = std::fs::read_to_string(&error_log).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let (path, err) = lines[0].split_once('\t').unwrap();
+        assert_eq!(Path::new(path), source.path().join("vanished.txt"));
+        assert!(!err.is_empty());
+
+        std::fs::rename(logs.path().join("vanished.txt"), source.path().join("vanished.txt")).unwrap();
+        let args = arguments(&["--from-list", error_log.to_str().unwrap()], source.path(), target.path());
+        assert!(gather_files_from_list(&args, &error_log).unwrap().is_empty());
+        let args = arguments(&["--from-error-log", error_log.to_str().unwrap()], source.path(), target.path());
+        assert_eq!(args.from_list.as_deref(), Some(error_log.as_path()));
+        let files = gather_files_from_list(&args, &error_log).unwrap();
+        assert_eq!(files.len(), 1);
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 1);
+        assert!(target.path().join("vanished.txt").exists());
+    }
+
+    #[test]
+    fn test_on_error_abort_stops_after_the_first_failure() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        for name in ["a.txt", "b.txt", "vanished.txt"] {
+            std::fs::write(source.path().join(name), name).unwrap();
+        }
+        let args = arguments(&[], source.path(), target.path());
+        let mut files = gather_files_for_copying(&args);
+        files.sort_by_key(|entry| !entry.path().ends_with("vanished.txt"));
+        std::fs::remove_file(source.path().join("vanished.txt")).unwrap();
+
+        let args = arguments(&["--on-error", "abort", "--threads", "1"], source.path(), target.path());
+        match copy_streaming(&args, files.clone().into_iter()) {
+            Err(CopyError::Aborted(path, _)) => { assert!(path.ends_with("vanished.txt")) }
+            result => { panic!("Expected the copy to abort, got {:?}", result.map(|report| report.copied)) }
+        }
+        assert!(!target.path().join("a.txt").exists());
+        assert!(!target.path().join("b.txt").exists());
+
+        let args = arguments(&["--on-error", "continue", "--threads", "1"], source.path(), target.path());
+        let report = copy_streaming(&args, files.into_iter()).unwrap();
+        assert_eq!(report.copied, 2);
+        assert_eq!(report.failures.len(), 1);
+    }
+
+    #[test]
+    fn test_copy_onto_itself_is_skipped() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("file.txt"), "content").unwrap();
+        for mode in ["copy", "move", "hardlink", "symlink"] {
+            let mut args = arguments(&["--mode", mode], source.path(), target.path());
+            args.target_root_file_path = args.source_root_file_path.clone();
+            let files = gather_files_for_copying(&args);
+            let report = copy(args, files).unwrap();
+            assert_eq!(report.copied, 0, "{}", mode);
+            assert_eq!(report.skipped, 1, "{}", mode);
+            let file = source.path().join("file.txt");
+            assert!(!std::fs::symlink_metadata(&file).unwrap().file_type().is_symlink(), "{}", mode);
+            assert_eq!(std::fs::read_to_string(&file).unwrap(), "content", "{}", mode);
+        }
+    }
+
+    #[test]
+    fn test_simulated_errors_are_reported_as_failures() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let logs = tempfile::tempdir().unwrap();
+        for index in 0..20 {
+            std::fs::write(source.path().join(format!("{}.txt", index)), "content").unwrap();
+        }
+        let error_log = logs.path().join("errors.log");
+        let args = arguments(&["--simulate-error-rate", "0.5", "--simulate-seed", "42", "--error-log", error_log.to_str().unwrap()],
+                             source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let report = copy(args, files).unwrap();
+        assert!(!report.failures.is_empty());
+        assert_eq!(report.copied + report.failures.len(), 20);
+        assert_eq!(std::fs::read_to_string(&error_log).unwrap().lines().count(), report.failures.len());
+        for (path, _) in &report.failures {
+            assert!(!target.path().join(path.file_name().unwrap()).exists());
+        }
+    }
+
+    #[test]
+    fn test_simulated_errors_write_nothing() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("a.txt"), "same").unwrap();
+        std::fs::write(source.path().join("b.txt"), "same").unwrap();
+        std::fs::write(target.path().join("a.txt"), "older").unwrap();
+        let args = arguments(&["--quiet", "--simulate-error-rate", "1.0", "--overwrite", "rename", "--dedup", "--dedup-link"],
+                             source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.failures.len(), 2);
+        let target_files: Vec<PathBuf> = std::fs::read_dir(target.path()).unwrap().map(|entry| entry.unwrap().path()).collect();
+        assert_eq!(target_files, vec![target.path().join("a.txt")]);
+        assert_eq!(std::fs::read_to_string(target.path().join("a.txt")).unwrap(), "older");
+    }
+
+    #[test]
+    fn test_stats_per_extension() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir(source.path().join("photos")).unwrap();
+        std::fs::write(source.path().join("photos").join("a.jpg"), vec![0u8; 300]).unwrap();
+        std::fs::write(source.path().join("photos").join("b.JPG"), vec![0u8; 200]).unwrap();
+        std::fs::write(source.path().join("notes.txt"), vec![0u8; 10]).unwrap();
+        std::fs::write(source.path().join("Makefile"), vec![0u8; 5]).unwrap();
+        let files = gather_files_for_copying(&arguments(&[], source.path(), target.path()));
+        let report = copy(arguments(&["--stats"], source.path(), target.path()), files.clone()).unwrap();
+        let stats: Vec<(&str, usize, u64)> = report.extension_stats.iter()
+            .map(|stat| (stat.extension.as_str(), stat.files, stat.bytes))
+            .collect();
+        assert_eq!(stats, vec![(".jpg", 2, 500), (".txt", 1, 10), ("(none)", 1, 5)]);
+        let report = copy(arguments(&[], source.path(), target.path()), files).unwrap();
+        assert!(report.extension_stats.is_empty());
+    }
+
+    #[test]
+    fn test_max_open_files_bounds_concurrent_copies() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        for index in 0..16 {
+            std::fs::write(source.path().join(format!("{}.txt", index)), vec![0u8; 64 * 1024]).unwrap();
+        }
+        let args = arguments(&["--threads", "4", "--max-open-files", "3"], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let active = AtomicUsize::new(0);
+        let max_active = AtomicUsize::new(0);
+        let report = copy_with_progress(args, files, |event| {
+            match event {
+                CopyEvent::Started { .. } => {
+                    let now_active = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_active.fetch_max(now_active, Ordering::SeqCst);
+                }
+                CopyEvent::Finished { .. } => { active.fetch_sub(1, Ordering::SeqCst); }
+            }
+        }).unwrap();
+        assert_eq!(report.copied, 16);
+        assert_eq!(max_active.into_inner(), 1);
+    }
+
+    #[test]
+    fn test_max_total_size() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        for (name, size) in [("a.bin", 400), ("b.bin", 300), ("c.bin", 200), ("d.bin", 100)] {
+            std::fs::write(source.path().join(name), vec![0u8; size]).unwrap();
+        }
+        let args = arguments(&["--max-total-size", "650", "--order", "size", "--threads", "1"], source.path(), target.path());
+        let mut files = gather_files_for_copying(&args);
+        sort_files(&mut files, Order::SizeAsc);
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 3);
+        assert_eq!(report.bytes_copied, 600);
+        assert_eq!(report.budget, Some(BudgetUsage { used: 600, limit: 650, exhausted: true }));
+        assert!(!target.path().join("a.bin").exists());
+
+        let args = arguments(&["--max-total-size", "1K"], source.path(), &target.path().join("all"));
+        let files = gather_files_for_copying(&args);
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 4);
+        assert_eq!(report.budget, Some(BudgetUsage { used: 1000, limit: 1000, exhausted: false }));
+    }
+
+    #[test]
+    fn test_sort_files() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir(source.path().join("b")).unwrap();
+        let files = [("b/old.txt", 30, 500), ("a.txt", 10, 1_000), ("c.txt", 20, 2_000)];
+        for (name, size, modified) in files {
+            let path = source.path().join(name);
+            std::fs::write(&path, vec![0u8; size]).unwrap();
+            File::options().write(true).open(&path).unwrap()
+                .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(modified)).unwrap();
+        }
+        let args = arguments(&[], source.path(), target.path());
+        let sorted = |order: Order| {
+            let mut files = gather_files_for_copying(&args);
+            sort_files(&mut files, order);
+            files.iter()
+                .map(|entry| entry.path().strip_prefix(source.path()).unwrap().to_string_lossy().replace(std::path::MAIN_SEPARATOR, "/"))
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(sorted(Order::Path), vec!["a.txt", "b/old.txt", "c.txt"]);
+        assert_eq!(sorted(Order::SizeAsc), vec!["a.txt", "c.txt", "b/old.txt"]);
+        assert_eq!(sorted(Order::SizeDesc), vec!["b/old.txt", "c.txt", "a.txt"]);
+        assert_eq!(sorted(Order::Mtime), vec!["b/old.txt", "a.txt", "c.txt"]);
+        assert_eq!(CliArgs::parse_from(["filescraper", "s", "t", "--order", "size"]).convert().unwrap().order, Some(Order::SizeAsc));
+    }
+
+    #[test]
+    fn test_size_budget_releases_unused_reservations() {
+        let size_budget = SizeBudget::new(100);
+        assert!(size_budget.try_reserve(60));
+        assert!(!size_budget.try_reserve(50));
+        assert!(size_budget.is_exhausted());
+        size_budget.release(60);
+        assert!(size_budget.try_reserve(100));
+        assert_eq!(size_budget.usage(), BudgetUsage { used: 100, limit: 100, exhausted: true });
+    }
+
+    #[test]
+    fn test_dry_run_does_not_touch_disk() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir(source.path().join("nested")).unwrap();
+        std::fs::write(source.path().join("nested").join("file.txt"), "content").unwrap();
+        let args = arguments(&["--dry-run"], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 0);
+        assert!(report.is_success());
+        assert!(!target.path().join("nested").exists());
+    }
+
+    #[test]
+    fn test_move_removes_source() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("file.txt"), "content").unwrap();
+        let args = arguments(&["--mode", "move"], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 1);
+        assert!(!source.path().join("file.txt").exists());
+        assert_eq!(std::fs::read_to_string(target.path().join("file.txt")).unwrap(), "content");
+    }
+
+    #[test]
+    fn test_skip_existing_skips_identical_targets() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("same.txt"), "content").unwrap();
+        std::fs::write(source.path().join("changed.txt"), "new content").unwrap();
+        std::fs::copy(source.path().join("same.txt"), target.path().join("same.txt")).unwrap();
+        let modified = std::fs::metadata(source.path().join("same.txt")).unwrap().modified().unwrap();
+        std::fs::File::options().write(true).open(target.path().join("same.txt")).unwrap().set_modified(modified).unwrap();
+        std::fs::write(target.path().join("changed.txt"), "old").unwrap();
+        let args = arguments(&["--skip-existing"], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 1);
+        assert_eq!(report.skipped, 1);
+        assert_eq!(std::fs::read_to_string(target.path().join("changed.txt")).unwrap(), "new content");
+    }
+
+    #[test]
+    fn test_overwrite_never_keeps_existing_targets() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("existing.txt"), "new").unwrap();
+        std::fs::write(source.path().join("missing.txt"), "new").unwrap();
+        std::fs::write(target.path().join("existing.txt"), "old").unwrap();
+        let args = arguments(&["--overwrite", "never"], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 1);
+        assert_eq!(report.skipped, 1);
+        assert_eq!(std::fs::read_to_string(target.path().join("existing.txt")).unwrap(), "old");
+        assert_eq!(std::fs::read_to_string(target.path().join("missing.txt")).unwrap(), "new");
+    }
+
+    #[test]
+    fn test_checksum_manifest_verifies_with_sha256sum() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir(source.path().join("nested")).unwrap();
+        std::fs::write(source.path().join("a.txt"), "a").unwrap();
+        std::fs::write(source.path().join("nested").join("b.txt"), "b").unwrap();
+        let checksum_manifest = target.path().join("SHA256SUMS");
+        let args = arguments(&["--quiet", "--checksum-manifest", checksum_manifest.to_str().unwrap()], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        copy(args, files).unwrap();
+        let content = std::fs::read_to_string(&checksum_manifest).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let nested = Path::new("nested").join("b.txt");
+        for (line, path) in lines.iter().zip([Path::new("a.txt"), nested.as_path()]) {
+            let (checksum, listed_path) = line.split_once("  ").unwrap();
+            assert_eq!(checksum.len(), 64);
+            assert!(checksum.chars().all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase()));
+            assert_eq!(Path::new(listed_path), path);
+        }
+        if let Ok(status) = std::process::Command::new("sha256sum").args(["-c", "--quiet", "SHA256SUMS"]).current_dir(target.path()).status() {
+            assert!(status.success());
+        }
+    }
+
+    #[test]
+    fn test_manifest_lists_copied_and_failed_files() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let manifest_dir = tempfile::tempdir().unwrap();
+        let manifest_path = manifest_dir.path().join("manifest.json");
+        std::fs::write(source.path().join("kept.txt"), "kept").unwrap();
+        std::fs::write(source.path().join("vanished.txt"), "vanished").unwrap();
+        let args = arguments(&["--manifest", manifest_path.to_str().unwrap()], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        std::fs::remove_file(source.path().join("vanished.txt")).unwrap();
+        copy(args, files).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&manifest_path).unwrap()).unwrap();
+        assert!(json["duration_ms"].is_u64());
+        let entries = json["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        let kept = entries.iter().find(|entry| entry["source"].as_str().unwrap().ends_with("kept.txt")).unwrap();
+        assert_eq!(kept["size"], 4);
+        assert_eq!(kept["success"], true);
+        assert!(kept["target"].as_str().unwrap().starts_with(target.path().to_str().unwrap()));
+        let vanished = entries.iter().find(|entry| entry["source"].as_str().unwrap().ends_with("vanished.txt")).unwrap();
+        assert_eq!(vanished["success"], false);
+    }
+
+    #[test]
+    fn test_verify_accepts_intact_copies() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("file.txt"), "content").unwrap();
+        let args = arguments(&["--verify", "--verify-delete-on-mismatch"], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let report = copy(args, files).unwrap();
+        assert!(report.is_success());
+        assert_eq!(report.copied, 1);
+        assert!(target.path().join("file.txt").exists());
+    }
+
+    #[test]
+    fn test_verify_catches_copies_corrupted_before_the_check() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let source_file = source.path().join("file.txt");
+        std::fs::write(&source_file, "content").unwrap();
+        for delete_on_mismatch in [false, true] {
+            let target_file = target.path().join(format!("deleted_{}.txt", delete_on_mismatch));
+            let part_file = PartFile::for_target(&target_file);
+            let part_path = part_file.path.clone();
+            std::fs::copy(&source_file, &part_path).unwrap();
+            std::fs::write(&part_path, "corrupted").unwrap();
+            let err = verified(&source_file, part_file, &target_file, delete_on_mismatch).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            assert!(!part_path.exists());
+            if delete_on_mismatch {
+                assert!(!target_file.exists());
+            } else {
+                assert_eq!(std::fs::read_to_string(&target_file).unwrap(), "corrupted");
+            }
+        }
+        std::fs::write(target.path().join("intact.txt.part"), "content").unwrap();
+        let part_file = PartFile { path: target.path().join("intact.txt.part"), committed: false };
+        assert!(verified(&source_file, part_file, &target.path().join("intact.txt"), true).is_ok());
+    }
+
+    #[test]
+    fn test_verify_skips_links_but_not_their_fallback_copies() {
+        let source = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("file.txt"), "content").unwrap();
+        for mode in ["hardlink", "symlink"] {
+            let target = tempfile::tempdir().unwrap();
+            let args = arguments(&["--quiet", "--verify", "--mode", mode], source.path(), target.path());
+            let files = gather_files_for_copying(&args);
+            let report = copy(args, files).unwrap();
+            assert!(report.is_success(), "{}", mode);
+            assert_eq!(report.copied, 1, "{}", mode);
+        }
+        let target = tempfile::tempdir().unwrap();
+        let args = arguments(&["--verify"], source.path(), target.path());
+        let target_file = target.path().join("file.txt");
+        assert_eq!(copy_through_part_file(&source.path().join("file.txt"), &target_file, &args, true).unwrap(), 7);
+        assert_eq!(std::fs::read_to_string(&target_file).unwrap(), "content");
+    }
+
+    #[test]
+    fn test_preserve_timestamps() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let source_file = source.path().join("file.txt");
+        std::fs::write(&source_file, "content").unwrap();
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        File::options().write(true).open(&source_file).unwrap().set_modified(modified).unwrap();
+        let args = arguments(&["--preserve-timestamps"], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        copy(args, files).unwrap();
+        let target_modified = std::fs::metadata(target.path().join("file.txt")).unwrap().modified().unwrap();
+        assert_eq!(target_modified, modified);
+    }
+
+    #[test]
+    fn test_preserve_permissions_of_readonly_files() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let source_file = source.path().join("file.txt");
+        std::fs::write(&source_file, "content").unwrap();
+        let mut permissions = std::fs::metadata(&source_file).unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&source_file, permissions).unwrap();
+        let args = arguments(&["--preserve-permissions", "--preserve-timestamps"], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let report = copy(args, files).unwrap();
+        assert!(report.is_success());
+        assert!(std::fs::metadata(target.path().join("file.txt")).unwrap().permissions().readonly());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_preserve_xattrs() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let source_file = source.path().join("tagged.txt");
+        std::fs::write(&source_file, "content").unwrap();
+        if xattr::set(&source_file, "user.filescraper.tag", b"red").is_err() {
+            // The filesystem of the temp dir does not support extended attributes
+            return;
+        }
+        let args = arguments(&["--preserve-xattrs"], source.path(), &target.path().join("with"));
+        let files = gather_files_for_copying(&args);
+        assert!(copy(args, files).unwrap().is_success());
+        assert_eq!(xattr::get(target.path().join("with").join("tagged.txt"), "user.filescraper.tag").unwrap(), Some(b"red".to_vec()));
+
+        let args = arguments(&[], source.path(), &target.path().join("without"));
+        let files = gather_files_for_copying(&args);
+        assert!(copy(args, files).unwrap().is_success());
+        assert_eq!(xattr::get(target.path().join("without").join("tagged.txt"), "user.filescraper.tag").unwrap(), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_preserve_permissions_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        for (name, mode) in [("script.sh", 0o750), ("private.txt", 0o600), ("shared.txt", 0o664)] {
+            let source_file = source.path().join(name);
+            std::fs::write(&source_file, "content").unwrap();
+            std::fs::set_permissions(&source_file, std::fs::Permissions::from_mode(mode)).unwrap();
+        }
+        for copy_mode in ["copy", "move"] {
+            let target_dir = target.path().join(copy_mode);
+            let args = arguments(&["--preserve-permissions", "--mode", copy_mode], source.path(), &target_dir);
+            let files = gather_files_for_copying(&args);
+            assert!(copy(args, files).unwrap().is_success());
+            for (name, mode) in [("script.sh", 0o750), ("private.txt", 0o600), ("shared.txt", 0o664)] {
+                let target_mode = std::fs::metadata(target_dir.join(name)).unwrap().permissions().mode();
+                assert_eq!(target_mode & 0o7777, mode, "{} ({})", name, copy_mode);
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_chmod() {
+        use std::os::unix::fs::PermissionsExt;
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(source.path().join("nested").join("empty")).unwrap();
+        let source_file = source.path().join("nested").join("script.sh");
+        std::fs::write(&source_file, "content").unwrap();
+        std::fs::set_permissions(&source_file, std::fs::Permissions::from_mode(0o600)).unwrap();
+        let args = arguments(&["--chmod", "0754", "--chmod-dirs", "0o750", "--preserve-empty-dirs"], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        assert!(copy(args, files).unwrap().is_success());
+        let mode_of = |path: PathBuf| std::fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode_of(target.path().join("nested").join("script.sh")), 0o754);
+        assert_eq!(mode_of(target.path().join("nested")), 0o750);
+        assert_eq!(mode_of(target.path().join("nested").join("empty")), 0o750);
+        assert_ne!(mode_of(target.path().to_path_buf()), 0o750);
+
+        for mode in ["555", "0600", "0100"] {
+            let cli_args = CliArgs::parse_from(["filescraper", "--chmod-dirs", mode, "source", "target"]);
+            assert!(cli_args.convert().is_err(), "{}", mode);
+        }
+    }
+
+    #[test]
+    fn test_archive_round_trip() {
+        let source = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(source.path().join("nested").join("empty")).unwrap();
+        std::fs::write(source.path().join("a.txt"), "a").unwrap();
+        std::fs::write(source.path().join("nested").join("b.txt"), "bb").unwrap();
+        for archive_name in ["backup.tar", "backup.zip"] {
+            let archive_path = output.path().join(archive_name);
+            let cli = ["filescraper", "--quiet", "--preserve-empty-dirs", "--archive", archive_path.to_str().unwrap(),
+                source.path().to_str().unwrap()];
+            let args = CliArgs::parse_from(cli).convert().unwrap();
+            let files = gather_files_for_copying(&args);
+            let report = copy(args, files).unwrap();
+            assert!(report.is_success());
+            assert_eq!(report.copied, 2);
+            assert_eq!(report.bytes_copied, 3);
+            assert!(!output.path().join(format!("{}.part", archive_name)).exists());
+
+            let extracted = output.path().join(format!("{}.extracted", archive_name));
+            let archive_file = File::open(&archive_path).unwrap();
+            if archive_name.ends_with(".tar") {
+                tar::Archive::new(archive_file).unpack(&extracted).unwrap();
+            } else {
+                zip::ZipArchive::new(archive_file).unwrap().extract(&extracted).unwrap();
+            }
+            assert_eq!(std::fs::read_to_string(extracted.join("a.txt")).unwrap(), "a");
+            assert_eq!(std::fs::read_to_string(extracted.join("nested").join("b.txt")).unwrap(), "bb");
+            assert!(extracted.join("nested").join("empty").is_dir());
+        }
+    }
+
+    #[test]
+    fn test_archive_path() {
+        let target_root = long_path(Path::new("backup.tar")).into_owned();
+        let source = Path::new("source").join("a.txt");
+        assert_eq!(archive_path(&target_root, &source, &target_root.join("nested").join("a.txt")).unwrap(),
+                   Path::new("nested").join("a.txt"));
+        assert_eq!(archive_path(&target_root, &source, &target_root).unwrap(), Path::new("a.txt"));
+        assert!(archive_path(&target_root, &source, &Path::new("elsewhere").join("a.txt")).is_err());
+    }
+
+    #[test]
+    fn test_archive_replaces_target() {
+        let cli = ["filescraper", "--archive", "backup.tar", "source", "target"];
+        assert!(CliArgs::parse_from(cli).convert().is_err());
+        let cli = ["filescraper", "--archive", "backup.7z", "source"];
+        assert!(CliArgs::parse_from(cli).convert().is_err());
+        let cli = ["filescraper", "--archive", "backup.zip", "--mode", "move", "source"];
+        assert!(CliArgs::parse_from(cli).convert().is_err());
+    }
+
+    #[test]
+    fn test_compress() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(source.path().join("nested")).unwrap();
+        std::fs::write(source.path().join("nested").join("a.txt"), "some text").unwrap();
+        let args = arguments(&["--quiet", "--compress", "gzip", "--compression-level", "9"], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let report = copy(args, files).unwrap();
+        assert!(report.is_success());
+        assert_eq!(report.bytes_copied, 9);
+        assert!(!target.path().join("nested").join("a.txt").exists());
+        let mut decompressed = String::new();
+        let compressed = File::open(target.path().join("nested").join("a.txt.gz")).unwrap();
+        flate2::read::GzDecoder::new(compressed).read_to_string(&mut decompressed).unwrap();
+        assert_eq!(decompressed, "some text");
+    }
+
+    #[test]
+    fn test_compression_level() {
+        assert!(CliArgs::try_parse_from(["filescraper", "--compression-level", "3", "source", "target"]).is_err());
+        let cli_args = CliArgs::parse_from(["filescraper", "--compress", "gzip", "--compression-level", "10", "source", "target"]);
+        assert!(cli_args.convert().is_err());
+        let cli_args = CliArgs::parse_from(["filescraper", "--compress", "zstd", "source", "target"]);
+        assert_eq!(cli_args.convert().unwrap().compression_level, 3);
+    }
+
+    #[test]
+    fn test_compress_conflicts() {
+        let options: [&[&str]; 4] = [&["target", "--verify"], &["target", "--skip-existing"], &["target", "--delete-extraneous"],
+            &["--archive", "out.tar"]];
+        for option in options {
+            for (compress, valid) in [("gzip", false), ("none", true)] {
+                let mut cli = vec!["filescraper", "source", "--compress", compress];
+                cli.extend_from_slice(option);
+                assert_eq!(CliArgs::parse_from(cli).convert().is_ok(), valid, "{:?} with {}", option, compress);
+            }
+        }
+    }
+
+    #[test]
+    fn test_since_manifest() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let manifests = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("unchanged.txt"), "same").unwrap();
+        std::fs::write(source.path().join("modified.txt"), "before").unwrap();
+        let first_manifest = manifests.path().join("first.sha256");
+        let args = arguments(&["--quiet", "--checksum-manifest", first_manifest.to_str().unwrap()], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        assert_eq!(copy(args, files).unwrap().copied, 2);
+
+        std::fs::write(source.path().join("modified.txt"), "after!").unwrap();
+        std::fs::write(target.path().join("unchanged.txt"), "edited at the target").unwrap();
+        let second_manifest = manifests.path().join("second.sha256");
+        let args = arguments(&["--quiet", "--since-manifest", first_manifest.to_str().unwrap(),
+            "--checksum-manifest", second_manifest.to_str().unwrap()], source.path(), target.path());
+        let files = gather_files_for_copying(&args);
+        let report = copy(args, files).unwrap();
+        assert_eq!(report.copied, 1);
+        assert_eq!(report.skipped, 1);
+        assert_eq!(std::fs::read_to_string(target.path().join("modified.txt")).unwrap(), "after!");
+        assert_eq!(std::fs::read_to_string(target.path().join("unchanged.txt")).unwrap(), "edited at the target");
+        assert_eq!(std::fs::read_to_string(second_manifest).unwrap().lines().count(), 2);
+
+        let cli_args = CliArgs::parse_from(["filescraper", "--compress", "gzip", "--since-manifest",
+            first_manifest.to_str().unwrap(), source.path().to_str().unwrap(), target.path().to_str().unwrap()]);
+        assert!(cli_args.convert().is_err());
+    }
+}
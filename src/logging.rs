@@ -0,0 +1,58 @@
+use std::io::Write;
+
+use chrono::{SecondsFormat, Utc};
+use env_logger::fmt::Formatter;
+use log::kv::{Key, Value, VisitSource};
+use log::Record;
+use serde_json::{Map, Value as JsonValue};
+
+/// Writes `record` as a single line JSON object, to be passed to [env_logger::Builder::format]
+pub fn format_json(buf: &mut Formatter, record: &Record) -> std::io::Result<()> {
+    writeln!(buf, "{}", json_record(record))
+}
+
+/// The record's timestamp, level, target and message, followed by its key-values like the `file` of copy events
+fn json_record(record: &Record) -> JsonValue {
+    let mut fields = Map::new();
+    fields.insert("timestamp".to_string(), JsonValue::from(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)));
+    fields.insert("level".to_string(), JsonValue::from(record.level().as_str()));
+    fields.insert("target".to_string(), JsonValue::from(record.target()));
+    fields.insert("message".to_string(), JsonValue::from(record.args().to_string()));
+    let mut key_values = KeyValues(&mut fields);
+    if let Err(err) = record.key_values().visit(&mut key_values) {
+        fields.insert("key_value_error".to_string(), JsonValue::from(err.to_string()));
+    }
+    JsonValue::Object(fields)
+}
+
+struct KeyValues<'a>(&'a mut Map<String, JsonValue>);
+
+impl<'kvs> VisitSource<'kvs> for KeyValues<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        self.0.insert(key.to_string(), JsonValue::from(value.to_string()));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::{Level, Record};
+
+    use crate::logging::json_record;
+
+    #[test]
+    fn test_json_record() {
+        let key_values = [("file", "source/a.txt")];
+        let json = json_record(&Record::builder()
+            .args(format_args!("Failed to copy {}", "source/a.txt"))
+            .level(Level::Warn)
+            .target("filescraper")
+            .key_values(&key_values)
+            .build());
+        assert_eq!(json["level"], "WARN");
+        assert_eq!(json["target"], "filescraper");
+        assert_eq!(json["message"], "Failed to copy source/a.txt");
+        assert_eq!(json["file"], "source/a.txt");
+        assert!(json["timestamp"].as_str().unwrap().ends_with('Z'));
+    }
+}
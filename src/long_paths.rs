@@ -0,0 +1,74 @@
+use std::borrow::Cow;
+use std::path::Path;
+
+/// Prefixes an absolute Windows path with `\\?\`, which lifts the 260 character limit of `MAX_PATH`.
+/// As the prefix turns off the normalization of the path, it is made absolute first
+#[cfg(windows)]
+pub fn long_path(path: &Path) -> Cow<'_, Path> {
+    let Ok(absolute) = std::path::absolute(path) else {
+        return Cow::Borrowed(path);
+    };
+    match absolute.to_str().and_then(with_long_path_prefix) {
+        Some(prefixed) => { Cow::Owned(prefixed.into()) }
+        None => { Cow::Borrowed(path) }
+    }
+}
+
+/// Paths are only limited on Windows
+#[cfg(not(windows))]
+pub fn long_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+/// `C:\dir` becomes `\\?\C:\dir` and `\\server\share` becomes `\\?\UNC\server\share`. Paths that are
+/// already prefixed, device paths and relative paths are left alone
+#[cfg_attr(not(windows), allow(dead_code))]
+fn with_long_path_prefix(path: &str) -> Option<String> {
+    if path.starts_with(r"\\?\") || path.starts_with(r"\\.\") {
+        return None;
+    }
+    if let Some(unc) = path.strip_prefix(r"\\") {
+        return Some(format!(r"\\?\UNC\{}", unc));
+    }
+    match path.as_bytes() {
+        [drive, b':', b'\\', ..] if drive.is_ascii_alphabetic() => { Some(format!(r"\\?\{}", path)) }
+        _ => { None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::long_paths::{long_path, with_long_path_prefix};
+
+    #[test]
+    fn test_with_long_path_prefix() {
+        assert_eq!(with_long_path_prefix(r"C:\data\photos\a.jpg").as_deref(), Some(r"\\?\C:\data\photos\a.jpg"));
+        assert_eq!(with_long_path_prefix(r"\\server\share\a.jpg").as_deref(), Some(r"\\?\UNC\server\share\a.jpg"));
+        assert_eq!(with_long_path_prefix(r"\\?\C:\data"), None);
+        assert_eq!(with_long_path_prefix(r"\\.\PhysicalDrive0"), None);
+        assert_eq!(with_long_path_prefix(r"data\photos"), None);
+        assert_eq!(with_long_path_prefix(r"C:data"), None);
+        assert_eq!(with_long_path_prefix("/home/user"), None);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn test_long_path_is_unchanged() {
+        let path = std::path::Path::new("target/a.txt");
+        assert_eq!(long_path(path), path);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_long_path_copies_beyond_max_path() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let deep = (0..30).fold(temp_dir.path().to_path_buf(), |path, _| path.join("a_rather_long_folder"));
+        assert!(deep.to_string_lossy().len() > 260);
+        std::fs::create_dir_all(long_path(&deep)).unwrap();
+        let source = temp_dir.path().join("a.txt");
+        std::fs::write(&source, "a").unwrap();
+        std::fs::copy(&source, long_path(&deep.join("a.txt"))).unwrap();
+        assert!(long_path(&deep).starts_with(r"\\?\"));
+        assert_eq!(std::fs::read_to_string(long_path(&deep.join("a.txt"))).unwrap(), "a");
+    }
+}
@@ -1,23 +1,209 @@
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::Path;
+use std::process::ExitCode;
+
+use anyhow::{bail, Context};
+use bytesize::ByteSize;
 use clap::Parser;
 use colorful::{Color, Colorful};
 use env_logger::Builder;
-use log::info;
-
-use filescraper::{copy, gather_files_for_copying, print_colorful_when_supported};
-use filescraper::args::CliArgs;
-
-
-fn main() -> anyhow::Result<()> {
-    let args: filescraper::args::Arguments = CliArgs::parse().convert();
-    Builder::new().filter_level(args.verbose.log_level_filter()).init();
-    let files = gather_files_for_copying(&args);
-    info!("Found {} files and directories eligible for copying", files.len());
-    let duration = copy(args, files);
-    let message = format!("Whole operation took {:?}", duration);
-    let message = message.as_str();
-    print_colorful_when_supported(message, |msg| msg.gradient(Color::Green));
-    Ok(())
+use log::{error, info};
+use walkdir::DirEntry;
+
+use filescraper::{copy_files, copy_streaming, count_files, discover_files, gather_files_for_copying, gather_files_from_list, print_colorful_when_supported, set_color_choice, sort_files};
+use filescraper::args::{Arguments, CliArgs, LogFormat};
+use filescraper::diff::{diff_files, DiffSummary};
+use filescraper::extraneous::{delete_extraneous, find_extraneous};
+use filescraper::hook::run_post_command;
+use filescraper::logging::format_json;
+use filescraper::manifest::Summary;
+use filescraper::stats::render_table;
+use filescraper::tree::render_tree;
+use filescraper::watch::{DEFAULT_DEBOUNCE, watch};
+
+/// How many extensions `--stats` lists before summing up the rest
+const STATS_TOP_EXTENSIONS: usize = 10;
+
+/// The conventional exit code of processes stopped by SIGINT
+const INTERRUPTED_EXIT_CODE: u8 = 130;
+
+fn main() -> anyhow::Result<ExitCode> {
+    let cli_args = CliArgs::parse();
+    let mut logger = Builder::new();
+    logger.filter_level(cli_args.log_level_filter());
+    if cli_args.log_format() == LogFormat::Json {
+        logger.format(format_json);
+    }
+    logger.init();
+    let args = cli_args.convert()?;
+    set_color_choice(args.color);
+    let quiet = args.quiet;
+    let summary_json = args.summary_json;
+    let post_command = args.post_command.clone();
+    if args.count_only {
+        let (count, bytes) = match &args.from_list {
+            Some(list) => { count_files(gather_files_from_list(&args, list)?) }
+            None => { count_files(discover_files(&args)) }
+        };
+        println!("{} files, {}", count, ByteSize(bytes));
+        return Ok(ExitCode::SUCCESS);
+    }
+    if args.diff {
+        let entries = match &args.from_list {
+            Some(list) => { diff_files(&args, gather_files_from_list(&args, list)?) }
+            None => { diff_files(&args, discover_files(&args)) }
+        };
+        if args.diff_files {
+            for entry in &entries {
+                println!("{:<8}{}", entry.status.to_string(), entry.source_path.to_string_lossy());
+            }
+        }
+        println!("{}", DiffSummary::of(&entries));
+        return Ok(ExitCode::SUCCESS);
+    }
+    if args.list_only || args.preview_tree {
+        let files = match &args.from_list {
+            Some(list) => { gather_files_from_list(&args, list)? }
+            None => { gather_files_for_copying(&args) }
+        };
+        if args.preview_tree {
+            for source_root in &args.source_roots {
+                let files = files.iter()
+                    .filter(|file| args.source_root_of(file.path()) == source_root)
+                    .map(DirEntry::path);
+                print!("{}", render_tree(Path::new(source_root), files));
+            }
+        } else {
+            for file in &files {
+                println!("{}", file.path().to_string_lossy());
+            }
+        }
+        if !quiet {
+            eprintln!("Found {} files and directories eligible for copying", files.len());
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+    let stop_signal = args.stop_signal.clone();
+    ctrlc::set_handler(move || {
+        if stop_signal.is_requested() {
+            stop_signal.abort();
+            std::process::exit(INTERRUPTED_EXIT_CODE.into());
+        }
+        stop_signal.request();
+        if !quiet {
+            print_colorful_when_supported("Finishing the running copies, press Ctrl-C again to abort them",
+                                          |msg| msg.gradient(Color::Yellow));
+        }
+    })?;
+    let ask_for_confirmation = !args.yes && !args.dry_run && std::io::stdout().is_terminal();
+    let report = if args.from_list.is_some() || ask_for_confirmation || args.order.is_some() {
+        let mut files = match &args.from_list {
+            Some(list) => { gather_files_from_list(&args, list)? }
+            None => { gather_files_for_copying(&args) }
+        };
+        info!("Found {} files and directories eligible for copying", files.len());
+        if let Some(order) = args.order {
+            sort_files(&mut files, order);
+        }
+        if ask_for_confirmation && !confirm_copy(&args, &files)? {
+            println!("Nothing was copied");
+            return Ok(ExitCode::SUCCESS);
+        }
+        copy_files(&args, files)?
+    } else {
+        copy_streaming(&args, discover_files(&args))?
+    };
+    if summary_json {
+        println!("{}", serde_json::to_string(&Summary::from(&report))?);
+    } else if !quiet {
+        let duplicates = if report.duplicates > 0 { format!(" and {} duplicates", report.duplicates) } else { String::new() };
+        let budget = match report.budget {
+            Some(budget) => { format!(", using {} of the {} budget", ByteSize(budget.used), ByteSize(budget.limit)) }
+            None => { String::new() }
+        };
+        let message = format!("Copied {} files ({}) and skipped {}{} in {:?} ({}/s){}",
+                              report.copied, ByteSize(report.bytes_copied), report.skipped, duplicates, report.duration,
+                              ByteSize(report.bytes_per_second()), budget);
+        let message = message.as_str();
+        print_colorful_when_supported(message, |msg| msg.gradient(Color::Green));
+        if args.stats && !report.extension_stats.is_empty() {
+            print!("{}", render_table(&report.extension_stats, STATS_TOP_EXTENSIONS));
+        }
+    }
+    if !report.is_success() {
+        error!("Failed to copy {} of {} files", report.failures.len(), report.copied + report.failures.len());
+    }
+    let mut deletion_succeeded = true;
+    if args.delete_extraneous && !report.interrupted {
+        let extraneous = find_extraneous(&args);
+        if extraneous.is_empty() {
+            info!("Nothing at the target is extraneous");
+        } else if args.dry_run || confirm_deletion(&args, extraneous.len())? {
+            let (deleted, failures) = delete_extraneous(&extraneous, args.dry_run);
+            if !quiet && !args.dry_run {
+                println!("Deleted {} entries that no longer exist at the source", deleted);
+            }
+            deletion_succeeded = failures.is_empty();
+        } else {
+            println!("Nothing was deleted");
+        }
+    }
+    if args.watch && !report.interrupted {
+        let copied = watch(&args, DEFAULT_DEBOUNCE)?;
+        if !quiet {
+            println!("Copied {} more files while watching", copied);
+        }
+    }
+    let post_command_succeeded = match &post_command {
+        Some(post_command) => {
+            let status = run_post_command(post_command, &report)
+                .with_context(|| format!("Could not run the post command '{}'", post_command))?;
+            if !status.success() {
+                error!("The post command '{}' failed with {}", post_command, status);
+            }
+            status.success()
+        }
+        None => { true }
+    };
+    if report.interrupted {
+        error!("Interrupted before all files were copied");
+        return Ok(ExitCode::from(INTERRUPTED_EXIT_CODE));
+    }
+    if !report.is_success() || !post_command_succeeded || !deletion_succeeded {
+        return Ok(ExitCode::FAILURE);
+    }
+    Ok(ExitCode::SUCCESS)
 }
 
+/// Asks on the terminal whether to go ahead if the copy exceeds the confirmation threshold
+fn confirm_copy(args: &Arguments, files: &[DirEntry]) -> anyhow::Result<bool> {
+    let bytes = files.iter()
+        .filter_map(|file| file.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum();
+    if !args.confirm_threshold.is_exceeded(files.len(), bytes) {
+        return Ok(true);
+    }
+    ask(&format!("About to copy {} files ({}) to {}, continue?", files.len(), ByteSize(bytes), args.target_root_file_path))
+}
 
+/// Asks on the terminal whether the `extraneous` entries of `--delete-extraneous` may be deleted, without a
+/// terminal to ask on `--yes` is required
+fn confirm_deletion(args: &Arguments, extraneous: usize) -> anyhow::Result<bool> {
+    if args.yes {
+        return Ok(true);
+    }
+    if !std::io::stdin().is_terminal() {
+        bail!("--delete-extraneous would delete {} entries, pass --yes to confirm this without a terminal", extraneous);
+    }
+    ask(&format!("About to delete {} entries at {} that no longer exist at the source, continue?", extraneous, args.target_root_file_path))
+}
 
+fn ask(question: &str) -> anyhow::Result<bool> {
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
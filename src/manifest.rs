@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::args::ChecksumAlgorithm;
+use crate::CopyReport;
+use crate::hash;
+
+/// A single file handled during a [crate::copy] run
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub size: u64,
+    pub success: bool,
+}
+
+/// A machine-readable record of a whole [crate::copy] run
+#[derive(Serialize, Debug)]
+pub struct Manifest {
+    pub duration_ms: u128,
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// The totals of a whole [crate::copy] run as printed by `--summary-json`
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Summary {
+    pub duration_ms: u128,
+    pub files_copied: usize,
+    pub files_skipped: usize,
+    pub files_duplicate: usize,
+    pub files_failed: usize,
+    pub bytes_copied: u64,
+    pub interrupted: bool,
+}
+
+impl From<&CopyReport> for Summary {
+    fn from(report: &CopyReport) -> Summary {
+        Summary {
+            duration_ms: report.duration.as_millis(),
+            files_copied: report.copied,
+            files_skipped: report.skipped,
+            files_duplicate: report.duplicates,
+            files_failed: report.failures.len(),
+            bytes_copied: report.bytes_copied,
+            interrupted: report.interrupted,
+        }
+    }
+}
+
+impl Manifest {
+    pub fn new(duration: Duration, entries: Vec<ManifestEntry>) -> Manifest {
+        Manifest { duration_ms: duration.as_millis(), entries }
+    }
+
+    /// Writes the manifest as JSON to a sibling temp file first and renames it into place afterwards,
+    /// so `path` never contains a partially written manifest
+    pub fn write_atomically(&self, path: &Path) -> std::io::Result<()> {
+        write_atomically(path, |file| Ok(serde_json::to_writer_pretty(file, self)?))
+    }
+}
+
+/// The checksums of all copied files collected by the workers for `--checksum-manifest`
+#[derive(Default)]
+pub struct ChecksumManifest {
+    entries: Mutex<Vec<(PathBuf, String)>>,
+}
+
+impl ChecksumManifest {
+    /// Adds the `checksum` of the file at `relative_target`, the path relative to the target root
+    pub fn record(&self, relative_target: PathBuf, checksum: String) {
+        self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push((relative_target, checksum));
+    }
+
+    /// Writes all checksums sorted by path in the format of `sha256sum`, the same way as
+    /// [Manifest::write_atomically]. The paths are separated by `/` on every platform, so a manifest written on
+    /// Windows reads the same elsewhere. Like in `sha256sum`, paths containing a backslash or newline are escaped
+    /// and their line starts with a backslash
+    pub fn write_atomically(self, path: &Path) -> std::io::Result<()> {
+        let mut entries = self.entries.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner());
+        entries.sort();
+        write_atomically(path, |file| {
+            for (relative_target, checksum) in &entries {
+                let relative_target = with_forward_slashes(relative_target);
+                if relative_target.contains(['\\', '\n']) {
+                    let escaped = relative_target.replace('\\', "\\\\").replace('\n', "\\n");
+                    writeln!(file, "\\{}  {}", checksum, escaped)?;
+                } else {
+                    writeln!(file, "{}  {}", checksum, relative_target)?;
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+/// The checksums of an earlier run's `--checksum-manifest`, read for `--since-manifest`. Their algorithm is
+/// told by the length of the checksums
+pub struct PreviousChecksums {
+    algorithm: ChecksumAlgorithm,
+    checksums: HashMap<PathBuf, String>,
+}
+
+impl PreviousChecksums {
+    /// Reads the checksum manifest at `path`, undoing the escaping of [ChecksumManifest::write_atomically]
+    pub fn read(path: &Path) -> std::io::Result<PreviousChecksums> {
+        let invalid = |line: &str| std::io::Error::new(ErrorKind::InvalidData, format!("'{}' is no checksum line", line));
+        let mut algorithm = None;
+        let mut checksums = HashMap::new();
+        for line in BufReader::new(std::fs::File::open(path)?).lines() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let (escaped, line_content) = match line.strip_prefix('\\') {
+                Some(line_content) => { (true, line_content) }
+                None => { (false, line.as_str()) }
+            };
+            let (checksum, relative_target) = line_content.split_once("  ").ok_or_else(|| invalid(&line))?;
+            let line_algorithm = algorithm_of(checksum).ok_or_else(|| invalid(&line))?;
+            if *algorithm.get_or_insert(line_algorithm) != line_algorithm {
+                return Err(std::io::Error::new(ErrorKind::InvalidData, "the checksums were computed with different algorithms"));
+            }
+            let relative_target = if escaped { unescape(relative_target) } else { relative_target.to_string() };
+            checksums.insert(PathBuf::from(relative_target), checksum.to_string());
+        }
+        Ok(PreviousChecksums { algorithm: algorithm.unwrap_or_default(), checksums })
+    }
+
+    pub fn algorithm(&self) -> ChecksumAlgorithm {
+        self.algorithm
+    }
+
+    /// Hashes `source_path` if the manifest lists `relative_target`, returning the checksum if it did not change
+    pub fn unchanged(&self, relative_target: &Path, source_path: &Path) -> std::io::Result<Option<String>> {
+        let Some(previous) = self.checksums.get(relative_target) else {
+            return Ok(None);
+        };
+        let checksum = hash::checksum(source_path, self.algorithm)?;
+        Ok((checksum == *previous).then_some(checksum))
+    }
+}
+
+/// Joins the components of the relative `path` with `/` instead of the separator of the platform
+fn with_forward_slashes(path: &Path) -> String {
+    path.components().map(|component| component.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/")
+}
+
+/// The algorithm producing lowercase hex checksums of the length of `checksum`
+fn algorithm_of(checksum: &str) -> Option<ChecksumAlgorithm> {
+    if !checksum.bytes().all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte)) {
+        return None;
+    }
+    match checksum.len() {
+        32 => { Some(ChecksumAlgorithm::Xxh128) }
+        64 => { Some(ChecksumAlgorithm::Sha256) }
+        128 => { Some(ChecksumAlgorithm::Sha512) }
+        _ => { None }
+    }
+}
+
+fn unescape(escaped: &str) -> String {
+    let mut unescaped = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some('n')) => {
+                unescaped.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                unescaped.push('\\');
+                chars.next();
+            }
+            (c, _) => { unescaped.push(c) }
+        }
+    }
+    unescaped
+}
+
+fn write_atomically(path: &Path, write: impl FnOnce(&mut std::io::BufWriter<&std::fs::File>) -> std::io::Result<()>) -> std::io::Result<()> {
+    let mut temp_path = path.as_os_str().to_os_string();
+    temp_path.push(".part");
+    let temp_path = PathBuf::from(temp_path);
+    let file = std::fs::File::create(&temp_path)?;
+    let mut writer = std::io::BufWriter::new(&file);
+    write(&mut writer)?;
+    writer.flush()?;
+    drop(writer);
+    file.sync_all()?;
+    drop(file);
+    std::fs::rename(&temp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use crate::CopyReport;
+    use crate::args::ChecksumAlgorithm;
+    use crate::manifest::{ChecksumManifest, Manifest, ManifestEntry, PreviousChecksums, Summary};
+
+    #[test]
+    fn test_write_atomically() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("manifest.json");
+        let manifest = Manifest::new(Duration::from_millis(1500), vec![ManifestEntry {
+            source: PathBuf::from("source/a.txt"),
+            target: PathBuf::from("target/a.txt"),
+            size: 3,
+            success: true,
+        }]);
+        manifest.write_atomically(&path).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["duration_ms"], 1500);
+        assert_eq!(json["entries"][0]["source"], "source/a.txt");
+        assert_eq!(json["entries"][0]["target"], "target/a.txt");
+        assert_eq!(json["entries"][0]["size"], 3);
+        assert_eq!(json["entries"][0]["success"], true);
+        assert!(!temp_dir.path().join("manifest.json.part").exists());
+    }
+
+    #[test]
+    fn test_checksum_manifest_format() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("SHA256SUMS");
+        let manifest = ChecksumManifest::default();
+        manifest.record(["nested", "b.txt"].iter().collect(), "bb".to_string());
+        manifest.record(PathBuf::from("a.txt"), "aa".to_string());
+        manifest.record(PathBuf::from("odd\nname.txt"), "cc".to_string());
+        manifest.write_atomically(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "aa  a.txt\nbb  nested/b.txt\n\\cc  odd\\nname.txt\n");
+        assert!(!temp_dir.path().join("SHA256SUMS.part").exists());
+    }
+
+    #[test]
+    fn test_read_previous_checksums() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("XXH128SUMS");
+        let manifest = ChecksumManifest::default();
+        manifest.record(PathBuf::from("a.txt"), "0".repeat(32));
+        manifest.record(PathBuf::from("odd\\back\nslash.txt"), "f".repeat(32));
+        manifest.write_atomically(&path).unwrap();
+        let previous = PreviousChecksums::read(&path).unwrap();
+        assert_eq!(previous.algorithm(), ChecksumAlgorithm::Xxh128);
+        assert_eq!(previous.checksums.get(&PathBuf::from("a.txt")), Some(&"0".repeat(32)));
+        assert_eq!(previous.checksums.get(&PathBuf::from("odd\\back\nslash.txt")), Some(&"f".repeat(32)));
+
+        std::fs::write(&path, format!("{}  a.txt\n{}  b.txt\n", "0".repeat(32), "0".repeat(64))).unwrap();
+        assert!(PreviousChecksums::read(&path).is_err());
+        std::fs::write(&path, "not a checksum\n").unwrap();
+        assert!(PreviousChecksums::read(&path).is_err());
+    }
+
+    #[test]
+    fn test_summary_json() {
+        let report = CopyReport {
+            duration: Duration::from_millis(2500),
+            copied: 3,
+            bytes_copied: 1_000,
+            skipped: 2,
+            duplicates: 1,
+            failures: vec![(PathBuf::from("broken.txt"), std::io::ErrorKind::NotFound.into())],
+            permission_denied: 0,
+            extension_stats: Vec::new(),
+            interrupted: false,
+            budget: None,
+        };
+        let json: serde_json::Value = serde_json::to_value(Summary::from(&report)).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "duration_ms": 2500,
+            "files_copied": 3,
+            "files_skipped": 2,
+            "files_duplicate": 1,
+            "files_failed": 1,
+            "bytes_copied": 1000,
+            "interrupted": false,
+        }));
+    }
+}
@@ -0,0 +1,138 @@
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::debug;
+use serde::Serialize;
+
+/// Updates are written at most this often, the final one is always written
+const UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// One line written by [ProgressWriter]
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ProgressUpdate {
+    /// Entries processed so far, whether they were copied, skipped or failed
+    pub current: usize,
+    /// All entries of the copy, `null` while they are still being discovered
+    pub total: Option<usize>,
+    /// Bytes copied so far
+    pub bytes: u64,
+    /// Whether this is the last update of the copy
+    pub done: bool,
+}
+
+/// Writes the progress of a copy as newline-delimited JSON for `--progress-fd`, like
+/// `{"current":12,"total":40,"bytes":123456,"done":false}`. Writing stops at the first error, e.g. once the
+/// reading side of a pipe was closed, as the copy itself should not fail because of it
+pub struct ProgressWriter {
+    state: Mutex<State>,
+}
+
+struct State {
+    writer: Box<dyn Write + Send>,
+    total: Option<usize>,
+    written_at: Option<Instant>,
+    broken: bool,
+}
+
+impl ProgressWriter {
+    pub fn new(writer: impl Write + Send + 'static) -> ProgressWriter {
+        let state = State { writer: Box::new(writer), total: None, written_at: None, broken: false };
+        ProgressWriter { state: Mutex::new(state) }
+    }
+
+    /// Writes to a duplicate of the open file descriptor `fd`, so the descriptor itself stays open
+    #[cfg(unix)]
+    pub fn from_fd(fd: i32) -> std::io::Result<ProgressWriter> {
+        use std::os::fd::FromRawFd;
+
+        // SAFETY: dup only reads the descriptor table and fails for descriptors that are not open
+        let duplicate = unsafe { libc::dup(fd) };
+        if duplicate < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // SAFETY: the duplicate was just created and is owned by nothing else
+        let file = unsafe { std::fs::File::from_raw_fd(duplicate) };
+        Ok(ProgressWriter::new(file))
+    }
+
+    /// Sets the total once it is known
+    pub fn set_total(&self, total: usize) {
+        self.lock().total = Some(total);
+    }
+
+    /// Writes an update unless the previous one was written less than [UPDATE_INTERVAL] ago
+    pub fn update(&self, current: usize, bytes: u64) {
+        let mut state = self.lock();
+        if state.written_at.is_some_and(|written_at| written_at.elapsed() < UPDATE_INTERVAL) {
+            return;
+        }
+        state.write(current, bytes, false);
+    }
+
+    /// Writes the final update
+    pub fn finish(&self, current: usize, bytes: u64) {
+        self.lock().write(current, bytes, true);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl State {
+    fn write(&mut self, current: usize, bytes: u64, done: bool) {
+        if self.broken {
+            return;
+        }
+        let update = ProgressUpdate { current, total: self.total, bytes, done };
+        let written = serde_json::to_writer(&mut self.writer, &update).map_err(std::io::Error::from)
+            .and_then(|_| self.writer.write_all(b"\n"))
+            .and_then(|_| self.writer.flush());
+        if let Err(err) = written {
+            debug!("Stopped writing progress updates due to {}", err);
+            self.broken = true;
+        }
+        self.written_at = Some(Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    use crate::progress::ProgressWriter;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_progress_writer() {
+        let buffer = SharedBuffer::default();
+        let writer = ProgressWriter::new(buffer.clone());
+        writer.update(1, 100);
+        writer.update(2, 200);
+        writer.set_total(3);
+        writer.finish(3, 300);
+        let written = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(written, "{\"current\":1,\"total\":null,\"bytes\":100,\"done\":false}\n\
+                             {\"current\":3,\"total\":3,\"bytes\":300,\"done\":true}\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_from_fd_rejects_closed_descriptors() {
+        assert!(ProgressWriter::from_fd(-1).is_err());
+    }
+}
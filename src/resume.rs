@@ -0,0 +1,95 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long newly completed paths may stay buffered before they are written to the state file
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The state file of `--resume`, which lists the source path of every completed copy on its own line.
+/// Paths listed by an earlier run are skipped, new ones are appended by all workers through a shared buffer
+pub struct ResumeState {
+    completed: HashSet<PathBuf>,
+    writer: Mutex<StateWriter>,
+}
+
+struct StateWriter {
+    file: BufWriter<File>,
+    flushed_at: Instant,
+}
+
+impl ResumeState {
+    /// Loads the paths completed so far from `path`, which is created if it does not exist yet
+    pub fn open(path: &Path) -> std::io::Result<ResumeState> {
+        let completed = match File::open(path) {
+            Ok(file) => {
+                BufReader::new(file).lines()
+                    .filter(|line| line.as_ref().map_or(true, |line| !line.is_empty()))
+                    .map(|line| line.map(PathBuf::from))
+                    .collect::<std::io::Result<HashSet<PathBuf>>>()?
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => { HashSet::new() }
+            Err(err) => { return Err(err); }
+        };
+        let file = File::options().create(true).append(true).open(path)?;
+        let writer = StateWriter { file: BufWriter::new(file), flushed_at: Instant::now() };
+        Ok(ResumeState { completed, writer: Mutex::new(writer) })
+    }
+
+    /// Whether an earlier run already copied `source_path`
+    pub fn is_completed(&self, source_path: &Path) -> bool {
+        self.completed.contains(source_path)
+    }
+
+    pub fn completed_count(&self) -> usize {
+        self.completed.len()
+    }
+
+    /// Appends `source_path`, the state file is only flushed every [FLUSH_INTERVAL]
+    pub fn record(&self, source_path: &Path) -> std::io::Result<()> {
+        let mut writer = self.writer.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        writeln!(writer.file, "{}", source_path.to_string_lossy())?;
+        if writer.flushed_at.elapsed() >= FLUSH_INTERVAL {
+            writer.file.flush()?;
+            writer.flushed_at = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Writes all buffered paths to the state file
+    pub fn flush(&self) -> std::io::Result<()> {
+        let mut writer = self.writer.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        writer.file.flush()?;
+        writer.flushed_at = Instant::now();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::resume::ResumeState;
+
+    #[test]
+    fn test_record_and_reload() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let state_file = temp_dir.path().join("state");
+        let state = ResumeState::open(&state_file).unwrap();
+        assert_eq!(state.completed_count(), 0);
+        state.record(Path::new("source/a.txt")).unwrap();
+        state.record(Path::new("source/nested/b.txt")).unwrap();
+        state.flush().unwrap();
+        drop(state);
+
+        let state = ResumeState::open(&state_file).unwrap();
+        assert!(state.is_completed(Path::new("source/a.txt")));
+        assert!(state.is_completed(Path::new("source/nested/b.txt")));
+        assert!(!state.is_completed(Path::new("source/c.txt")));
+        state.record(Path::new("source/c.txt")).unwrap();
+        drop(state);
+        assert_eq!(ResumeState::open(&state_file).unwrap().completed_count(), 3);
+    }
+}
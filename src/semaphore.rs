@@ -0,0 +1,92 @@
+use std::sync::{Condvar, Mutex, MutexGuard};
+
+/// Bounds how many files are open at the same time for `--max-open-files`, independently of the worker count
+pub struct Semaphore {
+    permits: usize,
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Holds its permits until it is dropped
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Semaphore {
+        let permits = permits.max(1);
+        Semaphore { permits, available: Mutex::new(permits), released: Condvar::new() }
+    }
+
+    /// Blocks until `permits` are available, at most all permits are taken so a single caller never waits forever
+    pub fn acquire(&self, permits: usize) -> SemaphorePermit<'_> {
+        let permits = permits.min(self.permits);
+        let mut available = self.lock();
+        while *available < permits {
+            available = self.released.wait(available).unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        *available -= permits;
+        SemaphorePermit { semaphore: self, permits }
+    }
+
+    pub fn available(&self) -> usize {
+        *self.lock()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, usize> {
+        self.available.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        *self.semaphore.lock() += self.permits;
+        self.semaphore.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use crate::semaphore::Semaphore;
+
+    #[test]
+    fn test_acquire_and_release() {
+        let semaphore = Semaphore::new(4);
+        let first = semaphore.acquire(2);
+        assert_eq!(semaphore.available(), 2);
+        let second = semaphore.acquire(2);
+        assert_eq!(semaphore.available(), 0);
+        drop(first);
+        assert_eq!(semaphore.available(), 2);
+        drop(second);
+        assert_eq!(semaphore.available(), 4);
+        let all = semaphore.acquire(10);
+        assert_eq!(semaphore.available(), 0);
+        drop(all);
+        assert_eq!(semaphore.available(), 4);
+    }
+
+    #[test]
+    fn test_bounds_concurrent_holders() {
+        let semaphore = Semaphore::new(4);
+        let holding = AtomicUsize::new(0);
+        let max_holding = AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    let _permit = semaphore.acquire(2);
+                    let now_holding = holding.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_holding.fetch_max(now_holding, Ordering::SeqCst);
+                    std::thread::sleep(Duration::from_millis(10));
+                    holding.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        assert!(max_holding.load(Ordering::SeqCst) <= 2);
+        assert_eq!(semaphore.available(), 4);
+    }
+}
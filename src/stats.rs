@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+use bytesize::ByteSize;
+
+use crate::args::file_extensions;
+
+/// The label of files without an extension
+const NO_EXTENSION: &str = "(none)";
+
+/// How many files and bytes of one extension were copied
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtensionStat {
+    pub extension: String,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// The per-extension totals of `--stats`, collected by the workers during a copy
+#[derive(Default)]
+pub struct ExtensionStats {
+    totals: Mutex<HashMap<String, (usize, u64)>>,
+}
+
+impl ExtensionStats {
+    /// Adds a copied file, keyed by its last and lowercased extension, so `archive.tar.gz` counts as `.gz`
+    pub fn record(&self, file_name: &str, bytes: u64) {
+        let extension = file_extensions(file_name, false).pop().unwrap_or_else(|| NO_EXTENSION.to_string());
+        let mut totals = self.totals.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (files, total_bytes) = totals.entry(extension).or_default();
+        *files += 1;
+        *total_bytes += bytes;
+    }
+
+    /// The totals from the most to the least bytes, extensions with equal sizes are ordered by their file count
+    pub fn into_sorted(self) -> Vec<ExtensionStat> {
+        let totals = self.totals.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut stats: Vec<ExtensionStat> = totals.into_iter()
+            .map(|(extension, (files, bytes))| ExtensionStat { extension, files, bytes })
+            .collect();
+        stats.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(b.files.cmp(&a.files)).then(a.extension.cmp(&b.extension)));
+        stats
+    }
+}
+
+/// Renders the first `top` of the sorted `stats` as a table, the remaining extensions are summed up in a last row
+pub fn render_table(stats: &[ExtensionStat], top: usize) -> String {
+    let mut rows: Vec<(String, usize, u64)> = stats.iter()
+        .take(top)
+        .map(|stat| (stat.extension.clone(), stat.files, stat.bytes))
+        .collect();
+    let rest = &stats[top.min(stats.len())..];
+    if !rest.is_empty() {
+        let label = format!("{} others", rest.len());
+        rows.push((label, rest.iter().map(|stat| stat.files).sum(), rest.iter().map(|stat| stat.bytes).sum()));
+    }
+    let width = rows.iter().map(|(label, _, _)| label.chars().count()).max().unwrap_or(0).max("Extension".len());
+    let mut table = format!("{:<width$}  {:>8}  {:>10}\n", "Extension", "Files", "Size");
+    for (label, files, bytes) in rows {
+        writeln!(table, "{:<width$}  {:>8}  {:>10}", label, files, ByteSize(bytes).to_string()).unwrap();
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stats::{ExtensionStat, ExtensionStats, render_table};
+
+    fn stat(extension: &str, files: usize, bytes: u64) -> ExtensionStat {
+        ExtensionStat { extension: extension.to_string(), files, bytes }
+    }
+
+    #[test]
+    fn test_aggregation() {
+        let stats = ExtensionStats::default();
+        stats.record("photo.jpg", 3000);
+        stats.record("Holiday.JPG", 2000);
+        stats.record("notes.txt", 10);
+        stats.record("archive.tar.gz", 4000);
+        stats.record("README", 20);
+        stats.record("todo.txt", 30);
+        assert_eq!(stats.into_sorted(), vec![
+            stat(".jpg", 2, 5000),
+            stat(".gz", 1, 4000),
+            stat(".txt", 2, 40),
+            stat("(none)", 1, 20),
+        ]);
+    }
+
+    #[test]
+    fn test_render_table() {
+        let stats = vec![stat(".jpg", 2, 5000), stat(".gz", 1, 4000), stat(".txt", 2, 40), stat("(none)", 1, 20)];
+        assert_eq!(render_table(&stats, 2), "\
+Extension     Files        Size
+.jpg              2      5.0 KB
+.gz               1      4.0 KB
+2 others          3        60 B
+");
+        assert_eq!(render_table(&stats[..1], 10), "\
+Extension     Files        Size
+.jpg              2      5.0 KB
+");
+    }
+}
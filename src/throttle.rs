@@ -0,0 +1,137 @@
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A token bucket shared by all workers that limits their combined throughput to `bytes_per_second`.
+/// Up to one second worth of bytes may be used in a burst, so the rate is only met on average
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_second: u64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Bytes that may be transferred right away, negative while workers wait for bytes they took in advance
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u64) -> RateLimiter {
+        let bucket = Bucket { tokens: bytes_per_second as f64, refilled_at: Instant::now() };
+        RateLimiter { bytes_per_second, bucket: Mutex::new(bucket) }
+    }
+
+    pub fn bytes_per_second(&self) -> u64 {
+        self.bytes_per_second
+    }
+
+    /// Takes `bytes` from the bucket and sleeps until they are covered by the rate
+    pub fn acquire(&self, bytes: u64) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let now = Instant::now();
+            let rate = self.bytes_per_second as f64;
+            let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * rate).min(rate);
+            bucket.refilled_at = now;
+            bucket.tokens -= bytes as f64;
+            if bucket.tokens >= 0.0 { Duration::ZERO } else { Duration::from_secs_f64(-bucket.tokens / rate) }
+        };
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+pub(crate) const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Copies the content and permissions of `source_path` to `target_path` like [std::fs::copy], but in chunks
+/// that are each paid for at `rate_limiter`
+pub fn copy_throttled(source_path: &Path, target_path: &Path, rate_limiter: &RateLimiter) -> std::io::Result<u64> {
+    copy_buffered(source_path, target_path, CHUNK_SIZE, Some(rate_limiter))
+}
+
+/// Copies the content and permissions of `source_path` to `target_path` like [std::fs::copy], but through
+/// an explicit buffer of `buffer_size` bytes, each chunk paid for at `rate_limiter` if one is given
+pub fn copy_buffered(source_path: &Path, target_path: &Path, buffer_size: usize, rate_limiter: Option<&RateLimiter>) -> std::io::Result<u64> {
+    let mut source = File::open(source_path)?;
+    let permissions = source.metadata()?.permissions();
+    let mut target = File::create(target_path)?;
+    let copied = copy_chunks(&mut source, &mut target, buffer_size, rate_limiter)?;
+    target.set_permissions(permissions)?;
+    Ok(copied)
+}
+
+/// Copies everything from `source` to `target` through a buffer of `buffer_size` bytes, each chunk paid for at
+/// `rate_limiter` if one is given
+pub(crate) fn copy_chunks(source: &mut impl Read, target: &mut impl Write, buffer_size: usize, rate_limiter: Option<&RateLimiter>) -> std::io::Result<u64> {
+    let mut buffer = vec![0u8; buffer_size.max(1)];
+    let mut copied = 0;
+    loop {
+        let read = match source.read(&mut buffer) {
+            Ok(read) => { read }
+            Err(err) if err.kind() == ErrorKind::Interrupted => { continue; }
+            Err(err) => { return Err(err); }
+        };
+        if read == 0 {
+            break;
+        }
+        if let Some(rate_limiter) = rate_limiter {
+            rate_limiter.acquire(read as u64);
+        }
+        target.write_all(&buffer[..read])?;
+        copied += read as u64;
+    }
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::throttle::{copy_buffered, copy_throttled, RateLimiter};
+
+    #[test]
+    fn test_rate_limiter_allows_a_burst_then_waits() {
+        let rate_limiter = RateLimiter::new(100_000);
+        let start = Instant::now();
+        rate_limiter.acquire(100_000);
+        assert!(start.elapsed() < Duration::from_millis(200));
+        rate_limiter.acquire(50_000);
+        assert!(start.elapsed() >= Duration::from_millis(450));
+    }
+
+    #[test]
+    fn test_copy_throttled() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let source = temp_dir.path().join("source.bin");
+        let target = temp_dir.path().join("target.bin");
+        let content: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&source, &content).unwrap();
+        let start = Instant::now();
+        let copied = copy_throttled(&source, &target, &RateLimiter::new(100_000)).unwrap();
+        assert_eq!(copied, 200_000);
+        assert_eq!(std::fs::read(&target).unwrap(), content);
+        assert!(start.elapsed() >= Duration::from_millis(900));
+    }
+
+    #[test]
+    fn test_copy_buffered_is_byte_for_byte() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let source = temp_dir.path().join("source.bin");
+        let content: Vec<u8> = (0..1_000_003u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8).collect();
+        std::fs::write(&source, &content).unwrap();
+        for buffer_size in [1, 4096, 999_999, 8 * 1024 * 1024] {
+            let target = temp_dir.path().join(format!("target-{}.bin", buffer_size));
+            assert_eq!(copy_buffered(&source, &target, buffer_size, None).unwrap(), content.len() as u64);
+            assert_eq!(std::fs::read(&target).unwrap(), content);
+        }
+        let empty = temp_dir.path().join("empty.bin");
+        std::fs::write(&empty, []).unwrap();
+        assert_eq!(copy_buffered(&empty, &temp_dir.path().join("empty-copy.bin"), 4096, None).unwrap(), 0);
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::Path;
+
+/// One directory level of [render_tree], siblings are kept sorted by name
+#[derive(Default)]
+struct Node {
+    children: BTreeMap<OsString, Node>,
+}
+
+/// Renders `paths` as an indented tree below `root`, drawn with box-drawing characters like the `tree` command.
+/// Paths outside of `root` are placed by all of their components
+pub fn render_tree<'a>(root: &Path, paths: impl IntoIterator<Item=&'a Path>) -> String {
+    let mut tree = Node::default();
+    for path in paths {
+        let relative_path = path.strip_prefix(root).unwrap_or(path);
+        let mut node = &mut tree;
+        for component in relative_path.components() {
+            node = node.children.entry(component.as_os_str().to_os_string()).or_default();
+        }
+    }
+    let mut rendered = format!("{}\n", root.to_string_lossy());
+    render_children(&tree, "", &mut rendered);
+    rendered
+}
+
+fn render_children(node: &Node, indent: &str, rendered: &mut String) {
+    let count = node.children.len();
+    for (index, (name, child)) in node.children.iter().enumerate() {
+        let is_last = index + 1 == count;
+        let (branch, continuation) = if is_last { ("└── ", "    ") } else { ("├── ", "│   ") };
+        rendered.push_str(indent);
+        rendered.push_str(branch);
+        rendered.push_str(&name.to_string_lossy());
+        rendered.push('\n');
+        render_children(child, &format!("{}{}", indent, continuation), rendered);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::tree::render_tree;
+
+    #[test]
+    fn test_render_tree() {
+        let root = Path::new("source");
+        let paths: Vec<PathBuf> = ["source/nested/deeper/d.txt", "source/b.txt", "source/nested/c.txt", "source/a.txt",
+            "source/zeta/e.txt", "source/nested"].iter().map(PathBuf::from).collect();
+        let rendered = render_tree(root, paths.iter().map(PathBuf::as_path));
+        assert_eq!(rendered, "\
+source
+├── a.txt
+├── b.txt
+├── nested
+│   ├── c.txt
+│   └── deeper
+│       └── d.txt
+└── zeta
+    └── e.txt
+");
+    }
+
+    #[test]
+    fn test_render_empty_tree() {
+        assert_eq!(render_tree(Path::new("source"), []), "source\n");
+    }
+}
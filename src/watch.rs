@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use notify::{EventKind, RecursiveMode, Watcher};
+use walkdir::DirEntry;
+
+use crate::{copy_files, CopyError, device_id, is_hidden, SkipCounts, tagging_source_root, walk_below};
+use crate::args::Arguments;
+
+/// How long a path has to stay unchanged before `--watch` copies it, so a file that is still being written
+/// is copied once it is complete instead of after every write
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// How often the stop signal is checked while no changes arrive
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Errors that end [watch]. Failures of individual files are only logged, like during the initial copy
+#[derive(Debug)]
+pub enum WatchError {
+    /// The source roots could not be watched
+    Notify(notify::Error),
+    /// Copying the changed files failed as a whole
+    Copy(CopyError),
+}
+
+impl Display for WatchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WatchError::Notify(err) => { write!(f, "Could not watch the source for changes due to {}", err) }
+            WatchError::Copy(err) => { write!(f, "Could not copy the changed files: {}", err) }
+        }
+    }
+}
+
+impl std::error::Error for WatchError {}
+
+/// Watches the source roots and copies created or modified entries that pass the filters, once no further
+/// changes arrived for them within `debounce`. Created directories are copied with their whole content, as
+/// folders moved into the source only report the folder itself. Every source root has a watcher of its own, so
+/// each change is known to belong to the root it was reported for. Runs until [Arguments::stop_signal] is
+/// requested and returns how many files were copied
+pub fn watch(args: &Arguments, debounce: Duration) -> Result<usize, WatchError> {
+    let (sender, receiver) = channel();
+    let _watchers = args.source_roots.iter().enumerate()
+        .map(|(index, source_root)| {
+            let sender = sender.clone();
+            let mut watcher = notify::recommended_watcher(move |event| {
+                let _ = sender.send((index, event));
+            }).map_err(WatchError::Notify)?;
+            watcher.watch(Path::new(source_root), RecursiveMode::Recursive).map_err(WatchError::Notify)?;
+            Ok(watcher)
+        })
+        .collect::<Result<Vec<_>, WatchError>>()?;
+    drop(sender);
+    info!("Watching {} for changes", args.source_roots.join(", "));
+    let mut pending: HashMap<PathBuf, (Instant, usize)> = HashMap::new();
+    let mut copied = 0;
+    while !args.stop_signal.is_requested() {
+        match receiver.recv_timeout(STOP_POLL_INTERVAL) {
+            Ok((source_root, Ok(event))) => {
+                if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                    let now = Instant::now();
+                    for path in event.paths {
+                        pending.entry(path).and_modify(|(changed, _)| *changed = now).or_insert((now, source_root));
+                    }
+                }
+            }
+            Ok((_, Err(err))) => { warn!("Could not receive changes due to {}", err) }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => { break; }
+        }
+        let settled = take_settled(&mut pending, debounce, Instant::now());
+        if !settled.is_empty() {
+            copied += copy_changed(args, &settled)?;
+        }
+    }
+    Ok(copied)
+}
+
+/// Removes and returns the paths without changes for at least `debounce`, parents before their children. Each
+/// pending path carries the time of its last change and the index of the source root it changed in
+fn take_settled(pending: &mut HashMap<PathBuf, (Instant, usize)>, debounce: Duration, now: Instant) -> Vec<(PathBuf, usize)> {
+    let mut settled: Vec<(PathBuf, usize)> = pending.iter()
+        .filter(|(_, (changed, _))| now.duration_since(*changed) >= debounce)
+        .map(|(path, (_, source_root))| (path.clone(), *source_root))
+        .collect();
+    for (path, _) in &settled {
+        pending.remove(path);
+    }
+    settled.sort();
+    settled
+}
+
+/// Copies the settled `paths` of the source roots at their index with the filters of the initial walk,
+/// including its depth limits. Paths below another settled path of the same root are left to the walk of that
+/// one, so no entry is copied twice within a batch
+fn copy_changed(args: &Arguments, paths: &[(PathBuf, usize)]) -> Result<usize, WatchError> {
+    let skipped = Arc::new(SkipCounts::default());
+    let mut entries: Vec<DirEntry> = Vec::new();
+    for (path, index) in paths {
+        if paths.iter().any(|(other, other_index)| other_index == index && other != path && path.starts_with(other)) {
+            continue;
+        }
+        let source_root = Path::new(&args.source_roots[*index]);
+        let Ok(relative_path) = path.strip_prefix(source_root) else {
+            continue;
+        };
+        if args.exclude_hidden && is_below_hidden(source_root, path) {
+            continue;
+        }
+        if args.respect_gitignore && is_gitignored(args, source_root, path) {
+            debug!("Skipped {} as it is ignored by an ignore file", path.to_string_lossy());
+            continue;
+        }
+        if args.one_filesystem && device_id(path) != device_id(source_root) {
+            debug!("Skipped {} as it is located on another file system than its source root", path.to_string_lossy());
+            continue;
+        }
+        let depth = relative_path.components().count();
+        let skip_reason = tagging_source_root(args, *index, |path, is_dir| args.skip_reason_of_type(path, is_dir));
+        entries.extend(walk_below(args, path, depth, skip_reason, skipped.clone()));
+    }
+    if entries.is_empty() {
+        return Ok(0);
+    }
+    debug!("Copying {} changed entries", entries.len());
+    let report = copy_files(args, entries).map_err(WatchError::Copy)?;
+    Ok(report.copied)
+}
+
+/// Whether `path` or any of its parents below `source_root` is left out by `--respect-gitignore`. A walk never
+/// matches its own start against the ignore files, so each of them is looked up in the listing of its parent
+fn is_gitignored(args: &Arguments, source_root: &Path, path: &Path) -> bool {
+    path.ancestors()
+        .take_while(|ancestor| *ancestor != source_root)
+        .any(|ancestor| {
+            let Some(parent) = ancestor.parent() else {
+                return false;
+            };
+            !ignore::WalkBuilder::new(parent)
+                .max_depth(Some(1))
+                .hidden(false)
+                .follow_links(args.follow_links)
+                .require_git(false)
+                .build()
+                .filter_map(|e| e.ok())
+                .any(|e| e.depth() == 1 && e.path() == ancestor)
+        })
+}
+
+/// Whether `path` or any of its parents below `source_root` is hidden
+fn is_below_hidden(source_root: &Path, path: &Path) -> bool {
+    path.ancestors()
+        .take_while(|ancestor| *ancestor != source_root)
+        .any(|ancestor| ancestor.file_name().is_some_and(|file_name| is_hidden(file_name, ancestor)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, Instant};
+
+    use clap::Parser;
+
+    use crate::args::CliArgs;
+    use crate::watch::{copy_changed, take_settled, watch};
+
+    #[test]
+    fn test_take_settled() {
+        let now = Instant::now();
+        let mut pending = HashMap::from([
+            (PathBuf::from("source/nested/b.txt"), (now - Duration::from_secs(2), 0)),
+            (PathBuf::from("source/nested"), (now - Duration::from_secs(1), 0)),
+            (PathBuf::from("other/c.txt"), (now - Duration::from_secs(1), 1)),
+            (PathBuf::from("source/fresh.txt"), (now, 0)),
+        ]);
+        let settled = take_settled(&mut pending, Duration::from_millis(500), now);
+        assert_eq!(settled, vec![(PathBuf::from("other/c.txt"), 1), (PathBuf::from("source/nested"), 0),
+                                 (PathBuf::from("source/nested/b.txt"), 0)]);
+        assert_eq!(pending.keys().collect::<Vec<&PathBuf>>(), vec![Path::new("source/fresh.txt")]);
+    }
+
+    #[test]
+    fn test_copy_changed_applies_the_walk_filters() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(source.path().join("nested").join("deeper")).unwrap();
+        std::fs::write(source.path().join("nested").join("a.txt"), "a").unwrap();
+        std::fs::write(source.path().join("nested").join("deeper").join("b.txt"), "b").unwrap();
+        std::fs::write(source.path().join("nested").join("ignored.log"), "log").unwrap();
+        std::fs::write(source.path().join(".gitignore"), "*.log\n").unwrap();
+        let args = CliArgs::parse_from(["filescraper", source.path().to_str().unwrap(), target.path().to_str().unwrap(),
+            "--max-depth", "2", "--respect-gitignore", "--quiet"]).convert().unwrap();
+        let changed = [
+            (source.path().join("nested"), 0),
+            (source.path().join("nested").join("a.txt"), 0),
+            (source.path().join("nested").join("deeper").join("b.txt"), 0),
+            (source.path().join("nested").join("ignored.log"), 0),
+        ];
+        assert_eq!(copy_changed(&args, &changed).unwrap(), 1);
+        assert!(target.path().join("nested").join("a.txt").exists());
+        assert!(!target.path().join("nested").join("deeper").join("b.txt").exists());
+        assert!(!target.path().join("nested").join("ignored.log").exists());
+        assert_eq!(copy_changed(&args, &changed[3..]).unwrap(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_changed_strips_the_root_the_change_was_reported_for() {
+        let source = tempfile::tempdir().unwrap();
+        let elsewhere = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(elsewhere.path().join("linked.txt"), "linked").unwrap();
+        std::os::unix::fs::symlink(elsewhere.path(), source.path().join("link")).unwrap();
+        let second_root = source.path().join("link");
+        let args = CliArgs::parse_from(["filescraper", source.path().to_str().unwrap(), target.path().to_str().unwrap(),
+            "--source", second_root.to_str().unwrap(), "--quiet"]).convert().unwrap();
+        assert_eq!(copy_changed(&args, &[(second_root.join("linked.txt"), 1)]).unwrap(), 1);
+        assert!(target.path().join("linked.txt").is_file());
+        assert!(!target.path().join("link").exists());
+    }
+
+    #[test]
+    fn test_watch_conflicts_with_whole_run_outputs() {
+        for flag in ["--error-log", "--manifest", "--checksum-manifest", "--max-total-size"] {
+            assert!(CliArgs::try_parse_from(["filescraper", "source", "target", "--watch", flag, "1"]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_watch_copies_created_files() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let args = CliArgs::parse_from(["filescraper", source.path().to_str().unwrap(), target.path().to_str().unwrap(),
+            "--file-extensions", "txt", "--quiet"]).convert().unwrap();
+        let stop_signal = args.stop_signal.clone();
+        std::thread::scope(|scope| {
+            let watching = scope.spawn(|| watch(&args, Duration::from_millis(50)));
+            // Give the watcher time to register before changing anything
+            std::thread::sleep(Duration::from_millis(200));
+            std::fs::create_dir(source.path().join("nested")).unwrap();
+            std::fs::write(source.path().join("nested").join("new.txt"), "new").unwrap();
+            std::fs::write(source.path().join("ignored.jpg"), "ignored").unwrap();
+            let copied = target.path().join("nested").join("new.txt");
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while !copied.exists() && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            stop_signal.request();
+            assert!(watching.join().unwrap().unwrap() >= 1);
+        });
+        assert_eq!(std::fs::read_to_string(target.path().join("nested").join("new.txt")).unwrap(), "new");
+        assert!(!target.path().join("ignored.jpg").exists());
+    }
+}
@@ -0,0 +1,90 @@
+use std::ffi::OsString;
+use std::path::Path;
+
+#[cfg(unix)]
+use log::{debug, warn};
+
+/// The extended attributes of a file as name and value pairs
+pub struct ExtendedAttributes {
+    #[cfg_attr(not(unix), allow(dead_code))]
+    attributes: Vec<(OsString, Vec<u8>)>,
+}
+
+/// Reads all extended attributes of `path`, `None` if there are none or the filesystem does not support them
+#[cfg(unix)]
+pub fn read(path: &Path) -> Option<ExtendedAttributes> {
+    let names = match xattr::list(path) {
+        Ok(names) => { names }
+        Err(err) => {
+            if !is_unsupported(&err) {
+                warn!("Could not read extended attributes of {} due to {}", path.to_string_lossy(), err);
+            }
+            return None;
+        }
+    };
+    let mut attributes = Vec::new();
+    for name in names {
+        match xattr::get(path, &name) {
+            Ok(Some(value)) => { attributes.push((name, value)) }
+            Ok(None) => {}
+            Err(err) => {
+                debug!("Could not read extended attribute {} of {} due to {}", name.to_string_lossy(), path.to_string_lossy(), err)
+            }
+        }
+    }
+    (!attributes.is_empty()).then_some(ExtendedAttributes { attributes })
+}
+
+#[cfg(not(unix))]
+pub fn read(_path: &Path) -> Option<ExtendedAttributes> {
+    None
+}
+
+/// Writes `attributes` to `path`. Attributes that need privileges, like `security.*` or `trusted.*` ones
+/// for non-root users, are skipped just like targets on filesystems without extended attributes
+#[cfg(unix)]
+pub fn apply(path: &Path, attributes: &ExtendedAttributes) {
+    for (name, value) in &attributes.attributes {
+        match xattr::set(path, name, value) {
+            Ok(_) => {}
+            Err(err) if is_unsupported(&err) => { return; }
+            Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied => {
+                debug!("Could not preserve extended attribute {} of {} due to {}", name.to_string_lossy(), path.to_string_lossy(), err)
+            }
+            Err(err) => {
+                warn!("Could not preserve extended attribute {} of {} due to {}", name.to_string_lossy(), path.to_string_lossy(), err)
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn apply(_path: &Path, _attributes: &ExtendedAttributes) {}
+
+#[cfg(unix)]
+fn is_unsupported(err: &std::io::Error) -> bool {
+    err.kind() == std::io::ErrorKind::Unsupported
+        || matches!(err.raw_os_error(), Some(code) if code == libc::ENOTSUP || code == libc::EOPNOTSUPP)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::xattrs::{apply, read};
+
+    #[test]
+    fn test_read_and_apply() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let source = temp_dir.path().join("source.txt");
+        let target = temp_dir.path().join("target.txt");
+        std::fs::write(&source, "source").unwrap();
+        std::fs::write(&target, "target").unwrap();
+        if xattr::set(&source, "user.filescraper.tag", b"important").is_err() {
+            // The filesystem of the temp dir does not support extended attributes
+            return;
+        }
+        let attributes = read(&source).unwrap();
+        apply(&target, &attributes);
+        assert_eq!(xattr::get(&target, "user.filescraper.tag").unwrap(), Some(b"important".to_vec()));
+        assert!(read(&target).is_some());
+    }
+}
@@ -0,0 +1,81 @@
+use std::path::Path;
+use std::process::Command;
+
+fn list_only(source: &Path, target: &Path, flags: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_filescraper"))
+        .arg(source)
+        .arg(target)
+        .arg("--list-only")
+        .args(flags)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_list_only_prints_matched_paths() {
+    let source = tempfile::tempdir().unwrap();
+    let target = tempfile::tempdir().unwrap();
+    std::fs::create_dir(source.path().join("nested")).unwrap();
+    std::fs::write(source.path().join("a.txt"), "a").unwrap();
+    std::fs::write(source.path().join("nested").join("b.txt"), "b").unwrap();
+    std::fs::write(source.path().join("c.jpg"), "c").unwrap();
+    let output = list_only(source.path(), target.path(), &["--file-extensions", "txt"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut listed: Vec<&str> = stdout.lines().collect();
+    listed.sort();
+    let expected_a = source.path().join("a.txt");
+    let expected_b = source.path().join("nested").join("b.txt");
+    assert_eq!(listed, vec![expected_a.to_str().unwrap(), expected_b.to_str().unwrap()]);
+    assert!(String::from_utf8(output.stderr).unwrap().contains("Found 2 files"));
+    assert_eq!(std::fs::read_dir(target.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn test_list_only_quiet_omits_the_count() {
+    let source = tempfile::tempdir().unwrap();
+    let target = tempfile::tempdir().unwrap();
+    std::fs::write(source.path().join("a.txt"), "a").unwrap();
+    let output = list_only(source.path(), target.path(), &["--quiet"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap().lines().count(), 1);
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn test_preview_tree_prints_a_tree() {
+    let source = tempfile::tempdir().unwrap();
+    let target = tempfile::tempdir().unwrap();
+    std::fs::create_dir(source.path().join("nested")).unwrap();
+    std::fs::write(source.path().join("a.txt"), "a").unwrap();
+    std::fs::write(source.path().join("nested").join("b.txt"), "b").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_filescraper"))
+        .arg(source.path())
+        .arg(target.path())
+        .args(["--preview-tree", "--quiet"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let expected = format!("{}\n├── a.txt\n└── nested\n    └── b.txt\n", source.path().to_string_lossy());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+    assert_eq!(std::fs::read_dir(target.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn test_count_only_prints_count_and_size() {
+    let source = tempfile::tempdir().unwrap();
+    let target = tempfile::tempdir().unwrap();
+    std::fs::create_dir(source.path().join("nested")).unwrap();
+    std::fs::write(source.path().join("a.txt"), vec![0u8; 1000]).unwrap();
+    std::fs::write(source.path().join("nested").join("b.txt"), vec![0u8; 500]).unwrap();
+    std::fs::write(source.path().join("c.jpg"), vec![0u8; 20]).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_filescraper"))
+        .arg(source.path())
+        .arg(target.path())
+        .args(["--count-only", "--file-extensions", "txt"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "2 files, 1.5 KB\n");
+    assert_eq!(std::fs::read_dir(target.path()).unwrap().count(), 0);
+}